@@ -0,0 +1,11 @@
+DROP TRIGGER IF EXISTS posts_search_delete;
+DROP TABLE IF EXISTS posts_search;
+DROP TABLE IF EXISTS comments;
+DROP TABLE IF EXISTS media;
+DROP TABLE IF EXISTS post_revisions;
+DROP TABLE IF EXISTS post_categories;
+DROP TABLE IF EXISTS categories;
+DROP TABLE IF EXISTS post_tags;
+DROP TABLE IF EXISTS tags;
+DROP TABLE IF EXISTS posts;
+DROP TABLE IF EXISTS users;
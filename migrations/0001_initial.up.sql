@@ -0,0 +1,111 @@
+-- The schema of the last version without migrations, which is why everything is only created
+-- if it doesn't exist yet: databases from then are adopted by running this. Databases of
+-- older versions lack some tables and columns, the tables are created here and the columns
+-- are added by `UNVERSIONED_COLUMNS` in src/migrate.rs.
+CREATE TABLE IF NOT EXISTS users(
+    salt BLOB UNIQUE NOT NULL,
+    name TEXT NOT NULL,
+    username TEXT UNIQUE NOT NULL PRIMARY KEY,
+    profile_pic TEXT,
+    sh_pass BLOB NOT NULL,
+    email TEXT NOT NULL UNIQUE,
+    rank TEXT NOT NULL,
+    reset_password INTEGER NOT NULL DEFAULT 0
+) STRICT;
+
+CREATE TABLE IF NOT EXISTS posts(
+    id INTEGER NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL,
+    slug TEXT,
+    content TEXT NOT NULL,
+    date INTEGER NOT NULL DEFAULT (unixepoch(CURRENT_TIMESTAMP)),
+    status TEXT NOT NULL DEFAULT 'Draft',
+    owner TEXT NOT NULL,
+    publish_at INTEGER,
+    format TEXT NOT NULL DEFAULT 'Html',
+    rendered TEXT,
+    toc TEXT,
+    FOREIGN KEY(owner) REFERENCES users(username)
+) STRICT;
+-- Columns can't be added as unique, so it is enforced with an index instead
+CREATE UNIQUE INDEX IF NOT EXISTS posts_slug ON posts(slug);
+
+CREATE TABLE IF NOT EXISTS tags(
+    id INTEGER NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL,
+    slug TEXT NOT NULL UNIQUE
+) STRICT;
+
+CREATE TABLE IF NOT EXISTS post_tags(
+    post INTEGER NOT NULL,
+    tag INTEGER NOT NULL,
+    PRIMARY KEY(post, tag),
+    FOREIGN KEY(post) REFERENCES posts(id) ON DELETE CASCADE,
+    FOREIGN KEY(tag) REFERENCES tags(id) ON DELETE CASCADE
+) STRICT;
+
+CREATE TABLE IF NOT EXISTS categories(
+    id INTEGER NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL,
+    slug TEXT NOT NULL UNIQUE,
+    description TEXT NOT NULL DEFAULT '',
+    parent INTEGER,
+    FOREIGN KEY(parent) REFERENCES categories(id) ON DELETE SET NULL
+) STRICT;
+
+CREATE TABLE IF NOT EXISTS post_categories(
+    post INTEGER NOT NULL,
+    category INTEGER NOT NULL,
+    PRIMARY KEY(post, category),
+    FOREIGN KEY(post) REFERENCES posts(id) ON DELETE CASCADE,
+    FOREIGN KEY(category) REFERENCES categories(id) ON DELETE CASCADE
+) STRICT;
+
+CREATE TABLE IF NOT EXISTS post_revisions(
+    id INTEGER NOT NULL PRIMARY KEY,
+    post INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    content TEXT NOT NULL,
+    tags TEXT NOT NULL DEFAULT '[]',
+    status TEXT NOT NULL,
+    format TEXT NOT NULL DEFAULT 'Html',
+    editor TEXT,
+    date INTEGER NOT NULL DEFAULT (unixepoch(CURRENT_TIMESTAMP)),
+    FOREIGN KEY(post) REFERENCES posts(id) ON DELETE CASCADE,
+    FOREIGN KEY(editor) REFERENCES users(username) ON DELETE SET NULL
+) STRICT;
+
+CREATE TABLE IF NOT EXISTS media(
+    id INTEGER NOT NULL PRIMARY KEY,
+    path TEXT NOT NULL UNIQUE,
+    source_url TEXT,
+    mime_type TEXT NOT NULL,
+    alt_text TEXT NOT NULL DEFAULT '',
+    caption TEXT NOT NULL DEFAULT '',
+    date INTEGER NOT NULL DEFAULT (unixepoch(CURRENT_TIMESTAMP))
+) STRICT;
+
+CREATE TABLE IF NOT EXISTS comments(
+    id INTEGER NOT NULL PRIMARY KEY,
+    post INTEGER NOT NULL,
+    parent INTEGER,
+    author TEXT,
+    author_name TEXT NOT NULL,
+    author_url TEXT NOT NULL DEFAULT '',
+    content TEXT NOT NULL,
+    date INTEGER NOT NULL DEFAULT (unixepoch(CURRENT_TIMESTAMP)),
+    status TEXT NOT NULL DEFAULT 'Pending',
+    FOREIGN KEY(post) REFERENCES posts(id) ON DELETE CASCADE,
+    FOREIGN KEY(parent) REFERENCES comments(id) ON DELETE CASCADE,
+    FOREIGN KEY(author) REFERENCES users(username)
+) STRICT;
+
+-- The text of the posts, kept up to date by cache_rendered
+CREATE VIRTUAL TABLE IF NOT EXISTS posts_search USING fts5(
+    name,
+    body,
+    tokenize = 'porter unicode61'
+);
+CREATE TRIGGER IF NOT EXISTS posts_search_delete AFTER DELETE ON posts BEGIN
+    DELETE FROM posts_search WHERE rowid = old.id;
+END;
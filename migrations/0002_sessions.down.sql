@@ -0,0 +1,2 @@
+DROP INDEX sessions_username;
+DROP TABLE sessions;
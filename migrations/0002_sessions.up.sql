@@ -0,0 +1,11 @@
+-- Signed in devices, a token is only accepted while its session is here
+CREATE TABLE sessions(
+    id TEXT NOT NULL PRIMARY KEY,
+    username TEXT NOT NULL,
+    created INTEGER NOT NULL DEFAULT (unixepoch(CURRENT_TIMESTAMP)),
+    last_seen INTEGER NOT NULL DEFAULT (unixepoch(CURRENT_TIMESTAMP)),
+    user_agent TEXT,
+    ip TEXT,
+    FOREIGN KEY(username) REFERENCES users(username) ON DELETE CASCADE
+) STRICT;
+CREATE INDEX sessions_username ON sessions(username);
@@ -0,0 +1,5 @@
+-- Only administrators were admins before, every other rank becomes a user
+UPDATE users SET rank = CASE rank
+    WHEN 'Administrator' THEN 'Admin'
+    ELSE 'User'
+END;
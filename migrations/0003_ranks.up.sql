@@ -0,0 +1,7 @@
+-- Users were either a User, who wrote and published their own posts like an Author does
+-- now, or an Admin
+UPDATE users SET rank = CASE rank
+    WHEN 'User' THEN 'Author'
+    WHEN 'Admin' THEN 'Administrator'
+    ELSE rank
+END;
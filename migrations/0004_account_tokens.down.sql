@@ -0,0 +1,3 @@
+DROP INDEX account_tokens_username;
+DROP TABLE account_tokens;
+ALTER TABLE users DROP COLUMN email_verified;
@@ -0,0 +1,14 @@
+-- Whether a user followed the link mailed to their address
+ALTER TABLE users ADD COLUMN email_verified INTEGER NOT NULL DEFAULT 0;
+
+-- Single use tokens of the links mailed to users, only their hashes are kept
+CREATE TABLE account_tokens(
+    hash BLOB NOT NULL PRIMARY KEY,
+    username TEXT NOT NULL,
+    purpose TEXT NOT NULL,
+    -- The address the link was sent to, verifying it fails once the email changed
+    email TEXT NOT NULL,
+    expires INTEGER NOT NULL,
+    FOREIGN KEY(username) REFERENCES users(username) ON DELETE CASCADE
+) STRICT;
+CREATE INDEX account_tokens_username ON account_tokens(username);
@@ -0,0 +1,3 @@
+DROP INDEX sign_up_attempts_ip;
+DROP TABLE sign_up_attempts;
+DROP TABLE invites;
@@ -0,0 +1,17 @@
+-- Codes administrators hand out for signing up, only their hashes are kept
+CREATE TABLE invites(
+    hash BLOB NOT NULL PRIMARY KEY,
+    created_by TEXT,
+    created INTEGER NOT NULL DEFAULT (unixepoch(CURRENT_TIMESTAMP)),
+    -- Never expires when NULL
+    expires INTEGER,
+    uses_left INTEGER NOT NULL,
+    FOREIGN KEY(created_by) REFERENCES users(username) ON DELETE SET NULL
+) STRICT;
+
+-- Recent sign ups by address, for limiting how often anyone can sign up
+CREATE TABLE sign_up_attempts(
+    ip TEXT NOT NULL,
+    attempted INTEGER NOT NULL DEFAULT (unixepoch(CURRENT_TIMESTAMP))
+) STRICT;
+CREATE INDEX sign_up_attempts_ip ON sign_up_attempts(ip, attempted);
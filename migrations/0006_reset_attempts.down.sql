@@ -0,0 +1,3 @@
+DROP INDEX reset_attempts_login;
+DROP INDEX reset_attempts_ip;
+DROP TABLE reset_attempts;
@@ -0,0 +1,9 @@
+-- Recent requests for password reset mails, for limiting how often they can be sent by
+-- address and by account
+CREATE TABLE reset_attempts(
+    ip TEXT NOT NULL,
+    login TEXT NOT NULL,
+    attempted INTEGER NOT NULL DEFAULT (unixepoch(CURRENT_TIMESTAMP))
+) STRICT;
+CREATE INDEX reset_attempts_ip ON reset_attempts(ip, attempted);
+CREATE INDEX reset_attempts_login ON reset_attempts(login, attempted);
@@ -0,0 +1,2 @@
+-- The sanitize policy a post was rendered with, so changing it renders the posts again
+ALTER TABLE posts ADD COLUMN rendered_with TEXT;
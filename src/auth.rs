@@ -1,4 +1,10 @@
+pub mod account_token;
 pub mod admin;
+pub mod capability;
+pub mod invite;
+pub mod keys;
+pub mod password;
+pub mod session;
 pub mod sign_in;
 pub mod sign_up;
 pub mod user;
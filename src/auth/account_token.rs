@@ -0,0 +1,382 @@
+//! Links mailed to users for resetting their password and verifying their email. The tokens
+//! in them work once and expire, only their hashes are stored.
+
+use std::{fmt::Display, net::SocketAddr, time::Duration};
+
+use axum::{
+    extract::{ConnectInfo, Query, State},
+    http::{HeaderMap, StatusCode},
+};
+use axum_typed_multipart::{TryFromMultipart, TypedMultipart};
+use base64::{engine::general_purpose, Engine};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use sqlx::{query, SqlitePool};
+
+use crate::config::SiteConfig;
+
+use super::{password::set_password, session::client_ip, user::User};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenPurpose {
+    ResetPassword,
+    VerifyEmail,
+}
+
+impl TokenPurpose {
+    /// How long the link of a mail works.
+    pub fn lifetime(&self) -> Duration {
+        match *self {
+            Self::ResetPassword => Duration::from_secs(60 * 60),
+            Self::VerifyEmail => Duration::from_secs(60 * 60 * 48),
+        }
+    }
+}
+
+impl Display for TokenPurpose {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match *self {
+            Self::ResetPassword => "reset_password",
+            Self::VerifyEmail => "verify_email",
+        })
+    }
+}
+
+/// Who a token was issued to, and the address it was mailed to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenOwner {
+    pub username: String,
+    pub email: String,
+}
+
+/// The parts of a user the mails need.
+pub struct TokenUser {
+    pub name: String,
+    pub username: String,
+    pub email: String,
+}
+
+impl From<&User> for TokenUser {
+    fn from(user: &User) -> Self {
+        TokenUser {
+            name: user.name.clone(),
+            username: user.username.clone(),
+            email: user.email.clone(),
+        }
+    }
+}
+
+/// What tokens are stored as, so a copy of the database doesn't give them away.
+pub fn hash_token(token: &str) -> Vec<u8> {
+    Sha3_256::digest(token.as_bytes()).to_vec()
+}
+
+/// Issues a token for `purpose` to a user, replacing the ones they were issued before.
+pub async fn issue_token(
+    pool: &SqlitePool,
+    username: &str,
+    email: &str,
+    purpose: TokenPurpose,
+) -> Result<String, sqlx::Error> {
+    let mut token = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut token);
+    let token = general_purpose::URL_SAFE_NO_PAD.encode(token);
+    let hash = hash_token(&token);
+    let purpose_name = purpose.to_string();
+    let now = chrono::Utc::now().timestamp();
+    let expires = now + purpose.lifetime().as_secs() as i64;
+    let mut tx = pool.begin().await?;
+    query!(
+        "DELETE FROM account_tokens WHERE expires <= ? OR (username = ? AND purpose = ?)",
+        now,
+        username,
+        purpose_name
+    )
+    .execute(&mut *tx)
+    .await?;
+    query!(
+        "INSERT INTO account_tokens(hash, username, purpose, email, expires) VALUES(?, ?, ?, ?, ?)",
+        hash,
+        username,
+        purpose_name,
+        email,
+        expires
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(token)
+}
+
+/// Uses up a token, returning who it was issued to unless it is unknown, expired or for
+/// something else.
+pub async fn redeem_token(
+    pool: &SqlitePool,
+    token: &str,
+    purpose: TokenPurpose,
+) -> Result<Option<TokenOwner>, sqlx::Error> {
+    let hash = hash_token(token);
+    let purpose = purpose.to_string();
+    let now = chrono::Utc::now().timestamp();
+    let owner = query!(
+        "DELETE FROM account_tokens WHERE hash = ? AND purpose = ? RETURNING username, email, expires",
+        hash,
+        purpose
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(owner
+        .filter(|owner| owner.expires > now)
+        .map(|owner| TokenOwner {
+            username: owner.username,
+            email: owner.email,
+        }))
+}
+
+/// What the mail templates are rendered with.
+#[derive(Serialize)]
+struct MailContext<'a> {
+    site: String,
+    name: &'a str,
+    username: &'a str,
+    email: &'a str,
+    link: String,
+    hours: u64,
+}
+
+/// Mails a link to a user, who proves they read their mail by following it.
+async fn mail_link(
+    config: &SiteConfig,
+    user: &TokenUser,
+    purpose: TokenPurpose,
+    page: &str,
+) -> anyhow::Result<()> {
+    let pool = config.db_pool.as_ref().unwrap();
+    let token = issue_token(pool, &user.username, &user.email, purpose).await?;
+    let context = MailContext {
+        site: config.base_url(),
+        name: &user.name,
+        username: &user.username,
+        email: &user.email,
+        link: format!("{}{page}?token={token}", config.base_url()),
+        hours: purpose.lifetime().as_secs() / 60 / 60,
+    };
+    config
+        .mailer
+        .send_template(&purpose.to_string(), &user.email, &context)
+        .await
+}
+
+/// Mails a user the link to verify their email with.
+pub async fn send_verification_mail(config: &SiteConfig, user: &TokenUser) -> anyhow::Result<()> {
+    mail_link(
+        config,
+        user,
+        TokenPurpose::VerifyEmail,
+        "/api/user/email/verify",
+    )
+    .await
+}
+
+#[derive(Deserialize, Serialize, TryFromMultipart)]
+pub struct ForgotPasswordRequest {
+    /// The username or the email of the account
+    login: String,
+}
+
+/// Seconds the password resets of an address or account are counted over.
+const RESET_WINDOW: i64 = 60 * 60;
+
+/// Counts a request for a reset mail, returns whether neither the address nor the login
+/// went over `per_hour`.
+async fn within_reset_limit(
+    pool: &SqlitePool,
+    ip: &str,
+    login: &str,
+    per_hour: u32,
+) -> Result<bool, sqlx::Error> {
+    let since = chrono::Utc::now().timestamp() - RESET_WINDOW;
+    let mut tx = pool.begin().await?;
+    query!("DELETE FROM reset_attempts WHERE attempted <= ?", since)
+        .execute(&mut *tx)
+        .await?;
+    let by_ip = sqlx::query_scalar!("SELECT COUNT(*) FROM reset_attempts WHERE ip = ?", ip)
+        .fetch_one(&mut *tx)
+        .await?;
+    let by_login =
+        sqlx::query_scalar!("SELECT COUNT(*) FROM reset_attempts WHERE login = ?", login)
+            .fetch_one(&mut *tx)
+            .await?;
+    if i64::from(by_ip.max(by_login)) >= i64::from(per_hour) {
+        return Ok(false);
+    }
+    query!(
+        "INSERT INTO reset_attempts(ip, login) VALUES(?, ?)",
+        ip,
+        login
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Looks up the account of `login` and mails it a reset link, if there is one.
+async fn mail_reset(config: &SiteConfig, login: &str) -> anyhow::Result<()> {
+    let user = query!(
+        "SELECT name, username, email FROM users WHERE username = ?1 OR email = ?1",
+        login
+    )
+    .fetch_optional(config.db_pool.as_ref().unwrap())
+    .await?;
+    let Some(user) = user else {
+        return Ok(());
+    };
+    let user = TokenUser {
+        name: user.name,
+        username: user.username,
+        email: user.email,
+    };
+    mail_link(
+        config,
+        &user,
+        TokenPurpose::ResetPassword,
+        &config.mail.reset_page,
+    )
+    .await
+}
+
+/// Mails a link to reset the password to a user. The mail is sent in the background and the
+/// response is the same whether there is such a user or not, so neither it nor how long it
+/// takes tells which accounts exist.
+pub async fn forgot_password(
+    headers: HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    State(config): State<SiteConfig>,
+    TypedMultipart(req): TypedMultipart<ForgotPasswordRequest>,
+) -> StatusCode {
+    let Some(ip) = client_ip(&config, &headers, connect_info) else {
+        return StatusCode::BAD_REQUEST;
+    };
+    let pool = config.db_pool.as_ref().unwrap();
+    match within_reset_limit(pool, &ip, &req.login, config.mail.resets_per_hour).await {
+        Ok(true) => {}
+        Ok(false) => return StatusCode::TOO_MANY_REQUESTS,
+        Err(e) => {
+            log::error!("Error while counting password resets: {}", e);
+            return StatusCode::OK;
+        }
+    }
+    tokio::spawn(async move {
+        if let Err(e) = mail_reset(&config, &req.login).await {
+            log::error!("Failed to mail a password reset for {}: {:#}", req.login, e);
+        }
+    });
+    StatusCode::OK
+}
+
+#[derive(Deserialize, Serialize, TryFromMultipart)]
+pub struct ResetPasswordRequest {
+    token: String,
+    pass: String,
+}
+
+/// Sets the password of the user a reset link was mailed to, which signs them out everywhere.
+pub async fn reset_password(
+    State(config): State<SiteConfig>,
+    TypedMultipart(req): TypedMultipart<ResetPasswordRequest>,
+) -> Result<StatusCode, (StatusCode, &'static str)> {
+    if req.pass.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "The password can't be empty"));
+    }
+    let pool = config.db_pool.as_ref().unwrap();
+    let owner = match redeem_token(pool, &req.token, TokenPurpose::ResetPassword).await {
+        Ok(Some(owner)) => owner,
+        Ok(None) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "The link is invalid or has expired",
+            ))
+        }
+        Err(e) => {
+            log::error!("Error while resetting a password: {}", e);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to reset the password",
+            ));
+        }
+    };
+    match set_password(pool, &owner.username, &req.pass).await {
+        Ok(_) => Ok(StatusCode::OK),
+        Err(e) => {
+            log::error!(
+                "Error while resetting the password of {}: {}",
+                owner.username,
+                e
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to reset the password",
+            ))
+        }
+    }
+}
+
+/// Mails the signed in user a new link to verify their email with.
+pub async fn request_verification(user: User, State(config): State<SiteConfig>) -> StatusCode {
+    match send_verification_mail(&config, &(&user).into()).await {
+        Ok(()) => StatusCode::OK,
+        Err(e) => {
+            log::error!("Failed to mail {} a verification: {:#}", user.username, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct VerifyEmailRequest {
+    token: String,
+}
+
+/// Where the links of verification mails go, the email has to be the one the link was mailed
+/// to.
+pub async fn verify_email(
+    State(config): State<SiteConfig>,
+    Query(req): Query<VerifyEmailRequest>,
+) -> Result<StatusCode, (StatusCode, &'static str)> {
+    let pool = config.db_pool.as_ref().unwrap();
+    let invalid = (
+        StatusCode::BAD_REQUEST,
+        "The link is invalid or has expired",
+    );
+    let owner = match redeem_token(pool, &req.token, TokenPurpose::VerifyEmail).await {
+        Ok(Some(owner)) => owner,
+        Ok(None) => return Err(invalid),
+        Err(e) => {
+            log::error!("Error while verifying an email: {}", e);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to verify the email",
+            ));
+        }
+    };
+    match query!(
+        "UPDATE users SET email_verified = 1 WHERE username = ? AND email = ?",
+        owner.username,
+        owner.email
+    )
+    .execute(pool)
+    .await
+    {
+        Ok(r) if r.rows_affected() == 0 => Err(invalid),
+        Ok(_) => Ok(StatusCode::OK),
+        Err(e) => {
+            log::error!("Error while verifying an email: {}", e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to verify the email",
+            ))
+        }
+    }
+}
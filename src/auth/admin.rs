@@ -1,8 +1,10 @@
-use axum::{async_trait, extract::{FromRequestParts, Request}, http::{request::Parts, StatusCode}, middleware::Next, response::{IntoResponse, Response}, Extension};
+use std::fmt::Display;
+
+use sqlx::SqlitePool;
 
 use crate::config::SiteConfig;
 
-use super::{sign_up::UserSignUp, user::{Rank, User}};
+use super::{session::end_sessions, sign_up::UserSignUp, user::{Rank, User, UserInfo}};
 
 pub async fn create_privileged(user: UserSignUp, rank: Rank, state: &SiteConfig) -> Result<(), String> {
     let user: User = user.try_into().unwrap();
@@ -27,25 +29,88 @@ pub async fn create_privileged(user: UserSignUp, rank: Rank, state: &SiteConfig)
     }
 }
 
-pub struct Admin(User);
+/// Every account of a site, by username.
+pub async fn list_users(pool: &SqlitePool) -> Result<Vec<UserInfo>, sqlx::Error> {
+    sqlx::query_as!(
+        UserInfo,
+        r#"SELECT name, username, profile_pic, email, rank, email_verified as "email_verified: bool" FROM users ORDER BY username"#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Changes the rank of a user, returns whether the user exists.
+pub async fn set_rank(pool: &SqlitePool, username: &str, rank: Rank) -> Result<bool, sqlx::Error> {
+    let rank = rank.to_string();
+    let result = sqlx::query!("UPDATE users SET rank = ? WHERE username = ?", rank, username)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
 
-#[async_trait]
-impl<S> FromRequestParts<S> for Admin
-where
-    S: Send + Sync,
-{
-    type Rejection = Response;
+/// Makes a user choose a new password before they can sign in again, returns whether the
+/// user exists.
+pub async fn require_password_reset(pool: &SqlitePool, username: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!("UPDATE users SET reset_password = 1 WHERE username = ?", username)
+        .execute(pool)
+        .await?;
+    // Signed in devices would otherwise keep going with the old password
+    end_sessions(pool, username).await?;
+    Ok(result.rows_affected() > 0)
+}
 
-    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        use axum::RequestPartsExt;
-        let Extension(user) = parts.extract::<Extension<User>>()
-            .await
-            .map_err(|err| err.into_response())?;
+#[derive(Debug)]
+pub enum UserDeleteError {
+    NotFound,
+    /// Posts can't be left without an owner, they have to be given to someone else first.
+    OwnsPosts(i64),
+    Database(sqlx::Error),
+}
 
-        if user.rank == Rank::Admin {
-            Err(StatusCode::UNAUTHORIZED.into_response())
-        } else {
-            Ok(Self(user))
+impl Display for UserDeleteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "There is no such user"),
+            Self::OwnsPosts(count) => write!(f, "The user still owns {count} posts"),
+            Self::Database(e) => write!(f, "{e}"),
         }
     }
 }
+
+impl From<sqlx::Error> for UserDeleteError {
+    fn from(value: sqlx::Error) -> Self {
+        Self::Database(value)
+    }
+}
+
+/// Deletes a user, whose posts go to `heir` if there is one. Their comments stay under the
+/// name they were written with.
+pub async fn delete_user(
+    pool: &SqlitePool,
+    username: &str,
+    heir: Option<&str>,
+) -> Result<(), UserDeleteError> {
+    let mut tx = pool.begin().await?;
+    if let Some(heir) = heir {
+        sqlx::query!("UPDATE posts SET owner = ? WHERE owner = ?", heir, username)
+            .execute(&mut *tx)
+            .await?;
+    }
+    let posts = sqlx::query_scalar!("SELECT COUNT(*) FROM posts WHERE owner = ?", username)
+        .fetch_one(&mut *tx)
+        .await?;
+    if posts > 0 {
+        return Err(UserDeleteError::OwnsPosts(posts.into()));
+    }
+    sqlx::query!("UPDATE comments SET author = NULL WHERE author = ?", username)
+        .execute(&mut *tx)
+        .await?;
+    let result = sqlx::query!("DELETE FROM users WHERE username = ?", username)
+        .execute(&mut *tx)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(UserDeleteError::NotFound);
+    }
+    tx.commit().await?;
+    Ok(())
+}
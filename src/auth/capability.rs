@@ -0,0 +1,123 @@
+//! What each rank may do, as named capabilities, and the extractor `setup_routes` guards
+//! routes with.
+
+use std::{fmt::Display, marker::PhantomData};
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
+    middleware::{from_extractor_with_state, FromExtractorLayer},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::config::SiteConfig;
+
+use super::user::{Rank, User};
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    /// Writing posts and editing them, publishing needs `PublishPosts` as well
+    EditOwnPosts,
+    PublishPosts,
+    EditOthersPosts,
+    ManageUsers,
+    ManageSettings,
+    ModerateComments,
+}
+
+impl Display for Capability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match *self {
+            Self::EditOwnPosts => "edit_own_posts",
+            Self::PublishPosts => "publish_posts",
+            Self::EditOthersPosts => "edit_others_posts",
+            Self::ManageUsers => "manage_users",
+            Self::ManageSettings => "manage_settings",
+            Self::ModerateComments => "moderate_comments",
+        })
+    }
+}
+
+impl Rank {
+    /// Every capability of the rank, each rank has those of the ranks below it.
+    pub fn capabilities(&self) -> &'static [Capability] {
+        use Capability::*;
+        match *self {
+            Self::Subscriber => &[],
+            Self::Contributor => &[EditOwnPosts],
+            Self::Author => &[EditOwnPosts, PublishPosts],
+            Self::Editor => &[
+                EditOwnPosts,
+                PublishPosts,
+                EditOthersPosts,
+                ModerateComments,
+            ],
+            Self::Administrator => &[
+                EditOwnPosts,
+                PublishPosts,
+                EditOthersPosts,
+                ManageUsers,
+                ManageSettings,
+                ModerateComments,
+            ],
+        }
+    }
+
+    pub fn can(&self, capability: Capability) -> bool {
+        self.capabilities().contains(&capability)
+    }
+}
+
+/// A capability a route can require, see [`Require`].
+pub trait RequiredCapability: Send + Sync + 'static {
+    const CAPABILITY: Capability;
+}
+
+macro_rules! capabilities {
+    ($($marker:ident => $capability:ident),* $(,)?) => {
+        $(
+            pub struct $marker;
+
+            impl RequiredCapability for $marker {
+                const CAPABILITY: Capability = Capability::$capability;
+            }
+        )*
+    };
+}
+
+capabilities! {
+    CanEditOwnPosts => EditOwnPosts,
+    CanPublishPosts => PublishPosts,
+    CanEditOthersPosts => EditOthersPosts,
+    CanManageUsers => ManageUsers,
+    CanManageSettings => ManageSettings,
+    CanModerateComments => ModerateComments,
+}
+
+/// A signed in user with the capability `C`, anyone else is turned away.
+pub struct Require<C: RequiredCapability>(pub User, PhantomData<C>);
+
+#[async_trait]
+impl<C: RequiredCapability> FromRequestParts<SiteConfig> for Require<C> {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &SiteConfig,
+    ) -> Result<Self, Self::Rejection> {
+        let user = User::from_request_parts(parts, state).await?;
+        match user.rank.can(C::CAPABILITY) {
+            true => Ok(Self(user, PhantomData)),
+            false => Err((StatusCode::FORBIDDEN, "Not allowed for your rank")),
+        }
+    }
+}
+
+/// Layer for a route of `setup_routes` that only lets users with the capability `C` through.
+pub fn require<C: RequiredCapability>(
+    config: &SiteConfig,
+) -> FromExtractorLayer<Require<C>, SiteConfig> {
+    from_extractor_with_state(config.clone())
+}
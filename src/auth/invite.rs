@@ -0,0 +1,98 @@
+//! Invite codes, which sites that are invite only need for signing up. Only their hashes are
+//! stored, the code is shown once when it is made.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use base64::{engine::general_purpose, Engine};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, SqliteConnection, SqlitePool};
+
+use crate::config::SiteConfig;
+
+use super::{account_token::hash_token, user::User};
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Invite {
+    pub code: String,
+    pub uses: u32,
+    /// Unix timestamp, the invite never expires without one
+    pub expires: Option<i64>,
+}
+
+/// Makes an invite code that works `uses` times, for `days` days or forever.
+pub async fn create_invite(
+    pool: &SqlitePool,
+    created_by: Option<&str>,
+    uses: u32,
+    days: Option<u32>,
+) -> Result<Invite, sqlx::Error> {
+    let mut code = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut code);
+    let code = general_purpose::URL_SAFE_NO_PAD.encode(code);
+    let hash = hash_token(&code);
+    let expires = days.map(|days| chrono::Utc::now().timestamp() + i64::from(days) * 60 * 60 * 24);
+    query!(
+        "INSERT INTO invites(hash, created_by, expires, uses_left) VALUES(?, ?, ?, ?)",
+        hash,
+        created_by,
+        expires,
+        uses
+    )
+    .execute(pool)
+    .await?;
+    Ok(Invite {
+        code,
+        uses,
+        expires,
+    })
+}
+
+/// Uses an invite code up once, returns whether it still worked. Takes a connection so the
+/// use can be rolled back along with a sign up that failed.
+pub async fn use_invite(conn: &mut SqliteConnection, code: &str) -> Result<bool, sqlx::Error> {
+    let hash = hash_token(code);
+    let now = chrono::Utc::now().timestamp();
+    let result = query!(
+        "UPDATE invites SET uses_left = uses_left - 1
+        WHERE hash = ? AND uses_left > 0 AND (expires IS NULL OR expires > ?)",
+        hash,
+        now
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct InviteRequest {
+    /// How many accounts can sign up with the code, one when left out
+    uses: Option<u32>,
+    /// How many days the code works, it doesn't expire when left out
+    days: Option<u32>,
+}
+
+pub async fn generate_invite(
+    user: User,
+    State(config): State<SiteConfig>,
+    Query(req): Query<InviteRequest>,
+) -> Result<Json<Invite>, StatusCode> {
+    let uses = req.uses.unwrap_or(1);
+    match create_invite(
+        config.db_pool.as_ref().unwrap(),
+        Some(&user.username),
+        uses,
+        req.days,
+    )
+    .await
+    {
+        Ok(invite) => Ok(Json(invite)),
+        Err(e) => {
+            log::error!("Error while creating an invite: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! The keys the tokens of a site are signed with. Every site has its own, kept in
+//! `PeroxideKeys.toml` next to its config, so a token of one site means nothing to the others.
+//! Tokens name their key with a `kid`, which lets older keys keep verifying the tokens they
+//! signed after a new one takes over.
+
+use std::{
+    fs, io,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use base64::{engine::general_purpose, Engine};
+use jsonwebtoken::{
+    errors::{Error, ErrorKind},
+    DecodingKey, EncodingKey, Header, Validation,
+};
+use rand::RngCore;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::session::SESSION_LIFETIME;
+
+pub const KEYS_FILE: &str = "PeroxideKeys.toml";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SigningKey {
+    pub kid: String,
+    /// Base64 of the HMAC secret
+    secret: String,
+    /// When the key was made, as a unix timestamp
+    pub created: i64,
+}
+
+impl SigningKey {
+    fn generate() -> Self {
+        let mut kid = [0u8; 9];
+        let mut secret = [0u8; 64];
+        rand::thread_rng().fill_bytes(&mut kid);
+        rand::thread_rng().fill_bytes(&mut secret);
+        Self {
+            kid: general_purpose::URL_SAFE_NO_PAD.encode(kid),
+            secret: general_purpose::STANDARD.encode(secret),
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64,
+        }
+    }
+
+    fn secret(&self) -> Result<Vec<u8>, Error> {
+        general_purpose::STANDARD
+            .decode(&self.secret)
+            .map_err(|_| ErrorKind::InvalidKeyFormat.into())
+    }
+}
+
+/// The signing keys of a site, oldest first. The newest one signs new tokens, all of them
+/// verify.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct SiteKeys {
+    keys: Vec<SigningKey>,
+}
+
+impl SiteKeys {
+    /// Keys with a single new key, which aren't saved anywhere.
+    pub fn generate() -> Self {
+        Self {
+            keys: vec![SigningKey::generate()],
+        }
+    }
+
+    /// Reads the keys of the site at `path`, `None` when it has none yet.
+    pub fn load(path: &str) -> anyhow::Result<Option<Self>> {
+        let file = format!("{path}/{KEYS_FILE}");
+        match fs::read_to_string(&file) {
+            Ok(keys) => {
+                let keys: Self = toml::from_str(&keys)
+                    .with_context(|| format!("Failed to parse the keys {file}"))?;
+                if keys.keys.is_empty() {
+                    anyhow::bail!("There are no keys in {file}");
+                }
+                Ok(Some(keys))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read the keys {file}")),
+        }
+    }
+
+    /// Reads the keys of the site at `path`, or makes and saves them when it has none yet.
+    pub fn load_or_generate(path: &str) -> anyhow::Result<Self> {
+        if let Some(keys) = Self::load(path)? {
+            return Ok(keys);
+        }
+        log::info!("Generating the signing key of {path}");
+        let keys = Self::generate();
+        keys.save(path)
+            .with_context(|| format!("Failed to write the keys {path}/{KEYS_FILE}"))?;
+        Ok(keys)
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let keys = toml::to_string(&self).expect("Encoding the SiteKeys struct");
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        io::Write::write_all(
+            &mut options.open(format!("{path}/{KEYS_FILE}"))?,
+            keys.as_bytes(),
+        )
+    }
+
+    pub fn keys(&self) -> &[SigningKey] {
+        &self.keys
+    }
+
+    /// Adds a key that signs from now on. Keys that were replaced longer ago than sessions
+    /// last are dropped, nothing they signed is valid anymore.
+    pub fn rotate(&mut self) -> &SigningKey {
+        let key = SigningKey::generate();
+        let expired = key.created - SESSION_LIFETIME.as_secs() as i64;
+        let replaced: Vec<i64> = self.keys.iter().skip(1).map(|k| k.created).collect();
+        let mut replaced = replaced.into_iter();
+        self.keys
+            .retain(|_| replaced.next().is_none_or(|when| when > expired));
+        self.keys.push(key);
+        self.keys.last().unwrap()
+    }
+
+    /// Drops every key but the newest, the tokens they signed stop working.
+    pub fn retire_old(&mut self) {
+        let newest = self.keys.len().saturating_sub(1);
+        self.keys.drain(..newest);
+    }
+
+    /// Signs `claims` with the newest key.
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String, Error> {
+        let key = self
+            .keys
+            .last()
+            .ok_or_else(|| Error::from(ErrorKind::InvalidKeyFormat))?;
+        let header = Header {
+            kid: Some(key.kid.clone()),
+            ..Default::default()
+        };
+        jsonwebtoken::encode(&header, claims, &EncodingKey::from_secret(&key.secret()?))
+    }
+
+    /// Verifies a token with the key it names.
+    pub fn decode<T: DeserializeOwned>(&self, token: &str) -> Result<T, Error> {
+        let header = jsonwebtoken::decode_header(token)?;
+        let key = self
+            .keys
+            .iter()
+            .find(|key| header.kid.as_ref() == Some(&key.kid))
+            .ok_or_else(|| Error::from(ErrorKind::InvalidToken))?;
+        let token = jsonwebtoken::decode::<T>(
+            token,
+            &DecodingKey::from_secret(&key.secret()?),
+            &Validation::new(jsonwebtoken::Algorithm::HS256),
+        )?;
+        Ok(token.claims)
+    }
+}
@@ -0,0 +1,94 @@
+//! Password hashing, Argon2id for new hashes while the salted SHA3 ones of older versions are
+//! still accepted.
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use sha3::{Digest, Sha3_512};
+use sqlx::{query, SqlitePool};
+
+use super::{session::end_sessions, user::User};
+
+/// Hashes of older versions, one round of SHA3-512 over the salt and the password.
+fn legacy_hash(salt: &[u8], pass: &str) -> Vec<u8> {
+    let mut hasher = Sha3_512::new();
+    hasher.update(salt);
+    hasher.update(pass.as_bytes());
+    hasher.finalize()[..].into()
+}
+
+/// A salt and the Argon2id hash of `pass` with it, as a PHC string, ready for the `salt` and
+/// `sh_pass` columns.
+pub fn hash_password(pass: &str) -> Result<(Vec<u8>, Vec<u8>), argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default().hash_password(pass.as_bytes(), &salt)?;
+    Ok((
+        salt.as_str().as_bytes().to_vec(),
+        hash.to_string().into_bytes(),
+    ))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verification {
+    Valid,
+    /// The password is right, but its hash is of an older kind and should be replaced.
+    Outdated,
+    Invalid,
+}
+
+/// Checks `pass` against the hash of a user, Argon2id hashes are stored as PHC strings and
+/// anything else is a legacy SHA3 hash.
+pub fn verify_password(user: &User, pass: &str) -> Verification {
+    let phc = std::str::from_utf8(&user.sh_pass)
+        .ok()
+        .and_then(|phc| PasswordHash::new(phc).ok());
+    match phc {
+        Some(hash) => match Argon2::default().verify_password(pass.as_bytes(), &hash) {
+            Ok(()) => Verification::Valid,
+            Err(_) => Verification::Invalid,
+        },
+        None if legacy_hash(&user.salt, pass) == user.sh_pass => Verification::Outdated,
+        None => Verification::Invalid,
+    }
+}
+
+/// Replaces the password of a user, lifts a required reset and signs them out everywhere.
+/// Returns whether the user exists.
+pub async fn set_password(
+    pool: &SqlitePool,
+    username: &str,
+    pass: &str,
+) -> Result<bool, anyhow::Error> {
+    let (salt, sh_pass) = hash_password(pass).map_err(|e| anyhow::anyhow!("{e}"))?;
+    let result = query!(
+        "UPDATE users SET salt = ?, sh_pass = ?, reset_password = 0 WHERE username = ?",
+        salt,
+        sh_pass,
+        username
+    )
+    .execute(pool)
+    .await?;
+    end_sessions(pool, username).await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Replaces an outdated hash of the password a user just signed in with by an Argon2id one.
+pub async fn rehash_password(
+    pool: &SqlitePool,
+    user: &mut User,
+    pass: &str,
+) -> Result<(), anyhow::Error> {
+    let (salt, sh_pass) = hash_password(pass).map_err(|e| anyhow::anyhow!("{e}"))?;
+    query!(
+        "UPDATE users SET salt = ?, sh_pass = ? WHERE username = ?",
+        salt,
+        sh_pass,
+        user.username
+    )
+    .execute(pool)
+    .await?;
+    user.salt = salt;
+    user.sh_pass = sh_pass;
+    Ok(())
+}
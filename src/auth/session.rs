@@ -0,0 +1,306 @@
+//! Sessions of signed in users, one for every device. The token in the cookie only names its
+//! session, so signing out takes effect at once and doesn't wait for the token to expire.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts, Query, State},
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    Json,
+};
+use axum_extra::extract::{
+    cookie::{Cookie, SameSite},
+    CookieJar,
+};
+use base64::{engine::general_purpose, Engine};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, SqlitePool};
+
+use crate::config::SiteConfig;
+
+use super::user::User;
+
+pub const SESSION_COOKIE: &str = "jwt-token";
+/// How long a session lasts after signing in, for the token and the cookie alike.
+pub const SESSION_LIFETIME: Duration = Duration::from_secs(60 * 60 * 24 * 14);
+/// `last_seen` is only written again after this many seconds, not on every request.
+const LAST_SEEN_INTERVAL: i64 = 60;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SessionToken {
+    username: String,
+    sid: String,
+    exp: u64,
+}
+
+/// The session a request was made with, along with its user.
+pub struct Session {
+    pub id: String,
+    pub user: User,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SessionInfo {
+    pub id: String,
+    pub created: i64,
+    pub last_seen: i64,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    /// Whether this is the session the sessions were listed with
+    pub current: bool,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Unix timestamp from before which sessions have expired.
+fn expired_before() -> i64 {
+    (now() - SESSION_LIFETIME.as_secs()) as i64
+}
+
+/// Starts a session for `user` and returns the token naming it.
+pub async fn start_session(
+    state: &SiteConfig,
+    user: &User,
+    user_agent: Option<&str>,
+    ip: Option<&str>,
+) -> Result<String, anyhow::Error> {
+    let mut id = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut id);
+    let id = general_purpose::URL_SAFE_NO_PAD.encode(id);
+    let pool = state.db_pool.as_ref().unwrap();
+    let expired = expired_before();
+    query!("DELETE FROM sessions WHERE created <= ?", expired)
+        .execute(pool)
+        .await?;
+    query!(
+        "INSERT INTO sessions(id, username, user_agent, ip) VALUES(?, ?, ?, ?)",
+        id,
+        user.username,
+        user_agent,
+        ip
+    )
+    .execute(pool)
+    .await?;
+    let token = SessionToken {
+        username: user.username.clone(),
+        sid: id,
+        exp: now() + SESSION_LIFETIME.as_secs(),
+    };
+    Ok(state.keys.encode(&token)?)
+}
+
+/// Starts a session for a user who just signed in, from the device the request came from.
+pub async fn start_session_from(
+    state: &SiteConfig,
+    user: &User,
+    headers: &HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+) -> Result<String, anyhow::Error> {
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|agent| agent.to_str().ok());
+    let ip = client_ip(state, headers, connect_info);
+    start_session(state, user, user_agent, ip.as_deref()).await
+}
+
+/// The address a request came from. Behind the proxy named by `forwarded_for` that is the
+/// last address in its header, the one the proxy added itself, and otherwise the address of
+/// the connection. `None` when it can't be told.
+pub fn client_ip(
+    state: &SiteConfig,
+    headers: &HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+) -> Option<String> {
+    match &state.forwarded_for {
+        Some(name) => headers
+            .get(name.as_str())?
+            .to_str()
+            .ok()?
+            .rsplit(',')
+            .next()?
+            .trim()
+            .parse::<IpAddr>()
+            .ok()
+            .map(|ip| ip.to_string()),
+        None => connect_info.map(|ConnectInfo(addr)| addr.ip().to_string()),
+    }
+}
+
+/// The cookie holding the token of a session.
+pub fn session_cookie(token: String) -> Cookie<'static> {
+    Cookie::build((SESSION_COOKIE, token))
+        .http_only(true)
+        .secure(true)
+        .same_site(SameSite::Lax)
+        .max_age(SESSION_LIFETIME.try_into().unwrap())
+        .path("/")
+        .build()
+}
+
+/// Ends every session of a user, like after their password changed. Returns how many there
+/// were.
+pub async fn end_sessions(pool: &SqlitePool, username: &str) -> Result<u64, sqlx::Error> {
+    let result = query!("DELETE FROM sessions WHERE username = ?", username)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+#[async_trait]
+impl FromRequestParts<SiteConfig> for Session {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &SiteConfig,
+    ) -> Result<Self, Self::Rejection> {
+        let jar = CookieJar::from_headers(&parts.headers);
+        let cookie = jar
+            .get(SESSION_COOKIE)
+            .ok_or((StatusCode::UNAUTHORIZED, "Session Cookie not found"))?;
+        let token: SessionToken = state.keys.decode(cookie.value()).map_err(|e| {
+            log::debug!("{}", e);
+            (StatusCode::UNAUTHORIZED, "Invalid or expired session")
+        })?;
+        let pool = state.db_pool.as_ref().unwrap();
+        let expired = expired_before();
+        // Ended sessions are gone, so their tokens stop working even before they expire
+        let user = query_as!(
+            User,
+            r#"SELECT users.salt, users.name, users.username, users.profile_pic, users.sh_pass,
+                users.email, users.rank, users.reset_password AS "reset_password: bool"
+            FROM sessions JOIN users ON users.username = sessions.username
+            WHERE sessions.id = ? AND sessions.username = ? AND sessions.created > ?"#,
+            token.sid,
+            token.username,
+            expired
+        )
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| {
+            log::error!("{}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to find the session",
+            )
+        })?
+        .ok_or((StatusCode::UNAUTHORIZED, "Invalid or expired session"))?;
+        let seen = now() as i64;
+        let stale = seen - LAST_SEEN_INTERVAL;
+        if let Err(e) = query!(
+            "UPDATE sessions SET last_seen = ? WHERE id = ? AND last_seen < ?",
+            seen,
+            token.sid,
+            stale
+        )
+        .execute(pool)
+        .await
+        {
+            log::error!("Failed to update the session of {}: {}", user.username, e);
+        }
+        Ok(Session {
+            id: token.sid,
+            user,
+        })
+    }
+}
+
+/// Removes the cookie of a session from the browser.
+fn remove_cookie(cookie_jar: CookieJar) -> CookieJar {
+    cookie_jar.remove(Cookie::build(SESSION_COOKIE).path("/"))
+}
+
+pub async fn logout(
+    session: Session,
+    cookie_jar: CookieJar,
+    State(state): State<SiteConfig>,
+) -> Result<CookieJar, StatusCode> {
+    match query!("DELETE FROM sessions WHERE id = ?", session.id)
+        .execute(&state.db_pool.unwrap())
+        .await
+    {
+        Ok(_) => Ok(remove_cookie(cookie_jar)),
+        Err(e) => {
+            log::error!("{}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Ends every session of the user, on every device.
+pub async fn logout_everywhere(
+    session: Session,
+    cookie_jar: CookieJar,
+    State(state): State<SiteConfig>,
+) -> Result<CookieJar, StatusCode> {
+    match end_sessions(&state.db_pool.unwrap(), &session.user.username).await {
+        Ok(_) => Ok(remove_cookie(cookie_jar)),
+        Err(e) => {
+            log::error!("{}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// The sessions of the user that are still active, the most recently seen first.
+pub async fn list_sessions(
+    session: Session,
+    State(state): State<SiteConfig>,
+) -> Result<Json<Vec<SessionInfo>>, StatusCode> {
+    let expired = expired_before();
+    match query_as!(
+        SessionInfo,
+        r#"SELECT id, created, last_seen, user_agent, ip, id = ? AS "current!: bool"
+        FROM sessions WHERE username = ? AND created > ? ORDER BY last_seen DESC"#,
+        session.id,
+        session.user.username,
+        expired
+    )
+    .fetch_all(&state.db_pool.unwrap())
+    .await
+    {
+        Ok(sessions) => Ok(Json(sessions)),
+        Err(e) => {
+            log::error!("{}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SessionRevokeRequest {
+    pub id: String,
+}
+
+/// Ends one of the sessions of the user, like that of a lost device.
+pub async fn revoke_session(
+    session: Session,
+    State(state): State<SiteConfig>,
+    Query(req): Query<SessionRevokeRequest>,
+) -> StatusCode {
+    match query!(
+        "DELETE FROM sessions WHERE id = ? AND username = ?",
+        req.id,
+        session.user.username
+    )
+    .execute(&state.db_pool.unwrap())
+    .await
+    {
+        Ok(result) if result.rows_affected() == 0 => StatusCode::NOT_FOUND,
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            log::error!("{}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
@@ -1,43 +1,41 @@
-use std::time::Duration;
+use std::net::SocketAddr;
 
-use sha3::Digest;
-
-use axum::{extract::State, http::StatusCode};
-use axum_extra::extract::{
-    cookie::{Cookie, SameSite},
-    CookieJar,
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, StatusCode},
 };
+use axum_extra::extract::CookieJar;
 use axum_typed_multipart::{TryFromMultipart, TypedMultipart};
-use jsonwebtoken::Header;
 use serde::{Deserialize, Serialize};
-use sha3::Sha3_512;
 use sqlx::{prelude::*, query_as};
 
 use crate::{auth::user::User, config::SiteConfig};
 
-use super::user::{UserToken, KEYS};
+use super::{
+    password::{rehash_password, verify_password, Verification},
+    session::{session_cookie, start_session_from},
+};
 
-#[derive(Deserialize, Serialize, Clone, Debug)]
-enum UserSignInError {
-    FailedHashing,
-}
 #[derive(Deserialize, Serialize, Encode, FromRow, TryFromMultipart)]
 pub struct UserSignIn {
     username: String,
     pass: String,
 }
 
-pub async fn sign_in<'a>(
+pub async fn sign_in(
     cookie_jar: CookieJar,
+    headers: HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
     State(state): State<SiteConfig>,
     TypedMultipart(user_resp): TypedMultipart<UserSignIn>,
 ) -> Result<CookieJar, (StatusCode, String)> {
-    let user = match query_as!(
+    let pool = state.db_pool.clone().unwrap();
+    let mut user = match query_as!(
         User,
-        "select * from users where username = ?",
+        r#"select salt, name, username, profile_pic, sh_pass, email, rank, reset_password as "reset_password: bool" from users where username = ?"#,
         user_resp.username
     )
-    .fetch_one(&state.db_pool.unwrap())
+    .fetch_one(&pool)
     .await
     {
         Ok(user) => user,
@@ -46,29 +44,28 @@ pub async fn sign_in<'a>(
             return Err((StatusCode::UNAUTHORIZED, String::from("Unable to Log in")));
         }
     };
-    let mut hasher = Sha3_512::new();
-    hasher.update(user.salt.clone());
-    hasher.update(user_resp.pass.as_bytes());
-    let salted_hash: Vec<u8> = hasher.finalize()[..].into();
-    if Vec::from(salted_hash) == user.sh_pass {
-        let user_token: UserToken = user.into();
-        match jsonwebtoken::encode(&Header::default(), &user_token, &KEYS.encoding) {
+    let verification = verify_password(&user, &user_resp.pass);
+    if verification != Verification::Invalid {
+        if user.reset_password {
+            return Err((
+                StatusCode::FORBIDDEN,
+                String::from("A password reset is required"),
+            ));
+        }
+        if verification == Verification::Outdated {
+            // Signing in still works with the old hash, so it is tried again the next time
+            if let Err(e) = rehash_password(&pool, &mut user, &user_resp.pass).await {
+                log::error!("Failed to rehash the password of {}: {}", user.username, e);
+            }
+        }
+        return match start_session_from(&state, &user, &headers, connect_info).await {
+            Ok(token) => Ok(cookie_jar.add(session_cookie(token))),
             Err(e) => {
                 log::error!("{}", e);
-                return Err((
+                Err((
                     StatusCode::INTERNAL_SERVER_ERROR,
                     String::from("Unable to Log in"),
-                ));
-            }
-            Ok(s) => {
-                return Ok(cookie_jar.add(
-                    Cookie::build(("jwt-token", s))
-                        .http_only(true)
-                        .secure(true)
-                        .same_site(SameSite::Lax)
-                        .max_age(Duration::from_secs(60 * 60 * 12).try_into().unwrap())
-                        .path("/"),
-                ));
+                ))
             }
         };
     }
@@ -1,16 +1,23 @@
-use axum::{extract::State, http::StatusCode, Json};
+use std::{fmt::Display, net::SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
 use axum_typed_multipart::{TryFromMultipart, TypedMultipart};
-use clap::Parser;
-use rand::random;
 use serde::{Deserialize, Serialize};
-use sha3::Sha3_512;
-use sqlx::{prelude::FromRow, Encode};
-
-use sha3::Digest;
+use sqlx::{prelude::FromRow, Encode, SqlitePool};
 
-use crate::config::SiteConfig;
+use crate::config::{RegistrationPolicy, SiteConfig};
 
-use super::user::{Rank, User, UserInfo};
+use super::{
+    account_token::send_verification_mail,
+    invite::use_invite,
+    password::hash_password,
+    session::client_ip,
+    user::{Rank, User, UserInfo},
+};
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub enum UserSignUpError {
@@ -21,22 +28,17 @@ impl TryFrom<UserSignUp> for User {
     type Error = UserSignUpError;
 
     fn try_from(value: UserSignUp) -> Result<Self, Self::Error> {
-        let mut salt: [u8; 64] = [0; 64];
-        for b in salt.iter_mut() {
-            *b = random();
-        }
-        let mut hasher = Sha3_512::new();
-        hasher.update(salt);
-        hasher.update(value.pass);
-        let salted_hash: Vec<u8> = hasher.finalize()[..].into();
+        let (salt, sh_pass) =
+            hash_password(&value.pass).map_err(|_| UserSignUpError::FailedHashing)?;
         Ok(User {
             name: value.name,
             username: value.username,
             profile_pic: None,
-            salt: Vec::from(salt),
-            sh_pass: salted_hash,
+            salt,
+            sh_pass,
             email: value.email,
-            rank: Rank::User,
+            rank: Rank::Author,
+            reset_password: false,
         })
     }
 }
@@ -50,26 +52,183 @@ pub struct UserSignUp {
 }
 
 pub async fn create_user(
-    admin: User,
+    _admin: User,
     State(state): State<SiteConfig>,
     TypedMultipart(user_resp): TypedMultipart<UserSignUp>,
 ) -> Result<Json<UserInfo>, StatusCode> {
     let new_user: User = user_resp.try_into().unwrap();
-    match sqlx::query!("insert into users (name, username, profile_pic, salt, sh_pass, email) values($1, $2, $3, $4, $5, $6)", new_user.name, new_user.username, new_user.profile_pic, new_user.salt, new_user.sh_pass, new_user.email).execute(&state.db_pool.unwrap()).await {
+    let rank = new_user.rank.to_string();
+    match sqlx::query!("insert into users (name, username, profile_pic, salt, sh_pass, email, rank) values($1, $2, $3, $4, $5, $6, $7)", new_user.name, new_user.username, new_user.profile_pic, new_user.salt, new_user.sh_pass, new_user.email, rank).execute(state.db_pool.as_ref().unwrap()).await {
         Err(e) => {
             log::error!("{:?}", e);
             Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
         Ok(r) => {
             log::info!("{:?}", r);
+            // The account is there either way, the user can ask for another mail
+            if let Err(e) = send_verification_mail(&state, &(&new_user).into()).await {
+                log::error!("Failed to mail {} a verification: {:#}", new_user.username, e);
+            }
             Ok(Json(UserInfo {
                 name: new_user.name,
                 username: new_user.username,
                 profile_pic: None,
                 email: new_user.email,
                 rank: new_user.rank,
+                email_verified: false,
             }))
 
         }
     }
 }
+
+/// What anyone can sign up with, see [`sign_up`].
+#[derive(Deserialize, Serialize, TryFromMultipart)]
+pub struct PublicSignUp {
+    pub name: String,
+    pub username: String,
+    pub pass: String,
+    pub email: String,
+    /// Needed on sites that are invite only
+    pub invite: Option<String>,
+}
+
+/// Seconds the sign ups of an address are counted over.
+const ATTEMPT_WINDOW: i64 = 60 * 60;
+
+/// Counts an attempt to sign up from `ip`, returns whether it is within `per_hour`.
+async fn within_rate_limit(
+    pool: &SqlitePool,
+    ip: &str,
+    per_hour: u32,
+) -> Result<bool, sqlx::Error> {
+    let since = chrono::Utc::now().timestamp() - ATTEMPT_WINDOW;
+    let mut tx = pool.begin().await?;
+    sqlx::query!("DELETE FROM sign_up_attempts WHERE attempted <= ?", since)
+        .execute(&mut *tx)
+        .await?;
+    let attempts = sqlx::query_scalar!("SELECT COUNT(*) FROM sign_up_attempts WHERE ip = ?", ip)
+        .fetch_one(&mut *tx)
+        .await?;
+    if i64::from(attempts) >= i64::from(per_hour) {
+        return Ok(false);
+    }
+    sqlx::query!("INSERT INTO sign_up_attempts(ip) VALUES(?)", ip)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// The domain of an email, lowercased, unless it doesn't look like one.
+fn email_domain(email: &str) -> Option<String> {
+    let (local, domain) = email.rsplit_once('@')?;
+    match local.is_empty() || domain.is_empty() {
+        true => None,
+        false => Some(domain.to_ascii_lowercase()),
+    }
+}
+
+fn sign_up_failed(e: impl Display) -> (StatusCode, &'static str) {
+    log::error!("Error while signing up: {}", e);
+    (StatusCode::INTERNAL_SERVER_ERROR, "Failed to sign up")
+}
+
+/// Lets anyone sign up as far as the registration policy of the site allows. The account
+/// gets the default rank of the site and a mail to verify its email with.
+pub async fn sign_up(
+    headers: HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    State(state): State<SiteConfig>,
+    TypedMultipart(form): TypedMultipart<PublicSignUp>,
+) -> Result<Json<UserInfo>, (StatusCode, &'static str)> {
+    let registration = &state.registration;
+    let pool = state.db_pool.as_ref().unwrap();
+    if registration.policy == RegistrationPolicy::Closed {
+        return Err((StatusCode::FORBIDDEN, "Signing up is closed"));
+    }
+    // Before anything else, so invite codes can't be guessed at either. Clients whose
+    // address is unknown would all share one limit, so they are turned away instead.
+    let ip = client_ip(&state, &headers, connect_info).ok_or((
+        StatusCode::BAD_REQUEST,
+        "The address of the request is unknown",
+    ))?;
+    if !within_rate_limit(pool, &ip, registration.attempts_per_hour)
+        .await
+        .map_err(sign_up_failed)?
+    {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many sign ups, try again later",
+        ));
+    }
+    if form.username.is_empty() || form.pass.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The username and password can't be empty",
+        ));
+    }
+    let domain =
+        email_domain(&form.email).ok_or((StatusCode::BAD_REQUEST, "The email is invalid"))?;
+    if registration.policy == RegistrationPolicy::DomainAllowlist
+        && !registration
+            .allowed_domains
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(&domain))
+    {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Signing up needs an email of another domain",
+        ));
+    }
+
+    let mut user: User = UserSignUp {
+        name: form.name,
+        username: form.username,
+        pass: form.pass,
+        email: form.email,
+    }
+    .try_into()
+    .map_err(|_| sign_up_failed("Failed to hash the password"))?;
+    user.rank = registration.default_rank;
+    let rank = user.rank.to_string();
+    // The invite is only used up along with an account that was made
+    let mut tx = pool.begin().await.map_err(sign_up_failed)?;
+    if registration.policy == RegistrationPolicy::InviteOnly {
+        let code = form.invite.unwrap_or_default();
+        if !use_invite(&mut tx, &code).await.map_err(sign_up_failed)? {
+            return Err((StatusCode::FORBIDDEN, "The invite is invalid or used up"));
+        }
+    }
+    let inserted = sqlx::query!(
+        "INSERT INTO users (name, username, profile_pic, salt, sh_pass, email, rank) VALUES(?, ?, ?, ?, ?, ?, ?)",
+        user.name,
+        user.username,
+        user.profile_pic,
+        user.salt,
+        user.sh_pass,
+        user.email,
+        rank
+    )
+    .execute(&mut *tx)
+    .await;
+    match inserted {
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            return Err((StatusCode::CONFLICT, "The username or email is taken"))
+        }
+        Err(e) => return Err(sign_up_failed(e)),
+        Ok(_) => tx.commit().await.map_err(sign_up_failed)?,
+    }
+    log::info!("{} signed up", user.username);
+    if let Err(e) = send_verification_mail(&state, &(&user).into()).await {
+        log::error!("Failed to mail {} a verification: {:#}", user.username, e);
+    }
+    Ok(Json(UserInfo {
+        name: user.name,
+        username: user.username,
+        profile_pic: None,
+        email: user.email,
+        rank: user.rank,
+        email_verified: false,
+    }))
+}
@@ -1,7 +1,4 @@
-use base64::Engine;
-use once_cell::sync::Lazy;
-use sha3::Digest;
-use std::{fmt::Display, time::{Duration, SystemTime}};
+use std::{fmt::Display, str::FromStr};
 
 use axum::{
     async_trait,
@@ -9,35 +6,11 @@ use axum::{
     http::{request::Parts, StatusCode},
     Json,
 };
-use axum_extra::extract::cookie::Cookie;
-use base64::engine::general_purpose;
-use jsonwebtoken::{DecodingKey, EncodingKey, Validation};
 use serde::{Deserialize, Serialize};
-use sha3::Sha3_512;
-use sqlx::query_as;
 
 use crate::config::SiteConfig;
 
-pub struct Keys {
-    pub encoding: EncodingKey,
-    pub decoding: DecodingKey,
-}
-
-// Keys for encoding JWTs
-impl Keys {
-    fn new(secret: &[u8]) -> Keys {
-        Self {
-            encoding: EncodingKey::from_secret(secret),
-            decoding: DecodingKey::from_secret(secret),
-        }
-    }
-}
-
-pub static KEYS: Lazy<Keys> = Lazy::new(|| {
-    let secret = std::env::var("JWT_SECRET")
-        .expect("JWT_SECRET Environment Vaiable not set, it must be set.");
-    Keys::new(secret.as_bytes())
-});
+use super::session::Session;
 
 // User struct, maps to the database
 #[derive(Deserialize, Serialize, Clone, Debug, Default)]
@@ -49,165 +22,89 @@ pub struct User {
     pub sh_pass: Vec<u8>,
     pub email: String,
     pub rank: Rank,
+    /// Set for accounts that must choose a new password before signing in
+    pub reset_password: bool,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, Eq)]
+/// What a user may do on a site, see [`Rank::capabilities`].
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub enum Rank {
     #[default]
-    User,
-    Admin,
+    Subscriber,
+    Contributor,
+    Author,
+    Editor,
+    Administrator,
+}
+
+impl Rank {
+    /// Every rank, from the one that may do the least to the one that may do everything.
+    pub const ALL: [Rank; 5] = [
+        Self::Subscriber,
+        Self::Contributor,
+        Self::Author,
+        Self::Editor,
+        Self::Administrator,
+    ];
 }
 
 impl Display for Rank {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(match *self {
-            Self::User => "User",
-            Self::Admin => "Admin",
+            Self::Subscriber => "Subscriber",
+            Self::Contributor => "Contributor",
+            Self::Author => "Author",
+            Self::Editor => "Editor",
+            Self::Administrator => "Administrator",
         })
     }
 }
 
+/// Ranks as they are stored, including the `User` and `Admin` of older versions. Anything
+/// else falls back to `Subscriber`, which may do the least.
 impl From<String> for Rank {
     fn from(value: String) -> Self {
-        if value.eq("Admin") {
-            Self::Admin
-        } else {
-            Self::User
+        match value.as_str() {
+            "User" => Self::Author,
+            "Admin" => Self::Administrator,
+            _ => value.parse().unwrap_or_default(),
         }
     }
 }
 
+/// Unlike [`From<String>`], which falls back to `Subscriber`, this fails on anything but a
+/// rank.
+impl FromStr for Rank {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|rank| rank.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let ranks: Vec<String> = Self::ALL.iter().map(Rank::to_string).collect();
+                format!("{s} is not a rank, it is one of {}", ranks.join(", "))
+            })
+    }
+}
+
 // implementing the "Auto Auth thing", slap a User in the arguments to a handler
 // and BAM, you get Auth
 #[async_trait]
-impl<'a> FromRequestParts<SiteConfig> for User {
+impl FromRequestParts<SiteConfig> for User {
     type Rejection = (StatusCode, &'static str);
 
     async fn from_request_parts(
         parts: &mut Parts,
         state: &SiteConfig,
     ) -> Result<Self, Self::Rejection> {
-        // Grab the Cookies, if not found, send back error
-        match parts.headers.get("Cookie") {
-            Some(cookie_string) => match Cookie::split_parse(match cookie_string.to_str() {
-                Ok(s) => s,
-                Err(_) => return Err((StatusCode::BAD_REQUEST, "Invalid Cookies")),
-            })
-            .map(|cookie| match cookie {
-                Ok(c) => Some(c),
-                Err(_) => None,
-            })
-            .filter(|c| c.is_some())
-            .map(|c| c.unwrap())
-            // Check if any of them is a "jwt-token"
-            .filter(|c| c.name() == "jwt-token")
-            .next()
-            {
-                // Check id the jwt-token cookie is actually a jsonwebtoken,
-                // and decode it
-                Some(c) => match jsonwebtoken::decode::<UserToken>(
-                    c.value(),
-                    &KEYS.decoding,
-                    &Validation::new(jsonwebtoken::Algorithm::HS256),
-                ) {
-                    // If it is a valid JWT, grab the User struct from the database
-                    // and match its creds with the token, then, if they match,
-                    // return the User struct
-                    Ok(token) => {
-                        // The database query
-                        let user = match query_as!(
-                            User,
-                            "select * from users where username = ?",
-                            token.claims.username
-                        )
-                        .fetch_one(&state.db_pool.clone().unwrap())
-                        .await
-                        {
-                            // If found return the user
-                            Ok(user) => user,
-                            // If not, Yell through HTTP
-                            Err(e) => {
-                                log::error!("{}", e);
-                                return Err((
-                                    StatusCode::UNAUTHORIZED,
-                                    "Incorrent Username or Password",
-                                ));
-                            }
-                        };
-
-                        // Hash the password to prepare for matching with the password in the db
-                        let mut hasher = Sha3_512::new();
-
-                        hasher.update(user.sh_pass.clone());
-
-                        // read hash digest
-                        let result: Vec<u8> = hasher.finalize()[..].into();
-
-                        // Check the new password with the password in the db
-                        // If both are the same, return the user
-                        let resp_hash = match general_purpose::STANDARD.decode(token.claims.sh_pass)
-                        {
-                            Ok(h) => h,
-                            Err(e) => {
-                                log::error!("{}", e);
-                                return Err((
-                                    StatusCode::BAD_REQUEST,
-                                    "Incorrect username or password",
-                                ));
-                            }
-                        };
-                        if result == resp_hash {
-                            return Ok(user);
-                        }
-                        // else, Yell through HTTP
-                        return Err((StatusCode::UNAUTHORIZED, "Incorrect username or password"));
-                    }
-                    Err(e) => {
-                        log::error!("At line {}, {}", line!(), e);
-                        return Err((StatusCode::BAD_REQUEST, "Could not parse the JWT"));
-                    }
-                },
-                None => {
-                    return Err((StatusCode::UNAUTHORIZED, "JWT Cookie not found"));
-                }
-            },
-            None => return Err((StatusCode::UNAUTHORIZED, "JWT Cookie not found")).into(),
-        };
-    }
-}
-
-#[derive(Deserialize, Serialize, Clone, Debug)]
-pub struct UserToken {
-    username: String,
-    sh_pass: String,
-    exp: u64,
-}
-
-impl From<User> for UserToken {
-    fn from(user: User) -> Self {
-        let mut hasher = Sha3_512::new();
-        hasher.update(user.sh_pass);
-        let shh_pass: Vec<u8> = hasher.finalize()[..].into();
-        let exp_time = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            + Duration::from_secs(60 * 60 * 24);
-
-        UserToken {
-            username: user.username,
-            sh_pass: general_purpose::STANDARD.encode(shh_pass),
-            exp: exp_time.as_secs(),
-        }
+        // The user of the session the request was made with
+        Session::from_request_parts(parts, state)
+            .await
+            .map(|session| session.user)
     }
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, Default)]
-struct Claims {
-    username: String,
-    sh_pass: Vec<u8>,
-    exp: usize,
-}
-
 #[derive(Serialize, Deserialize)]
 pub struct UserInfo {
     pub name: String,
@@ -215,6 +112,8 @@ pub struct UserInfo {
     pub profile_pic: Option<String>,
     pub email: String,
     pub rank: Rank,
+    /// Whether the user followed the link mailed to `email`
+    pub email_verified: bool,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -230,7 +129,7 @@ pub async fn get_user(
     let username = req.username;
     match sqlx::query_as!(
         UserInfo,
-        "SELECT name, username, profile_pic, email, rank FROM users WHERE username IS ?",
+        r#"SELECT name, username, profile_pic, email, rank, email_verified as "email_verified: bool" FROM users WHERE username IS ?"#,
         username
     )
     .fetch_one(&state.db_pool.unwrap())
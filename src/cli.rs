@@ -0,0 +1,474 @@
+//! The command line, serving the sites and looking after them. Every command can be run
+//! from scripts with flags, what is left out is only asked for on a terminal.
+
+use std::{
+    fs,
+    io::{self, BufRead, IsTerminal},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, Context};
+use chrono::DateTime;
+use clap::{Args as ClapArgs, Parser, Subcommand};
+use inquire::{Password, Text};
+use log::error;
+
+use crate::{
+    auth::{
+        admin::{create_privileged, delete_user, list_users, require_password_reset, set_rank},
+        invite::create_invite,
+        keys::SiteKeys,
+        password::set_password,
+        sign_up::UserSignUp,
+        user::Rank,
+    },
+    config::PeroxideConfig,
+    migrate::{migrate, rollback, status},
+    site::{check_site, create_site, init_site, open_site, DEFAULT_ADDRESS},
+    wordpress::WordpressSite,
+};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+pub struct Args {
+    /// The config listing the sites to serve
+    #[arg(short, long, default_value_t = String::from("Peroxide.toml"))]
+    pub config: String,
+    /// What to do, the sites are served without one
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Serves every site of the config
+    Serve,
+    /// Creates sites
+    Site {
+        #[command(subcommand)]
+        action: SiteAction,
+    },
+    /// Manages the accounts of a site
+    User {
+        #[command(subcommand)]
+        action: UserAction,
+    },
+    /// Imports a site from elsewhere
+    Import {
+        #[command(subcommand)]
+        source: ImportSource,
+    },
+    /// Shows, applies or rolls back the migrations of the database of a site
+    Migrate {
+        #[command(flatten)]
+        site: SiteArg,
+        #[command(subcommand)]
+        action: MigrateAction,
+    },
+    /// Looks for problems in the configs, templates and databases of the sites
+    Check {
+        /// Only checks this site instead of those of the config
+        #[arg(long)]
+        site: Option<String>,
+    },
+}
+
+#[derive(ClapArgs, Debug)]
+pub struct SiteArg {
+    /// Directory of the site, where its PeroxideSite.toml is
+    #[arg(long)]
+    pub site: String,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SiteAction {
+    /// Creates a site with a config, a page and a database, and adds it to the config
+    New {
+        path: String,
+        /// The address the site is served at
+        #[arg(long, default_value_t = String::from(DEFAULT_ADDRESS))]
+        domain: String,
+        #[arg(long, default_value_t = String::from("db.sqlite3"))]
+        db_filename: String,
+    },
+    /// Adds a key that signs the tokens of a site from now on, which is used once the site
+    /// is served again. Tokens signed by the older keys stay valid until they expire.
+    RotateKey {
+        path: String,
+        /// Drops the older keys instead, which signs everyone out
+        #[arg(long)]
+        retire_old: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum UserAction {
+    /// Adds an account, the password is read from stdin when it isn't a terminal
+    Add {
+        #[command(flatten)]
+        site: SiteArg,
+        username: Option<String>,
+        /// The name displayed to other people
+        #[arg(long)]
+        name: Option<String>,
+        #[arg(long)]
+        email: Option<String>,
+        // Parsed with FromStr, clap would pick From<String> which takes typos for Subscriber
+        #[arg(long, default_value_t = Rank::Author, value_parser = Rank::from_str)]
+        rank: Rank,
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// Lists the accounts
+    List {
+        #[command(flatten)]
+        site: SiteArg,
+    },
+    /// Deletes an account
+    Delete {
+        #[command(flatten)]
+        site: SiteArg,
+        username: String,
+        /// Gives the posts of the account to this user, accounts with posts can't be
+        /// deleted otherwise
+        #[arg(long)]
+        reassign: Option<String>,
+    },
+    /// Changes the rank of an account
+    SetRank {
+        #[command(flatten)]
+        site: SiteArg,
+        username: String,
+        #[arg(value_parser = Rank::from_str)]
+        rank: Rank,
+    },
+    /// Sets a new password, which is read from stdin when it isn't a terminal
+    ResetPassword {
+        #[command(flatten)]
+        site: SiteArg,
+        username: String,
+        #[arg(long)]
+        password: Option<String>,
+        /// Makes the user choose a new password on their own instead
+        #[arg(long, conflicts_with = "password")]
+        require: bool,
+    },
+    /// Makes an invite code for signing up on sites that are invite only
+    Invite {
+        #[command(flatten)]
+        site: SiteArg,
+        /// How many accounts can sign up with the code
+        #[arg(long, default_value_t = 1)]
+        uses: u32,
+        /// How many days the code works, it doesn't expire without
+        #[arg(long)]
+        days: Option<u32>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ImportSource {
+    /// Imports a WordPress site, through the wp-json api of a live site or from an export
+    Wordpress {
+        /// Url of the live site
+        #[arg(required_unless_present = "export", conflicts_with = "export")]
+        url: Option<String>,
+        /// Imports from a WordPress export file instead
+        #[arg(short = 'x', long)]
+        export: Option<String>,
+        /// Where the imported site is created
+        #[arg(short, long, default_value_t = String::from("./"))]
+        path: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MigrateAction {
+    /// Lists the migrations and when they were applied
+    Status,
+    /// Applies the pending migrations
+    Run {
+        /// Stops after the migration with this version
+        #[arg(long)]
+        to: Option<i64>,
+    },
+    /// Rolls back the newest applied migration
+    Rollback {
+        /// Rolls back every migration after this version instead
+        #[arg(long)]
+        to: Option<i64>,
+    },
+}
+
+/// The value of a flag, asked for on a terminal when it was left out.
+fn flag_or_prompt(value: Option<String>, flag: &str, prompt: &str) -> anyhow::Result<String> {
+    match value {
+        Some(value) => Ok(value),
+        None if io::stdin().is_terminal() => Ok(Text::new(prompt).prompt()?),
+        None => bail!("{flag} is required when not run from a terminal"),
+    }
+}
+
+/// A password from its flag, a prompt on a terminal or else the first line of stdin, so it
+/// doesn't have to show up in the list of processes.
+fn password_or_prompt(value: Option<String>) -> anyhow::Result<String> {
+    if let Some(value) = value {
+        return Ok(value);
+    }
+    if io::stdin().is_terminal() {
+        return Ok(Password::new("Enter the password: ").prompt()?);
+    }
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    let password = line.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        bail!("A password is required, through --password or stdin");
+    }
+    Ok(password.to_string())
+}
+
+/// Runs a command, failing with what went wrong.
+pub async fn run(args: Args) -> anyhow::Result<()> {
+    match args.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(&args.config).await,
+        Command::Site {
+            action:
+                SiteAction::New {
+                    path,
+                    domain,
+                    db_filename,
+                },
+        } => new_site(&args.config, &path, domain, db_filename).await,
+        Command::Site {
+            action: SiteAction::RotateKey { path, retire_old },
+        } => rotate_key(&path, retire_old),
+        Command::User { action } => user(action).await,
+        Command::Import {
+            source: ImportSource::Wordpress { url, export, path },
+        } => import_wordpress(url, export, path).await,
+        Command::Migrate { site, action } => run_migrate(&site.site, action).await,
+        Command::Check { site } => check(&args.config, site).await,
+    }
+}
+
+fn read_config(path: &str) -> anyhow::Result<PeroxideConfig> {
+    let config =
+        fs::read_to_string(path).with_context(|| format!("Failed to read the config {path}"))?;
+    toml::from_str(&config).with_context(|| format!("Failed to parse the config {path}"))
+}
+
+async fn serve(config: &str) -> anyhow::Result<()> {
+    let config = read_config(config)?;
+    let mut work_group = tokio::task::JoinSet::new();
+    for dir in config.directories.into_iter() {
+        work_group.spawn(init_site(dir));
+    }
+    while let Some(result) = work_group.join_next().await {
+        error!("{:?}", result);
+    }
+    Ok(())
+}
+
+/// Creates a site and adds it to the sites that are served, if there is a config yet.
+async fn new_site(
+    config_path: &str,
+    path: &str,
+    domain: String,
+    db_filename: String,
+) -> anyhow::Result<()> {
+    create_site(path, domain, db_filename).await?;
+    log::info!("Created the site {path}");
+    if fs::metadata(config_path).is_err() {
+        log::info!("Add {path} to the directories of a config to serve it");
+        return Ok(());
+    }
+    let mut config = read_config(config_path)?;
+    if !config.directories.iter().any(|dir| dir == path) {
+        config.directories.push(path.to_string());
+        fs::write(config_path, toml::to_string(&config)?)
+            .with_context(|| format!("Failed to write the config {config_path}"))?;
+        log::info!("Added {path} to {config_path}");
+    }
+    Ok(())
+}
+
+fn rotate_key(path: &str, retire_old: bool) -> anyhow::Result<()> {
+    if fs::metadata(format!("{path}/PeroxideSite.toml")).is_err() {
+        bail!("There is no site at {path}");
+    }
+    let mut keys = SiteKeys::load_or_generate(path)?;
+    let kid = keys.rotate().kid.clone();
+    if retire_old {
+        keys.retire_old();
+    }
+    keys.save(path)
+        .with_context(|| format!("Failed to write the keys of {path}"))?;
+    log::info!("Tokens of {path} are signed with the key {kid} once it is served again");
+    Ok(())
+}
+
+async fn user(action: UserAction) -> anyhow::Result<()> {
+    match action {
+        UserAction::Add {
+            site,
+            username,
+            name,
+            email,
+            rank,
+            password,
+        } => {
+            let config = open_site(&site.site).await?;
+            let username = flag_or_prompt(username, "The username", "Enter the username: ")?;
+            let name = flag_or_prompt(name, "--name", "Enter the display name: ")?;
+            let email = flag_or_prompt(email, "--email", "Enter the mail: ")?;
+            let pass = password_or_prompt(password)?;
+            let user = UserSignUp {
+                name,
+                username: username.clone(),
+                pass,
+                email,
+            };
+            create_privileged(user, rank, &config)
+                .await
+                .map_err(|e| anyhow!("Failed to add {username}: {e}"))?;
+            log::info!("Added the user {username}");
+        }
+        UserAction::List { site } => {
+            let config = open_site(&site.site).await?;
+            for user in list_users(config.db_pool.as_ref().unwrap()).await? {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    user.username, user.rank, user.name, user.email
+                );
+            }
+        }
+        UserAction::Delete {
+            site,
+            username,
+            reassign,
+        } => {
+            let config = open_site(&site.site).await?;
+            delete_user(
+                config.db_pool.as_ref().unwrap(),
+                &username,
+                reassign.as_deref(),
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to delete {username}: {e}"))?;
+            log::info!("Deleted the user {username}");
+        }
+        UserAction::SetRank {
+            site,
+            username,
+            rank,
+        } => {
+            let config = open_site(&site.site).await?;
+            if !set_rank(config.db_pool.as_ref().unwrap(), &username, rank).await? {
+                bail!("There is no user {username}");
+            }
+            log::info!("Changed the rank of {username}");
+        }
+        UserAction::ResetPassword {
+            site,
+            username,
+            password,
+            require,
+        } => {
+            let config = open_site(&site.site).await?;
+            let pool = config.db_pool.as_ref().unwrap();
+            let found = match require {
+                true => require_password_reset(pool, &username).await?,
+                false => set_password(pool, &username, &password_or_prompt(password)?).await?,
+            };
+            if !found {
+                bail!("There is no user {username}");
+            }
+            log::info!("Reset the password of {username}");
+        }
+        UserAction::Invite { site, uses, days } => {
+            let config = open_site(&site.site).await?;
+            let invite = create_invite(config.db_pool.as_ref().unwrap(), None, uses, days).await?;
+            println!("{}", invite.code);
+        }
+    }
+    Ok(())
+}
+
+async fn import_wordpress(
+    url: Option<String>,
+    export: Option<String>,
+    path: String,
+) -> anyhow::Result<()> {
+    let wp_site = match (url, export) {
+        (Some(url), _) => WordpressSite::from_site_url(url)
+            .await
+            .map_err(|e| anyhow!("Failed to fetch the WordPress site, Error: {}", e))?,
+        (None, Some(file)) => WordpressSite::from_wxr_file(file)
+            .map_err(|e| anyhow!("Failed to read the WordPress export, Error: {}", e))?,
+        (None, None) => bail!("Either the url of a site or --export is required"),
+    };
+    let config = wp_site
+        .save(path)
+        .await
+        .map_err(|e| anyhow!("Failed to save the imported site, Error: {:?}", e))?;
+    log::info!("Imported the site into {}", config.site_path);
+    Ok(())
+}
+
+async fn run_migrate(site: &str, action: MigrateAction) -> anyhow::Result<()> {
+    let config = open_site(site).await?;
+    let pool = config.db_pool.unwrap();
+    let status = status(&pool)
+        .await
+        .with_context(|| format!("Failed to read the migrations of {site}"))?;
+    let (done, result) = match action {
+        MigrateAction::Status => {
+            for migration in status.iter() {
+                let applied = migration
+                    .applied
+                    .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0));
+                let applied = match applied {
+                    Some(date) => format!("applied {}", date.format("%Y-%m-%d %H:%M:%S")),
+                    None => "pending".to_string(),
+                };
+                println!("{:>4} {:<24} {applied}", migration.version, migration.name);
+            }
+            return Ok(());
+        }
+        MigrateAction::Run { to } => ("Applied", migrate(&pool, to).await),
+        MigrateAction::Rollback { to } => {
+            let newest = status.iter().rev().find(|m| m.applied.is_some());
+            let target = to.unwrap_or(newest.map_or(0, |m| m.version - 1));
+            ("Rolled back", rollback(&pool, target).await)
+        }
+    };
+    let versions = result.with_context(|| format!("Failed to migrate the database of {site}"))?;
+    match versions.is_empty() {
+        true => log::info!("No migrations to run for {site}"),
+        false => log::info!("{done} the migrations {:?} of {site}", versions),
+    }
+    Ok(())
+}
+
+/// Prints the problems of every site, failing if there are any.
+async fn check(config: &str, site: Option<String>) -> anyhow::Result<()> {
+    let sites = match site {
+        Some(site) => vec![site],
+        None => read_config(config)?.directories,
+    };
+    let mut broken = 0;
+    for site in sites.iter() {
+        let problems = check_site(site).await;
+        match problems.is_empty() {
+            true => println!("{site}: ok"),
+            false => broken += 1,
+        }
+        for problem in problems.iter() {
+            println!("{site}: {problem}");
+        }
+    }
+    if broken > 0 {
+        bail!("{broken} of {} sites have problems", sites.len());
+    }
+    Ok(())
+}
@@ -0,0 +1,203 @@
+use std::{collections::HashMap, fmt::Display};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use axum_typed_multipart::{TryFromMultipart, TypedMultipart};
+use log::error;
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, query, query_as, SqlitePool};
+
+use crate::{
+    auth::{capability::Capability, user::User},
+    config::SiteConfig,
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum CommentStatus {
+    Pending,
+    Approved,
+    Rejected,
+    Spam,
+}
+
+impl Display for CommentStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match *self {
+            Self::Pending => "Pending",
+            Self::Approved => "Approved",
+            Self::Rejected => "Rejected",
+            Self::Spam => "Spam",
+        })
+    }
+}
+
+impl From<String> for CommentStatus {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "Approved" => Self::Approved,
+            "Rejected" => Self::Rejected,
+            "Spam" => Self::Spam,
+            _ => Self::Pending,
+        }
+    }
+}
+
+#[derive(Serialize, FromRow, Deserialize, Clone, Debug)]
+pub struct Comment {
+    pub id: i64,
+    pub post: i64,
+    pub parent: Option<i64>,
+    pub author: Option<String>,
+    pub author_name: String,
+    pub author_url: String,
+    pub content: String,
+    pub date: i64,
+    pub status: CommentStatus,
+}
+
+/// A comment along with how deep in its thread it is, replies directly follow their parent.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ThreadedComment {
+    #[serde(flatten)]
+    pub comment: Comment,
+    pub depth: usize,
+}
+
+/// Orders the comments so that every reply follows its parent, siblings keep their
+/// relative order. Comments replying to a comment that is not in the list are treated
+/// as top level comments.
+pub fn thread(comments: Vec<Comment>) -> Vec<ThreadedComment> {
+    let ids: Vec<i64> = comments.iter().map(|c| c.id).collect();
+    let mut replies: HashMap<Option<i64>, Vec<Comment>> = HashMap::new();
+    for comment in comments {
+        let parent = comment.parent.filter(|parent| ids.contains(parent));
+        replies.entry(parent).or_default().push(comment);
+    }
+
+    let mut threaded = Vec::with_capacity(ids.len());
+    let mut stack: Vec<(Comment, usize)> = replies
+        .remove(&None)
+        .unwrap_or_default()
+        .into_iter()
+        .rev()
+        .map(|c| (c, 0))
+        .collect();
+    while let Some((comment, depth)) = stack.pop() {
+        if let Some(children) = replies.remove(&Some(comment.id)) {
+            stack.extend(children.into_iter().rev().map(|c| (c, depth + 1)));
+        }
+        threaded.push(ThreadedComment { comment, depth });
+    }
+    threaded
+}
+
+/// Fetches the approved comments of a published post with their content sanitized, oldest first.
+pub async fn approved_comments(pool: &SqlitePool, post: i64) -> Result<Vec<Comment>, sqlx::Error> {
+    let comments = query_as!(
+        Comment,
+        "SELECT id, post, parent, author, author_name, author_url, content, date, status FROM comments
+        WHERE post = ? AND status = 'Approved'
+        AND EXISTS(SELECT 1 FROM posts WHERE posts.id = comments.post AND posts.status = 'Published')
+        ORDER BY date, id",
+        post
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(comments
+        .into_iter()
+        .map(|comment| Comment {
+            content: ammonia::clean(comment.content.as_str()),
+            ..comment
+        })
+        .collect())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CommentListRequest {
+    post: i64,
+}
+
+pub async fn list_comments(
+    Query(req): Query<CommentListRequest>,
+    State(config): State<SiteConfig>,
+) -> Result<Json<Vec<ThreadedComment>>, StatusCode> {
+    match approved_comments(&config.db_pool.unwrap(), req.post).await {
+        Ok(comments) => Ok(Json(thread(comments))),
+        Err(e) => {
+            error!("Error while fetching comments: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, TryFromMultipart)]
+pub struct CommentCreateRequest {
+    post: i64,
+    parent: Option<i64>,
+    content: String,
+}
+
+/// Comments by moderators are approved right away, everyone else's wait for moderation.
+pub async fn create_comment(
+    State(config): State<SiteConfig>,
+    user: User,
+    TypedMultipart(form): TypedMultipart<CommentCreateRequest>,
+) -> StatusCode {
+    let status = match user.rank.can(Capability::ModerateComments) {
+        true => CommentStatus::Approved,
+        false => CommentStatus::Pending,
+    }
+    .to_string();
+    let content = ammonia::clean(form.content.as_str());
+    // Only published posts take comments, and the parent has to be a comment on the same post
+    match query!(
+        "INSERT INTO comments(post, parent, author, author_name, content, status)
+        SELECT ?1, ?2, ?3, ?4, ?5, ?6
+        WHERE EXISTS(SELECT 1 FROM posts WHERE id = ?1 AND status = 'Published')
+        AND (?2 IS NULL OR EXISTS(SELECT 1 FROM comments WHERE id = ?2 AND post = ?1))",
+        form.post,
+        form.parent,
+        user.username,
+        user.name,
+        content,
+        status
+    )
+    .execute(&config.db_pool.unwrap())
+    .await
+    {
+        Ok(r) if r.rows_affected() == 0 => StatusCode::BAD_REQUEST,
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            error!("Error while inserting a comment: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CommentModerateRequest {
+    id: i64,
+    status: CommentStatus,
+}
+
+pub async fn moderate_comment(
+    _admin: User,
+    State(config): State<SiteConfig>,
+    Query(req): Query<CommentModerateRequest>,
+) -> StatusCode {
+    let status = req.status.to_string();
+    match query!("UPDATE comments SET status = ? WHERE id = ?", status, req.id)
+        .execute(&config.db_pool.unwrap())
+        .await
+    {
+        Ok(r) if r.rows_affected() == 0 => StatusCode::NOT_FOUND,
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            error!("Error while moderating a comment: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
@@ -10,6 +10,13 @@ use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
 use tinytemplate_async::TinyTemplate;
 
+use crate::{
+    auth::{keys::SiteKeys, user::Rank},
+    mail::SiteMailer,
+    post::{default_list_template, Post},
+    search::default_search_template,
+};
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct PeroxideConfig {
     pub directories: Vec<String>,
@@ -30,6 +37,31 @@ pub struct SiteConfig {
     pub templates: Arc<RwLock<TinyTemplate>>,
     #[serde(default = "create_user_default")]
     pub create_user: bool,
+    #[serde(default)]
+    pub sanitize: SanitizeConfig,
+    #[serde(default)]
+    pub feed: FeedConfig,
+    #[serde(default)]
+    pub robots: RobotsConfig,
+    #[serde(default)]
+    pub mail: MailConfig,
+    #[serde(default)]
+    pub registration: RegistrationConfig,
+    /// The templates that lists of posts, like search results, may be rendered with when
+    /// a request names one. Anyone can list posts, so the others stay out of reach.
+    #[serde(default = "list_templates_default")]
+    pub list_templates: Vec<String>,
+    /// The header a reverse proxy in front of the site puts the address of clients in, like
+    /// `X-Forwarded-For`. Only set it when every request goes through such a proxy, as
+    /// clients can send the header themselves.
+    #[serde(default)]
+    pub forwarded_for: Option<String>,
+    /// What the tokens of the site are signed with, kept apart from the config
+    #[serde(skip)]
+    pub keys: SiteKeys,
+    /// Sends the mails of the site, made from `mail` when the site is opened
+    #[serde(skip)]
+    pub mailer: SiteMailer,
 }
 
 impl SiteConfig {
@@ -37,17 +69,256 @@ impl SiteConfig {
         let new_config = toml::to_string(&self).expect("Decoding the SiteConfig struct");
         fs::write(format!("{}/PeroxideSite.toml", self.site_path), new_config)
     }
+
+    /// The url the site is reached at, for links that are followed from elsewhere.
+    pub fn base_url(&self) -> String {
+        match self.domain.contains("://") {
+            true => self.domain.trim_end_matches('/').to_string(),
+            false => format!("https://{}", self.domain.trim_end_matches('/')),
+        }
+    }
+
+    /// The permalink pattern posts are linked to with, that of the first route (in
+    /// alphabetical order) that renders posts.
+    pub fn permalink_pattern(&self) -> Option<String> {
+        self.routes
+            .iter()
+            .filter_map(|(route, path)| path.permalink_pattern(route))
+            .min()
+    }
+
+    /// Absolute url of a post, sites without a route that renders posts only have the api
+    /// to link to.
+    pub fn post_url(&self, post: &Post) -> String {
+        match self.permalink_pattern() {
+            Some(pattern) => format!("{}{}", self.base_url(), post.permalink(&pattern)),
+            None => format!("{}/api/post?slug={}", self.base_url(), post.slug),
+        }
+    }
 }
 
 fn create_user_default() -> bool {
     false
 }
 
+/// Only the templates the post listing and the search render with by default.
+pub fn list_templates_default() -> Vec<String> {
+    vec![default_list_template(), default_search_template()]
+}
+
+/// Html the sanitizer lets through on top of its defaults, like embedded videos or the
+/// classes a theme styles. Tags whose content is always dropped (`script`, `style`),
+/// event handler attributes, `rel` and `javascript:` urls are never allowed.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct SanitizeConfig {
+    pub tags: Vec<String>,
+    /// Attributes allowed on every tag
+    pub generic_attributes: Vec<String>,
+    /// Attributes allowed per tag
+    pub tag_attributes: HashMap<String, Vec<String>>,
+    /// Schemes allowed in urls besides the usual ones, like `ipfs`
+    pub url_schemes: Vec<String>,
+}
+
+impl SanitizeConfig {
+    /// What WordPress lets authors publish, so imported posts keep their embeds and blocks.
+    pub fn wordpress() -> Self {
+        let attributes = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        SanitizeConfig {
+            tags: attributes(&["iframe", "figure", "figcaption", "video", "audio", "source"]),
+            generic_attributes: attributes(&["class"]),
+            tag_attributes: HashMap::from([
+                (
+                    "iframe".to_string(),
+                    attributes(&[
+                        "src",
+                        "width",
+                        "height",
+                        "title",
+                        "allow",
+                        "allowfullscreen",
+                        "frameborder",
+                    ]),
+                ),
+                (
+                    "video".to_string(),
+                    attributes(&[
+                        "src", "controls", "poster", "width", "height", "loop", "muted",
+                    ]),
+                ),
+                (
+                    "audio".to_string(),
+                    attributes(&["src", "controls", "loop"]),
+                ),
+                ("source".to_string(), attributes(&["src", "type"])),
+            ]),
+            url_schemes: Vec::new(),
+        }
+    }
+}
+
+/// What goes into the rss and atom feeds.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct FeedConfig {
+    /// The domain when left empty
+    pub title: String,
+    pub description: String,
+    /// How many of the newest posts are in a feed
+    pub items: u32,
+    /// Whether entries have the whole post or only an excerpt
+    pub full_text: bool,
+}
+
+impl Default for FeedConfig {
+    fn default() -> Self {
+        FeedConfig {
+            title: String::new(),
+            description: String::new(),
+            items: 20,
+            full_text: false,
+        }
+    }
+}
+
+/// The rules of robots.txt, which apply to every crawler. Routes and posts that are disallowed
+/// are left out of the sitemap too.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct RobotsConfig {
+    pub allow: Vec<String>,
+    pub disallow: Vec<String>,
+}
+
+impl Default for RobotsConfig {
+    fn default() -> Self {
+        RobotsConfig {
+            allow: Vec::new(),
+            disallow: vec!["/admin/".to_string(), "/api/".to_string()],
+        }
+    }
+}
+
+/// Who may sign up on their own through the api, rather than being added by an administrator.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct RegistrationConfig {
+    pub policy: RegistrationPolicy,
+    /// The rank of the accounts that sign up
+    pub default_rank: Rank,
+    /// Domains of the emails that may sign up under `domain_allowlist`, like `example.com`
+    pub allowed_domains: Vec<String>,
+    /// How many times an address may try to sign up in an hour
+    pub attempts_per_hour: u32,
+}
+
+impl Default for RegistrationConfig {
+    fn default() -> Self {
+        RegistrationConfig {
+            policy: RegistrationPolicy::Closed,
+            default_rank: Rank::Subscriber,
+            allowed_domains: Vec::new(),
+            attempts_per_hour: 5,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RegistrationPolicy {
+    #[default]
+    Closed,
+    Open,
+    /// Only with an invite code from an administrator
+    InviteOnly,
+    /// Only with an email of one of `allowed_domains`
+    DomainAllowlist,
+}
+
+/// How the mails of a site, like password resets, are sent.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct MailConfig {
+    /// Who mails are from, like `Peroxide <noreply@example.com>`
+    pub from: String,
+    /// The page of the site with the form that takes the token of a password reset, the
+    /// links in the mails go there
+    pub reset_page: String,
+    /// How many password resets an address, or an account, may ask for in an hour
+    pub resets_per_hour: u32,
+    pub transport: MailTransport,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        MailConfig {
+            from: "Peroxide <noreply@localhost>".to_string(),
+            reset_page: "/reset_password".to_string(),
+            resets_per_hour: 5,
+            transport: MailTransport::Stdout,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MailTransport {
+    /// Prints the mails instead of sending them, for local development
+    Stdout,
+    /// Appends the mails to a file, relative to the site
+    File { path: String },
+    Smtp(SmtpConfig),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SmtpConfig {
+    pub host: String,
+    /// The default port of `security` when left out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    /// Tls from the start, on port 465
+    #[default]
+    Tls,
+    /// Upgrading a plain connection with STARTTLS, on port 587
+    StartTls,
+    /// Nothing at all, only for relays on the same machine
+    None,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct PagePath {
     pub path: String,
     #[serde(default = "default_template")]
     pub template: Option<String>,
+    /// Where the posts rendered with `template` are served, like `/blog/:year/:month/:slug`.
+    /// Posts are found by their `:slug` or `:id`, `:year`, `:month` and `:day` have to match
+    /// when they were posted. `<route>/:slug` when left out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permalink: Option<String>,
+}
+
+impl PagePath {
+    /// The permalink pattern of the posts under `route`, if it renders posts at all.
+    pub fn permalink_pattern(&self, route: &str) -> Option<String> {
+        self.template.as_ref()?;
+        Some(
+            self.permalink
+                .clone()
+                .unwrap_or_else(|| format!("{}/:slug", route.trim_end_matches('/'))),
+        )
+    }
 }
 
 fn default_template() -> Option<String> {
@@ -0,0 +1,223 @@
+//! Rss and atom feeds of the published posts.
+
+use std::fmt::Write;
+
+use axum::{
+    extract::{OriginalUri, Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use chrono::{DateTime, Utc};
+use log::error;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::{
+    config::SiteConfig,
+    post::{query_posts, Post},
+    render::excerpt,
+};
+
+/// How many words excerpts have, like in WordPress.
+const EXCERPT_WORDS: usize = 55;
+
+/// Narrows a feed down to the posts with a tag or of an author, by their slug and username.
+#[derive(Serialize, Deserialize, Default)]
+pub struct FeedRequest {
+    tag: Option<String>,
+    author: Option<String>,
+}
+
+/// The newest published posts that belong in the feed.
+async fn feed_posts(
+    pool: &SqlitePool,
+    req: &FeedRequest,
+    items: u32,
+) -> Result<Vec<Post>, sqlx::Error> {
+    query_posts!(
+        r#"WHERE status = 'Published'
+        AND (?1 IS NULL OR id IN (
+            SELECT post_tags.post FROM post_tags JOIN tags ON tags.id = post_tags.tag WHERE tags.slug = ?1
+        ))
+        AND (?2 IS NULL OR owner = ?2)
+        ORDER BY date DESC LIMIT ?3"#,
+        req.tag,
+        req.author,
+        items
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Escapes text for xml, in elements as well as in attributes.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn date(timestamp: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(timestamp, 0).unwrap_or_default()
+}
+
+/// What both kinds of feeds are made of.
+struct Feed {
+    title: String,
+    description: String,
+    site: String,
+    /// Where the feed itself is
+    url: String,
+    posts: Vec<Post>,
+}
+
+impl Feed {
+    async fn new(
+        config: &SiteConfig,
+        uri: &OriginalUri,
+        req: &FeedRequest,
+    ) -> Result<Feed, StatusCode> {
+        let posts = feed_posts(config.db_pool.as_ref().unwrap(), req, config.feed.items)
+            .await
+            .map_err(|e| {
+                error!("Error while fetching the posts of a feed: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        let title = match config.feed.title.is_empty() {
+            true => config.domain.clone(),
+            false => config.feed.title.clone(),
+        };
+        Ok(Feed {
+            title,
+            description: config.feed.description.clone(),
+            site: config.base_url(),
+            url: format!("{}{}", config.base_url(), uri.0),
+            posts: posts
+                .into_iter()
+                .map(|post| post.sanitized(&config.sanitize))
+                .collect(),
+        })
+    }
+
+    /// When the newest post was published, or now without any posts.
+    fn updated(&self) -> DateTime<Utc> {
+        self.posts
+            .first()
+            .map(|post| date(post.date))
+            .unwrap_or_else(Utc::now)
+    }
+}
+
+/// Rss 2.0 feed of the newest published posts.
+pub async fn rss_feed(
+    State(config): State<SiteConfig>,
+    uri: OriginalUri,
+    Query(req): Query<FeedRequest>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let full_text = config.feed.full_text;
+    let feed = Feed::new(&config, &uri, &req).await?;
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\" xmlns:content=\"http://purl.org/rss/1.0/modules/content/\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n<channel>\n",
+    );
+    let _ = write!(
+        xml,
+        "<title>{}</title>\n<link>{}</link>\n<description>{}</description>\n<atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n<lastBuildDate>{}</lastBuildDate>\n",
+        escape(&feed.title),
+        escape(&feed.site),
+        escape(&feed.description),
+        escape(&feed.url),
+        feed.updated().to_rfc2822()
+    );
+    for post in feed.posts.iter() {
+        let link = escape(&config.post_url(post));
+        let _ = write!(
+            xml,
+            "<item>\n<title>{}</title>\n<link>{link}</link>\n<guid isPermaLink=\"true\">{link}</guid>\n<dc:creator>{}</dc:creator>\n<pubDate>{}</pubDate>\n",
+            escape(&post.name),
+            escape(&post.owner),
+            date(post.date).to_rfc2822()
+        );
+        for tag in post.tags.data.iter() {
+            let _ = writeln!(xml, "<category>{}</category>", escape(tag));
+        }
+        let _ = writeln!(
+            xml,
+            "<description>{}</description>",
+            escape(&excerpt(&post.rendered, EXCERPT_WORDS))
+        );
+        if full_text {
+            let _ = writeln!(
+                xml,
+                "<content:encoded>{}</content:encoded>",
+                escape(&post.rendered)
+            );
+        }
+        xml.push_str("</item>\n");
+    }
+    xml.push_str("</channel>\n</rss>\n");
+    Ok((
+        [(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")],
+        xml,
+    ))
+}
+
+/// Atom feed of the newest published posts.
+pub async fn atom_feed(
+    State(config): State<SiteConfig>,
+    uri: OriginalUri,
+    Query(req): Query<FeedRequest>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let full_text = config.feed.full_text;
+    let feed = Feed::new(&config, &uri, &req).await?;
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n",
+    );
+    let _ = write!(
+        xml,
+        "<title>{}</title>\n<subtitle>{}</subtitle>\n<id>{url}</id>\n<link href=\"{url}\" rel=\"self\"/>\n<link href=\"{}\"/>\n<updated>{}</updated>\n",
+        escape(&feed.title),
+        escape(&feed.description),
+        escape(&feed.site),
+        feed.updated().to_rfc3339(),
+        url = escape(&feed.url),
+    );
+    for post in feed.posts.iter() {
+        let link = escape(&config.post_url(post));
+        let _ = write!(
+            xml,
+            "<entry>\n<title>{}</title>\n<id>{link}</id>\n<link href=\"{link}\"/>\n<author><name>{}</name></author>\n<published>{date}</published>\n<updated>{date}</updated>\n",
+            escape(&post.name),
+            escape(&post.owner),
+            date = date(post.date).to_rfc3339()
+        );
+        for tag in post.tags.data.iter() {
+            let _ = writeln!(xml, "<category term=\"{}\"/>", escape(tag));
+        }
+        let _ = writeln!(
+            xml,
+            "<summary type=\"html\">{}</summary>",
+            escape(&excerpt(&post.rendered, EXCERPT_WORDS))
+        );
+        if full_text {
+            let _ = writeln!(
+                xml,
+                "<content type=\"html\">{}</content>",
+                escape(&post.rendered)
+            );
+        }
+        xml.push_str("</entry>\n");
+    }
+    xml.push_str("</feed>\n");
+    Ok((
+        [(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")],
+        xml,
+    ))
+}
@@ -1,7 +1,15 @@
-#![feature(exact_size_is_empty)]
-#![feature(iter_next_chunk)]
 pub mod auth;
+pub mod cli;
+pub mod comment;
 pub mod config;
+pub mod feed;
+pub mod mail;
+pub mod migrate;
 pub mod post;
+pub mod render;
+pub mod revision;
+pub mod search;
 pub mod site;
+pub mod sitemap;
+pub mod tag;
 pub mod wordpress;
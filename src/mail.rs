@@ -0,0 +1,221 @@
+//! Mails to users, rendered from templates and sent with a [`Mailer`].
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use anyhow::Context;
+use axum::async_trait;
+use lettre::{
+    message::header::ContentType, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+use serde::Serialize;
+use tinytemplate_async::{format_unescaped, TinyTemplate};
+
+use crate::config::{MailConfig, MailTransport, SmtpConfig, SmtpSecurity};
+
+/// The mails every site has, sites change them with a file of the same name in
+/// `templates/mail/`. The first line of a template is the subject, like `Subject: Hello`.
+const MAIL_TEMPLATES: &[(&str, &str)] = &[
+    (
+        "reset_password",
+        include_str!("../data/mail/reset_password.txt"),
+    ),
+    (
+        "verify_email",
+        include_str!("../data/mail/verify_email.txt"),
+    ),
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mail {
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    /// Plain text
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: &Mail) -> anyhow::Result<()>;
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpMailer {
+    /// Only sets up the transport, nothing is connected to before the first mail.
+    pub fn new(config: &SmtpConfig) -> anyhow::Result<Self> {
+        let mut builder = match config.security {
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+            SmtpSecurity::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+            }
+            SmtpSecurity::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host)
+            }
+        };
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let Some(username) = &config.username {
+            builder = builder.credentials(Credentials::new(
+                username.clone(),
+                config.password.clone().unwrap_or_default(),
+            ));
+        }
+        Ok(SmtpMailer {
+            transport: builder.build(),
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: &Mail) -> anyhow::Result<()> {
+        let message = Message::builder()
+            .from(mail.from.parse()?)
+            .to(mail.to.parse()?)
+            .subject(&mail.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(mail.body.clone())?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+/// Writes mails out instead of sending them, appended to a file or to stdout when there is
+/// none.
+pub struct FileMailer {
+    pub path: Option<PathBuf>,
+}
+
+impl FileMailer {
+    /// The mails as they are written out, separated by a line of dashes.
+    pub fn format(mail: &Mail) -> String {
+        format!(
+            "From: {}\nTo: {}\nSubject: {}\n\n{}\n----\n",
+            mail.from, mail.to, mail.subject, mail.body
+        )
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, mail: &Mail) -> anyhow::Result<()> {
+        let formatted = Self::format(mail);
+        match &self.path {
+            Some(path) => fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| file.write_all(formatted.as_bytes()))
+                .with_context(|| format!("Failed to write a mail to {}", path.display()))?,
+            None => print!("{formatted}"),
+        }
+        Ok(())
+    }
+}
+
+/// The mailer of a site along with the templates of its mails.
+#[derive(Clone)]
+pub struct SiteMailer {
+    pub from: String,
+    pub mailer: Arc<dyn Mailer>,
+    pub templates: Arc<RwLock<TinyTemplate>>,
+}
+
+/// Prints the mails, which are made from the built in templates.
+impl Default for SiteMailer {
+    fn default() -> Self {
+        SiteMailer {
+            from: MailConfig::default().from,
+            mailer: Arc::new(FileMailer { path: None }),
+            templates: Arc::new(RwLock::new(
+                setup_mail_templates(None).expect("Compiling the built in mail templates"),
+            )),
+        }
+    }
+}
+
+impl SiteMailer {
+    /// The mailer of `config` for the site at `site_path`, with the mail templates of the site.
+    pub fn new(config: &MailConfig, site_path: &str) -> anyhow::Result<Self> {
+        let mailer: Arc<dyn Mailer> = match &config.transport {
+            MailTransport::Stdout => Arc::new(FileMailer { path: None }),
+            MailTransport::File { path } => Arc::new(FileMailer {
+                path: Some(Path::new(site_path).join(path)),
+            }),
+            MailTransport::Smtp(smtp) => Arc::new(
+                SmtpMailer::new(smtp)
+                    .with_context(|| format!("Failed to set up the smtp relay {}", smtp.host))?,
+            ),
+        };
+        Ok(SiteMailer {
+            from: config.from.clone(),
+            mailer,
+            templates: Arc::new(RwLock::new(setup_mail_templates(Some(site_path))?)),
+        })
+    }
+
+    /// Renders the mail template `name` with `context` and sends it to `to`.
+    pub async fn send_template<C: Serialize>(
+        &self,
+        name: &str,
+        to: &str,
+        context: &C,
+    ) -> anyhow::Result<()> {
+        let rendered = self
+            .templates
+            .read()
+            .unwrap()
+            .render(name, context)
+            .with_context(|| format!("Failed to render the mail template {name}"))?;
+        let (subject, body) = rendered.split_once('\n').unwrap_or((&rendered, ""));
+        let mail = Mail {
+            from: self.from.clone(),
+            to: to.to_string(),
+            subject: subject.trim_start_matches("Subject:").trim().to_string(),
+            body: body.trim_start_matches('\n').to_string(),
+        };
+        self.mailer.send(&mail).await
+    }
+}
+
+/// The built in mail templates, replaced by those in `templates/mail/` of the site at
+/// `site_path`. Mails are plain text, so nothing is escaped.
+pub fn setup_mail_templates(site_path: Option<&str>) -> anyhow::Result<TinyTemplate> {
+    let mut templates = TinyTemplate::new();
+    templates.set_default_formatter(format_unescaped);
+    for (name, content) in MAIL_TEMPLATES {
+        templates.add_template(name.to_string(), content.to_string())?;
+    }
+    let dir = match site_path {
+        Some(site_path) => format!("{site_path}/templates/mail"),
+        None => return Ok(templates),
+    };
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(templates),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        let name = match path.file_stem() {
+            Some(name) if path.is_file() => name.to_string_lossy().to_string(),
+            _ => continue,
+        };
+        log::info!("Found mail template {}", path.display());
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        templates
+            .add_template(name, content)
+            .with_context(|| format!("Failed to compile the mail template {}", path.display()))?;
+    }
+    Ok(templates)
+}
@@ -0,0 +1,287 @@
+//! Versioned migrations of the site databases, which are embedded from `migrations/`.
+
+use std::fmt::Display;
+
+use serde::Serialize;
+use sqlx::{query, query_scalar, Executor, SqlitePool};
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+}
+
+/// Every migration in the order they are applied in, new ones go at the end.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        up: include_str!("../migrations/0001_initial.up.sql"),
+        down: include_str!("../migrations/0001_initial.down.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "sessions",
+        up: include_str!("../migrations/0002_sessions.up.sql"),
+        down: include_str!("../migrations/0002_sessions.down.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "ranks",
+        up: include_str!("../migrations/0003_ranks.up.sql"),
+        down: include_str!("../migrations/0003_ranks.down.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "account_tokens",
+        up: include_str!("../migrations/0004_account_tokens.up.sql"),
+        down: include_str!("../migrations/0004_account_tokens.down.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "registration",
+        up: include_str!("../migrations/0005_registration.up.sql"),
+        down: include_str!("../migrations/0005_registration.down.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "reset_attempts",
+        up: include_str!("../migrations/0006_reset_attempts.up.sql"),
+        down: include_str!("../migrations/0006_reset_attempts.down.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "rendered_with",
+        up: include_str!("../migrations/0007_rendered_with.up.sql"),
+        down: include_str!("../migrations/0007_rendered_with.down.sql"),
+    },
+];
+
+/// Columns that databases from before migrations may not have yet, they were added to the
+/// tables of the first migration without a version to tell.
+const UNVERSIONED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("users", "reset_password", "INTEGER NOT NULL DEFAULT 0"),
+    ("posts", "publish_at", "INTEGER"),
+    ("posts", "format", "TEXT NOT NULL DEFAULT 'Html'"),
+    ("posts", "rendered", "TEXT"),
+    ("posts", "toc", "TEXT"),
+    ("posts", "slug", "TEXT"),
+    ("post_revisions", "format", "TEXT NOT NULL DEFAULT 'Html'"),
+];
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Database(sqlx::Error),
+    /// The database has been migrated by a newer version, which this one knows nothing of.
+    Unknown {
+        version: i64,
+        name: String,
+    },
+}
+
+impl Display for MigrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Database(e) => write!(f, "{e}"),
+            Self::Unknown { version, name } => write!(
+                f,
+                "The database has the migration {version} {name}, which is newer than this version"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<sqlx::Error> for MigrationError {
+    fn from(value: sqlx::Error) -> Self {
+        Self::Database(value)
+    }
+}
+
+/// A migration along with when it was applied, as a unix timestamp.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub name: String,
+    pub applied: Option<i64>,
+}
+
+async fn create_version_table(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    query!(
+        "CREATE TABLE IF NOT EXISTS schema_version(
+            version INTEGER NOT NULL PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied INTEGER NOT NULL DEFAULT (unixepoch(CURRENT_TIMESTAMP))
+        ) STRICT"
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Adds the columns that tables from before migrations lack, so the first migration can be
+/// applied to them.
+async fn adopt_unversioned(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let tables = query_scalar!(r#"SELECT name AS "name!" FROM sqlite_master WHERE type = 'table'"#)
+        .fetch_all(pool)
+        .await?;
+    let has = |table: &str| tables.iter().any(|name| name == table);
+    if has("schema_version") || !has("users") {
+        return Ok(());
+    }
+    log::info!("Adopting a database from before migrations");
+    for (table, column, definition) in UNVERSIONED_COLUMNS.iter() {
+        if has(table) {
+            add_missing_column(pool, table, column, definition).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn add_missing_column(
+    pool: &SqlitePool,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), sqlx::Error> {
+    let exists: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM pragma_table_info(?) WHERE name = ?")
+            .bind(table)
+            .bind(column)
+            .fetch_one(pool)
+            .await?;
+    if exists == 0 {
+        log::info!("Adding the column {column} to {table}");
+        sqlx::query(&format!(
+            "ALTER TABLE {table} ADD COLUMN {column} {definition}"
+        ))
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Every known migration and the applied ones this version doesn't know, by version.
+pub async fn status(pool: &SqlitePool) -> Result<Vec<MigrationStatus>, sqlx::Error> {
+    let versioned = query_scalar!(
+        r#"SELECT EXISTS(
+            SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_version'
+        ) AS "versioned!: bool""#
+    )
+    .fetch_one(pool)
+    .await?;
+    // Looking shouldn't create the table, which would keep older databases from being adopted
+    let applied = match versioned {
+        true => {
+            query!("SELECT version, name, applied FROM schema_version ORDER BY version")
+                .fetch_all(pool)
+                .await?
+        }
+        false => Vec::new(),
+    };
+    let mut status: Vec<MigrationStatus> = MIGRATIONS
+        .iter()
+        .map(|migration| MigrationStatus {
+            version: migration.version,
+            name: migration.name.to_string(),
+            applied: applied
+                .iter()
+                .find(|row| row.version == migration.version)
+                .map(|row| row.applied),
+        })
+        .collect();
+    for row in applied.into_iter() {
+        if !MIGRATIONS.iter().any(|m| m.version == row.version) {
+            status.push(MigrationStatus {
+                version: row.version,
+                name: row.name,
+                applied: Some(row.applied),
+            });
+        }
+    }
+    status.sort_by_key(|migration| migration.version);
+    Ok(status)
+}
+
+/// Fails on migrations this version can't roll back or build on.
+fn check_known(status: &[MigrationStatus]) -> Result<(), MigrationError> {
+    match status
+        .iter()
+        .find(|s| !MIGRATIONS.iter().any(|m| m.version == s.version))
+    {
+        Some(unknown) => Err(MigrationError::Unknown {
+            version: unknown.version,
+            name: unknown.name.clone(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Applies the pending migrations up to `target`, or all of them, each in its own
+/// transaction. Returns the versions that were applied.
+pub async fn migrate(pool: &SqlitePool, target: Option<i64>) -> Result<Vec<i64>, MigrationError> {
+    adopt_unversioned(pool).await?;
+    create_version_table(pool).await?;
+    let status = status(pool).await?;
+    check_known(&status)?;
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS.iter() {
+        let pending = status
+            .iter()
+            .any(|s| s.version == migration.version && s.applied.is_none());
+        if !pending || target.is_some_and(|target| migration.version > target) {
+            continue;
+        }
+        log::info!(
+            "Applying the migration {} {}",
+            migration.version,
+            migration.name
+        );
+        let mut tx = pool.begin().await?;
+        (&mut *tx).execute(migration.up).await?;
+        query!(
+            "INSERT INTO schema_version(version, name) VALUES(?, ?)",
+            migration.version,
+            migration.name
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        applied.push(migration.version);
+    }
+    Ok(applied)
+}
+
+/// Rolls back the applied migrations newer than `target`, newest first. Returns the versions
+/// that were rolled back.
+pub async fn rollback(pool: &SqlitePool, target: i64) -> Result<Vec<i64>, MigrationError> {
+    let status = status(pool).await?;
+    check_known(&status)?;
+    let mut rolled_back = Vec::new();
+    for migration in MIGRATIONS.iter().rev() {
+        let applied = status
+            .iter()
+            .any(|s| s.version == migration.version && s.applied.is_some());
+        if !applied || migration.version <= target {
+            continue;
+        }
+        log::info!(
+            "Rolling back the migration {} {}",
+            migration.version,
+            migration.name
+        );
+        let mut tx = pool.begin().await?;
+        (&mut *tx).execute(migration.down).await?;
+        query!(
+            "DELETE FROM schema_version WHERE version = ?",
+            migration.version
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        rolled_back.push(migration.version);
+    }
+    Ok(rolled_back)
+}
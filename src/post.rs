@@ -1,226 +1,208 @@
 use axum::async_trait;
-use futures::{stream::Stream, StreamExt};
-use std::{error::Error, io::Write};
+use futures::stream::Stream;
+use std::{error::Error, fmt::Display, string::FromUtf8Error, time::Duration};
 
 use axum::{
     body::Bytes,
     extract::{Query, State},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
     Json,
 };
 use axum_typed_multipart::{
-    FieldMetadata, TryFromChunks, TryFromMultipart, TypedMultipart, TypedMultipartError,
+    FieldMetadata, TryFromChunks, TryFromField, TryFromMultipart, TypedMultipart,
+    TypedMultipartError,
 };
+use chrono::DateTime;
 use log::error;
 use serde::{Deserialize, Serialize};
+use slug::slugify;
 use sqlx::{
     database::HasValueRef,
     prelude::{FromRow, Type},
-    query, query_as,
+    query, query_scalar,
     sqlite::SqliteTypeInfo,
-    Database, Decode, Encode, Sqlite,
+    Database, Decode, Encode, Sqlite, SqliteConnection, SqlitePool,
 };
+use tokio::task::JoinHandle;
 
-use crate::{auth::user::User, config::SiteConfig};
+use crate::{
+    auth::{capability::Capability, user::User},
+    config::{SanitizeConfig, SiteConfig},
+    render::{cache_rendered, sanitize, ContentFormat, Toc},
+    revision::record_revision,
+    tag::set_post_tags,
+};
 
 #[derive(Serialize, Deserialize, TryFromMultipart)]
 pub struct PostCreateRequest {
     name: String,
+    /// Made from the name when left out
+    slug: Option<String>,
     content: String,
     #[serde(default)]
     tags: Option<VecStr>,
+    status: Option<PostStatus>,
+    publish_at: Option<i64>,
+    format: Option<ContentFormat>,
 }
 
-#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+/// A list of strings, like the tags of a post.
+///
+/// It is encoded the same way everywhere, in json bodies, multipart fields and the
+/// database: as a json array of strings, `["rust", "web"]`. Multipart fields may also be
+/// a comma separated list, `rust, web`, so that plain html forms work. Blobs written by
+/// older versions, strings each prefixed with their length as a little endian u64, are
+/// still read from the database.
+#[derive(Default, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(transparent)]
 pub struct VecStr {
     pub data: Vec<String>,
 }
 
+#[derive(Debug)]
+pub enum VecStrError {
+    Json(serde_json::Error),
+    /// A length prefixed blob ended in the middle of a string.
+    Truncated,
+    Utf8(FromUtf8Error),
+}
+
+impl Display for VecStrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Json(e) => write!(f, "Not a json array of strings: {e}"),
+            Self::Truncated => write!(f, "The list ended in the middle of a string"),
+            Self::Utf8(e) => write!(f, "A string in the list is not valid utf-8: {e}"),
+        }
+    }
+}
+
+impl Error for VecStrError {}
+
+impl VecStr {
+    pub fn to_json(&self) -> String {
+        serde_json::Value::from(self.data.clone()).to_string()
+    }
+
+    pub fn from_json(json: &str) -> Result<VecStr, VecStrError> {
+        serde_json::from_str(json).map_err(VecStrError::Json)
+    }
+
+    /// Reads a form field, either a json array or a comma separated list of strings.
+    /// Surrounding whitespace and empty entries are dropped from comma separated lists.
+    pub fn from_field(field: &str) -> Result<VecStr, VecStrError> {
+        if field.trim_start().starts_with('[') {
+            return Self::from_json(field);
+        }
+        Ok(VecStr {
+            data: field
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect(),
+        })
+    }
+
+    /// Reads a value stored in the database, a json array or a legacy length prefixed blob.
+    pub fn from_bytes(bytes: &[u8]) -> Result<VecStr, VecStrError> {
+        match std::str::from_utf8(bytes).map(Self::from_json) {
+            Ok(Ok(vec)) => Ok(vec),
+            _ => Self::from_length_prefixed(bytes),
+        }
+    }
+
+    fn from_length_prefixed(mut bytes: &[u8]) -> Result<VecStr, VecStrError> {
+        let mut data = Vec::new();
+        while !bytes.is_empty() {
+            let (length, rest) = bytes.split_at(bytes.len().min(8));
+            let length = u64::from_le_bytes(length.try_into().map_err(|_| VecStrError::Truncated)?);
+            let length = usize::try_from(length)
+                .ok()
+                .filter(|length| *length <= rest.len())
+                .ok_or(VecStrError::Truncated)?;
+            let (string, rest) = rest.split_at(length);
+            data.push(String::from_utf8(string.to_vec()).map_err(VecStrError::Utf8)?);
+            bytes = rest;
+        }
+        Ok(VecStr { data })
+    }
+}
+
 #[async_trait]
 impl TryFromChunks for VecStr {
-    #[doc = r" This expects the vector to be in the format"]
-    #[doc = r" [ vector length ][ 1st strings length ][ string data ][ 2nd strings length ][ string data ]...[ last strings length ][ string data ]"]
+    /// Reads the field with [`VecStr::from_field`].
     async fn try_from_chunks(
-        mut chunks: impl 'async_trait
+        chunks: impl 'async_trait
             + Stream<Item = Result<Bytes, TypedMultipartError>>
             + Send
             + Sync
             + Unpin,
-        _metadata: FieldMetadata,
+        metadata: FieldMetadata,
     ) -> Result<Self, TypedMultipartError> {
-        let chunk = chunks.next().await;
-        let chunk = match chunk {
-            Some(c) => match c {
-                Ok(b) => b,
-                Err(e) => return Err(e),
-            },
-            None => {
-                return Err(TypedMultipartError::Other {
-                    source: anyhow::Error::msg("Empty String"),
-                })
-            }
-        };
-        let count = match chunk.first() {
-            Some(c) => c,
-            None => {
-                return Err(TypedMultipartError::Other {
-                    source: anyhow::Error::msg("Empty String"),
-                })
-            }
-        };
-
-        let mut data = Vec::with_capacity(*count as usize);
-        let mut buf = Vec::with_capacity(chunks.size_hint().0);
-        while let Some(chunk) = chunks.next().await {
-            let _ = match chunk {
-                Ok(chunk) => buf.write_all(chunk.to_vec().as_slice()),
-                Err(e) => return Err(e),
-            };
-        }
-        let mut i = 0;
-        for _ in 0..*count {
-            let length = match buf.get(i) {
-                Some(l) => l,
-                None => {
-                    return Err(TypedMultipartError::Other {
-                        source: anyhow::Error::msg("Data ended too early"),
-                    })
-                }
-            };
-            data.push(
-                String::from_utf8(match buf.get(i..(i + *length as usize)) {
-                    Some(s) => s.to_vec(),
-                    None => {
-                        return Err(TypedMultipartError::Other {
-                            source: anyhow::Error::msg("Data ended too early"),
-                        })
-                    }
-                })
-                .map_err(|e| TypedMultipartError::Other {
-                    source: anyhow::Error::from(e),
-                })?,
-            );
-            i += *length as usize;
-        }
-        Ok(VecStr { data })
+        let field = String::try_from_chunks(chunks, metadata).await?;
+        VecStr::from_field(field.as_str()).map_err(|e| TypedMultipartError::Other {
+            source: anyhow::Error::from(e),
+        })
     }
 }
 
 impl Type<Sqlite> for VecStr {
     fn type_info() -> <Sqlite as Database>::TypeInfo {
-        <&[u8] as Type<Sqlite>>::type_info()
+        <String as Type<Sqlite>>::type_info()
     }
 
     fn compatible(ty: &SqliteTypeInfo) -> bool {
-        <&[u8] as Type<Sqlite>>::compatible(ty) || <Vec<u8> as Type<Sqlite>>::compatible(ty)
+        <String as Type<Sqlite>>::compatible(ty) || <Vec<u8> as Type<Sqlite>>::compatible(ty)
     }
 }
 
-impl<'r> Encode<'r, Sqlite> for VecStr
-where
-    &'r [u8]: Encode<'r, Sqlite>,
-{
+impl<'q> Encode<'q, Sqlite> for VecStr {
     fn encode_by_ref(
         &self,
-        buf: &mut <Sqlite as sqlx::database::HasArguments<'r>>::ArgumentBuffer,
+        buf: &mut <Sqlite as sqlx::database::HasArguments<'q>>::ArgumentBuffer,
     ) -> sqlx::encode::IsNull {
-        if self.data.is_empty() {
-            return sqlx::encode::IsNull::Yes;
-        }
-        let mut bytes = Vec::new();
-        for string in self.data.iter() {
-            bytes
-                .write_all(&(string.as_bytes().len() as u64).to_le_bytes())
-                .unwrap();
-            bytes.write_all(string.as_bytes()).unwrap();
-        }
-        let byte_slice: Box<[u8]> = Box::from(bytes.as_slice());
-        <Box<[u8]> as Encode<Sqlite>>::encode(byte_slice, buf)
+        <String as Encode<Sqlite>>::encode(self.to_json(), buf)
     }
 }
-// DB is the database driver
-// `'r` is the lifetime of the `Row` being decoded
-impl<'r, DB: Database> Decode<'r, DB> for VecStr
-where
-    Vec<u8>: Decode<'r, DB>,
-{
+
+impl<'r> Decode<'r, Sqlite> for VecStr {
     fn decode(
-        value: <DB as HasValueRef<'r>>::ValueRef,
+        value: <Sqlite as HasValueRef<'r>>::ValueRef,
     ) -> Result<VecStr, Box<dyn Error + 'static + Send + Sync>> {
-        // Decoding from a Rows of String format
-        let ros = <Vec<u8> as Decode<DB>>::decode(value)?;
-        let mut result: Vec<String> = Vec::new();
-        let mut idx = 0;
-        while idx < ros.len() {
-            let length = u64::from_le_bytes(match ros.get(idx..(idx + 8)) {
-                Some(x) => x.try_into().unwrap(),
-                None => {
-                    return Err(Box::new(sqlx::Error::TypeNotFound {
-                        type_name: "Row of String".to_string(),
-                    }))
-                }
-            });
-            idx += 8;
-            result.push(
-                String::from_utf8(match ros.get(idx..(idx + length as usize)) {
-                    Some(x) => x.to_vec(),
-                    None => {
-                        return Err(Box::new(sqlx::Error::TypeNotFound {
-                            type_name: "Row of String".to_string(),
-                        }))
-                    }
-                })
-                .unwrap(),
-            );
-            idx += length as usize;
-        }
-        Ok(VecStr { data: result })
-    }
-}
-
-impl From<Vec<u8>> for VecStr {
-    fn from(ros: Vec<u8>) -> Self {
-        // Decoding from a Rows of String format
-        let mut result: Vec<String> = Vec::new();
-        let mut idx = 0;
-        while idx < ros.len() {
-            let length = u64::from_le_bytes(match ros.get(idx..(idx + 8)) {
-                Some(x) => x.try_into().unwrap(),
-                None => {
-                    log::warn!("Error while reading string length");
-                    [0u8; 8]
-                }
-            });
-            idx += 8;
-            result.push(
-                String::from_utf8(match ros.get(idx..(idx + length as usize)) {
-                    Some(x) => x.to_vec(),
-                    None => {
-                        log::warn!("Error while reading string content");
-                        Vec::new()
-                    }
-                })
-                .unwrap(),
-            );
-            idx += length as usize;
-        }
-        VecStr { data: result }
+        let bytes = <&[u8] as Decode<Sqlite>>::decode(value)?;
+        Ok(VecStr::from_bytes(bytes)?)
+    }
+}
+
+/// Reads the json array of names that post queries aggregate the tags table into.
+impl From<String> for VecStr {
+    fn from(json: String) -> Self {
+        VecStr::from_json(json.as_str()).unwrap_or_else(|e| {
+            log::warn!("Error while reading tag names: {}", e);
+            VecStr::default()
+        })
     }
 }
 
-pub async fn create_post<'a>(
+pub async fn create_post(
     State(config): State<SiteConfig>,
     user: User,
     TypedMultipart(form): TypedMultipart<PostCreateRequest>,
 ) -> StatusCode {
-    match query!(
-        "INSERT INTO posts(name, content, tags, owner) VALUES(?1, ?2, ?3, ?4)",
-        form.name,
-        form.content,
-        form.tags,
-        user.username
+    if form.status == Some(PostStatus::Scheduled) && form.publish_at.is_none() {
+        return StatusCode::BAD_REQUEST;
+    }
+    if form.status.is_some_and(|status| !can_set_status(&user, &status)) {
+        return StatusCode::FORBIDDEN;
+    }
+    match insert_post(
+        &config.db_pool.unwrap(),
+        form,
+        user.username,
+        &config.sanitize,
     )
-    .execute(&config.db_pool.clone().unwrap())
     .await
     {
         Err(e) => {
@@ -231,23 +213,209 @@ pub async fn create_post<'a>(
     }
 }
 
+async fn insert_post(
+    pool: &SqlitePool,
+    form: PostCreateRequest,
+    owner: String,
+    policy: &SanitizeConfig,
+) -> Result<i64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let status = form.status.unwrap_or(PostStatus::Draft).to_string();
+    let format = form.format.unwrap_or_default().to_string();
+    let slug = unique_slug(&mut tx, form.slug.as_deref().unwrap_or(&form.name), None).await?;
+    let id = query!(
+        "INSERT INTO posts(name, slug, content, owner, status, publish_at, format) VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        form.name,
+        slug,
+        form.content,
+        owner,
+        status,
+        form.publish_at,
+        format
+    )
+    .execute(&mut *tx)
+    .await?
+    .last_insert_rowid();
+    set_post_tags(&mut tx, id, &form.tags.unwrap_or_default()).await?;
+    cache_rendered(&mut tx, id, policy).await?;
+    tx.commit().await?;
+    Ok(id)
+}
+
+/// A slug made from `wanted` that no other post has, numbered when it is taken. `post` is
+/// the post the slug is for, when it exists already.
+pub async fn unique_slug(
+    conn: &mut SqliteConnection,
+    wanted: &str,
+    post: Option<i64>,
+) -> Result<String, sqlx::Error> {
+    let mut base = slugify(wanted);
+    if base.is_empty() {
+        base = "post".to_string();
+    }
+    let mut slug = base.clone();
+    let mut number = 1;
+    while query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM posts WHERE slug = ?1 AND id IS NOT ?2) AS "taken!: bool""#,
+        slug,
+        post
+    )
+    .fetch_one(&mut *conn)
+    .await?
+    {
+        number += 1;
+        slug = format!("{base}-{number}");
+    }
+    Ok(slug)
+}
+
+/// Gives the posts from older versions, which had no slugs, one made from their name.
+pub async fn assign_missing_slugs(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let posts = query!("SELECT id, name FROM posts WHERE slug IS NULL ORDER BY id")
+        .fetch_all(pool)
+        .await?;
+    let mut conn = pool.acquire().await?;
+    for post in posts.iter() {
+        let slug = unique_slug(&mut conn, &post.name, Some(post.id)).await?;
+        query!("UPDATE posts SET slug = ?1 WHERE id = ?2", slug, post.id)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Posts can be edited by whoever may edit the posts of others, and by their owner while
+/// their rank lets them write posts. Owners who can't publish, like contributors, only
+/// edit their posts until they are published.
+pub fn can_edit(user: &User, owner: &str, status: &PostStatus) -> bool {
+    user.rank.can(Capability::EditOthersPosts)
+        || (user.username == owner
+            && user.rank.can(Capability::EditOwnPosts)
+            && (user.rank.can(Capability::PublishPosts) || !status.publishes()))
+}
+
+/// Only ranks that may publish posts can put them in front of readers, the others can
+/// still save drafts and send them for review.
+pub fn can_set_status(user: &User, status: &PostStatus) -> bool {
+    !status.publishes() || user.rank.can(Capability::PublishPosts)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PostUpdateQuery {
+    id: i64,
+}
+
+/// Fields left out are not changed.
+#[derive(Serialize, Deserialize, TryFromMultipart)]
+pub struct PostUpdateRequest {
+    name: Option<String>,
+    /// Renaming a post keeps its slug, so links to it keep working
+    slug: Option<String>,
+    content: Option<String>,
+    tags: Option<VecStr>,
+    status: Option<PostStatus>,
+    publish_at: Option<i64>,
+    format: Option<ContentFormat>,
+}
+
+/// Edits a post, the version it replaces is kept in its revisions.
+pub async fn update_post(
+    State(config): State<SiteConfig>,
+    user: User,
+    Query(query): Query<PostUpdateQuery>,
+    TypedMultipart(form): TypedMultipart<PostUpdateRequest>,
+) -> StatusCode {
+    // Scheduling needs the time to publish at in the same request
+    if form.status == Some(PostStatus::Scheduled) && form.publish_at.is_none() {
+        return StatusCode::BAD_REQUEST;
+    }
+    if form.status.is_some_and(|status| !can_set_status(&user, &status)) {
+        return StatusCode::FORBIDDEN;
+    }
+    let pool = config.db_pool.unwrap();
+    match query!("SELECT owner, status FROM posts WHERE id = ?", query.id)
+        .fetch_optional(&pool)
+        .await
+    {
+        Ok(Some(post)) if can_edit(&user, &post.owner, &post.status.clone().into()) => (),
+        Ok(Some(_)) => return StatusCode::FORBIDDEN,
+        Ok(None) => return StatusCode::NOT_FOUND,
+        Err(e) => {
+            error!("Error while fetching a post: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    }
+    match edit_post(&pool, query.id, form, &user.username, &config.sanitize).await {
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            error!("Error while updating a post: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn edit_post(
+    pool: &SqlitePool,
+    id: i64,
+    form: PostUpdateRequest,
+    editor: &str,
+    policy: &SanitizeConfig,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    record_revision(&mut tx, id, editor).await?;
+    let status = form.status.map(|status| status.to_string());
+    let format = form.format.map(|format| format.to_string());
+    let slug = match form.slug {
+        Some(slug) => Some(unique_slug(&mut tx, &slug, Some(id)).await?),
+        None => None,
+    };
+    query!(
+        "UPDATE posts SET name = COALESCE(?1, name), content = COALESCE(?2, content),
+        status = COALESCE(?3, status), publish_at = COALESCE(?4, publish_at),
+        format = COALESCE(?5, format), slug = COALESCE(?6, slug) WHERE id = ?7",
+        form.name,
+        form.content,
+        status,
+        form.publish_at,
+        format,
+        slug,
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+    cache_rendered(&mut tx, id, policy).await?;
+    if let Some(tags) = form.tags {
+        set_post_tags(&mut tx, id, &tags).await?;
+    }
+    tx.commit().await
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct PostDeleteRequest {
     id: i64,
 }
 
-pub async fn delete_post<'a>(
+pub async fn delete_post(
     State(config): State<SiteConfig>,
     user: User,
     form: Query<PostDeleteRequest>,
 ) -> StatusCode {
-    match query!(
-        "DELETE FROM posts WHERE id = ? AND owner = ?",
-        form.id,
-        user.username
-    )
-    .execute(&config.db_pool.clone().unwrap())
-    .await
+    let pool = config.db_pool.unwrap();
+    match query!("SELECT owner, status FROM posts WHERE id = ?", form.id)
+        .fetch_optional(&pool)
+        .await
+    {
+        Ok(Some(post)) if can_edit(&user, &post.owner, &post.status.clone().into()) => (),
+        Ok(Some(_)) => return StatusCode::FORBIDDEN,
+        Ok(None) => return StatusCode::NOT_FOUND,
+        Err(e) => {
+            error!("Error while fetching a post: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    }
+    match query!("DELETE FROM posts WHERE id = ?", form.id)
+        .execute(&pool)
+        .await
     {
         Ok(_) => StatusCode::OK,
         Err(e) => {
@@ -261,26 +429,32 @@ pub async fn delete_post<'a>(
 pub struct PostGetRequest {
     #[serde(default = "one")]
     id: i64,
+    /// Looked up instead of the id when given
+    slug: Option<String>,
 }
 
 fn one() -> i64 {
     1
 }
 
-pub async fn get_post<'a>(
+/// Only published posts are handed out to everyone, the rest only to whoever can edit them.
+pub async fn get_post(
     query: Query<PostGetRequest>,
+    user: Option<User>,
     State(config): State<SiteConfig>,
 ) -> Result<Json<Post>, StatusCode> {
-    match query_as!(Post, "SELECT * FROM posts WHERE id IS ?", query.id)
-        .fetch_one(&config.db_pool.unwrap())
-        .await
+    match query_posts!(
+        "WHERE CASE WHEN ?2 IS NULL THEN id = ?1 ELSE slug = ?2 END",
+        query.id,
+        query.slug
+    )
+    .fetch_one(&config.db_pool.unwrap())
+    .await
     {
-        Ok(post) => {
-            let content = ammonia::clean(post.content.as_str());
-            let post = Post { content, ..post };
-            Ok(Json(post))
+        Ok(post) if post.visible_to(user.as_ref()) => {
+            Ok(Json(post.sanitized_for(user.as_ref(), &config.sanitize)))
         }
-
+        Ok(_) => Err(StatusCode::NOT_FOUND),
         Err(e) => {
             log::warn!("{e}");
             Err(StatusCode::NOT_FOUND)
@@ -288,29 +462,399 @@ pub async fn get_post<'a>(
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PostSort {
+    #[default]
+    Date,
+    Name,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ListFormat {
+    Json,
+    Html,
+}
+
+/// Filters and paging for `/api/posts`, dates are unix timestamps and `before` is exclusive.
 #[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PostListRequest {
+    #[serde(default = "one")]
+    pub page: i64,
+    #[serde(default = "default_per_page")]
+    pub per_page: i64,
+    pub status: Option<PostStatus>,
+    pub owner: Option<String>,
+    /// Slug of a tag the posts must have
+    pub tag: Option<String>,
+    pub after: Option<i64>,
+    pub before: Option<i64>,
+    #[serde(default)]
+    pub sort: PostSort,
+    #[serde(default)]
+    pub order: SortOrder,
+    /// Defaults to html for htmx requests and json otherwise
+    pub format: Option<ListFormat>,
+    /// The template html responses are rendered with
+    #[serde(default = "default_list_template")]
+    pub template: String,
+}
+
+fn default_per_page() -> i64 {
+    20
+}
+
+pub(crate) fn default_list_template() -> String {
+    "admin_panel/blog_row".to_string()
+}
+
+pub const MAX_PER_PAGE: i64 = 100;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PostList {
+    pub posts: Vec<Post>,
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
+    /// Query string of the next page, for templates to load more with
+    pub next: Option<String>,
+}
+
+/// Lists posts a page at a time, trashed posts are left out unless asked for by `status`.
+/// Htmx callers get the page rendered with the `template` of the request.
+pub async fn list_posts(
+    State(config): State<SiteConfig>,
+    user: Option<User>,
+    headers: HeaderMap,
+    Query(mut req): Query<PostListRequest>,
+) -> Result<Response, StatusCode> {
+    req.page = req.page.max(1);
+    req.per_page = req.per_page.clamp(1, MAX_PER_PAGE);
+    let list = match fetch_posts(
+        config.db_pool.as_ref().unwrap(),
+        &req,
+        user.as_ref(),
+        &config.sanitize,
+    )
+    .await
+    {
+        Ok(list) => list,
+        Err(e) => {
+            error!("Error while listing posts: {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    list_response(&config, &headers, req.format, &req.template, &list)
+}
+
+/// Whose posts are listed besides the published ones. Those who may edit the posts of
+/// others see every post, which is `None`, everyone else the published ones and their own.
+pub fn list_viewer(user: Option<User>) -> Option<String> {
+    match user {
+        Some(user) if user.rank.can(Capability::EditOthersPosts) => None,
+        Some(user) => Some(user.username),
+        None => Some(String::new()),
+    }
+}
+
+/// Answers with a list as json, or rendered with `template` when html is asked for. Html is
+/// the default for htmx requests, and only the `list_templates` of the site render lists.
+pub fn list_response<T: Serialize>(
+    config: &SiteConfig,
+    headers: &HeaderMap,
+    format: Option<ListFormat>,
+    template: &str,
+    list: &T,
+) -> Result<Response, StatusCode> {
+    let format = format.unwrap_or(if headers.contains_key("HX-Request") {
+        ListFormat::Html
+    } else {
+        ListFormat::Json
+    });
+    if format == ListFormat::Json {
+        return Ok(Json(list).into_response());
+    }
+    if !config.list_templates.iter().any(|allowed| allowed == template) {
+        log::warn!(
+            "Refusing to render a list with {}, it isn't a list template",
+            template
+        );
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    match config.templates.read().unwrap().render(template, list) {
+        Ok(x) => Ok(Html(x).into_response()),
+        Err(e) => {
+            log::warn!("Error while rendering {} with a list: {}", template, e);
+            Err(StatusCode::BAD_REQUEST)
+        }
+    }
+}
+
+/// Only the posts [`list_viewer`] lets `user` see are listed.
+async fn fetch_posts(
+    pool: &SqlitePool,
+    req: &PostListRequest,
+    user: Option<&User>,
+    policy: &SanitizeConfig,
+) -> Result<PostList, sqlx::Error> {
+    let viewer = list_viewer(user.cloned());
+    let status = req.status.map(|status| status.to_string());
+    let by_name = matches!(req.sort, PostSort::Name);
+    let ascending = matches!(req.order, SortOrder::Asc);
+    let offset = (req.page - 1) * req.per_page;
+    let posts = query_posts!(
+        r#"WHERE (?1 IS NULL OR status = ?1) AND (?2 IS NULL OR owner = ?2)
+        AND (?3 IS NULL OR EXISTS(
+            SELECT 1 FROM post_tags JOIN tags ON tags.id = post_tags.tag
+            WHERE post_tags.post = posts.id AND tags.slug = ?3
+        ))
+        AND (?4 IS NULL OR date >= ?4) AND (?5 IS NULL OR date < ?5)
+        AND (?10 IS NULL OR status = 'Published' OR owner = ?10)
+        AND (?1 IS NOT NULL OR status != 'Trashed')
+        ORDER BY
+            CASE WHEN ?6 AND ?7 THEN name END ASC,
+            CASE WHEN ?6 AND NOT ?7 THEN name END DESC,
+            CASE WHEN NOT ?6 AND ?7 THEN date END ASC,
+            CASE WHEN NOT ?6 AND NOT ?7 THEN date END DESC,
+            CASE WHEN ?7 THEN id END ASC,
+            id DESC
+        LIMIT ?8 OFFSET ?9"#,
+        status,
+        req.owner,
+        req.tag,
+        req.after,
+        req.before,
+        by_name,
+        ascending,
+        req.per_page,
+        offset,
+        viewer
+    )
+    .fetch_all(pool)
+    .await?;
+    let total = query_scalar!(
+        r#"SELECT COUNT(*) AS "count!: i64" FROM posts
+        WHERE (?1 IS NULL OR status = ?1) AND (?2 IS NULL OR owner = ?2)
+        AND (?3 IS NULL OR EXISTS(
+            SELECT 1 FROM post_tags JOIN tags ON tags.id = post_tags.tag
+            WHERE post_tags.post = posts.id AND tags.slug = ?3
+        ))
+        AND (?4 IS NULL OR date >= ?4) AND (?5 IS NULL OR date < ?5)
+        AND (?6 IS NULL OR status = 'Published' OR owner = ?6)
+        AND (?1 IS NOT NULL OR status != 'Trashed')"#,
+        status,
+        req.owner,
+        req.tag,
+        req.after,
+        req.before,
+        viewer
+    )
+    .fetch_one(pool)
+    .await?;
+    let next = (offset + req.per_page < total)
+        .then(|| {
+            serde_urlencoded::to_string(PostListRequest {
+                page: req.page + 1,
+                ..req.clone()
+            })
+            .ok()
+        })
+        .flatten();
+    Ok(PostList {
+        posts: posts
+            .into_iter()
+            .map(|post| post.sanitized_for(user, policy))
+            .collect(),
+        page: req.page,
+        per_page: req.per_page,
+        total,
+        next,
+    })
+}
+
+#[derive(Serialize, Deserialize, TryFromField, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PostStatus {
     Draft,
+    /// Waiting for review before it is published
+    Pending,
+    /// Published once its `publish_at` is reached
+    Scheduled,
     Published,
+    /// Published, but only for its owner and admins
+    Private,
+    Trashed,
+}
+
+impl Display for PostStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match *self {
+            Self::Draft => "Draft",
+            Self::Pending => "Pending",
+            Self::Scheduled => "Scheduled",
+            Self::Published => "Published",
+            Self::Private => "Private",
+            Self::Trashed => "Trashed",
+        })
+    }
+}
+
+impl PostStatus {
+    /// Whether the status takes a post past drafts and reviews, like publishing does.
+    pub fn publishes(&self) -> bool {
+        matches!(self, Self::Published | Self::Scheduled | Self::Private)
+    }
 }
 
 impl From<String> for PostStatus {
     fn from(value: String) -> Self {
-        if value.eq("Published") {
-            Self::Published
-        } else {
-            Self::Draft
+        match value.as_str() {
+            "Pending" => Self::Pending,
+            "Scheduled" => Self::Scheduled,
+            "Published" => Self::Published,
+            "Private" => Self::Private,
+            "Trashed" => Self::Trashed,
+            _ => Self::Draft,
         }
     }
 }
 
+/// Queries [`Post`]s, `$sql` is the rest of the query after `FROM posts`, like its `WHERE`
+/// and `ORDER BY`. Every query of whole posts goes through here so they read the same columns.
+macro_rules! query_posts {
+    ($sql:literal $(, $args:expr)* $(,)?) => {
+        sqlx::query_as!(
+            $crate::post::Post,
+            r#"SELECT id, name, COALESCE(slug, '') AS "slug!: String", content, date, (
+                SELECT json_group_array(tags.name) FROM post_tags JOIN tags ON tags.id = post_tags.tag
+                WHERE post_tags.post = posts.id
+            ) AS "tags!: String", owner, status, publish_at, format,
+            COALESCE(rendered, '') AS "rendered!: String", COALESCE(toc, '[]') AS "toc!: String"
+            FROM posts "# + $sql
+            $(, $args)*
+        )
+    };
+}
+pub(crate) use query_posts;
+
 #[derive(Serialize, FromRow, Deserialize, Clone, Debug)]
 pub struct Post {
     pub id: i64,
     pub name: String,
+    /// Unique, identifies the post in its permalinks
+    pub slug: String,
     pub content: String,
     pub date: i64,
     pub tags: VecStr,
     pub owner: String,
     pub status: PostStatus,
+    /// When a scheduled post gets published, as a unix timestamp
+    pub publish_at: Option<i64>,
+    pub format: ContentFormat,
+    /// The content as sanitized html, cached whenever the content changes
+    pub rendered: String,
+    pub toc: Toc,
+}
+
+impl Post {
+    /// Whether the post may be shown to the user, or to visitors when there is none. Owners
+    /// still see their posts once they can't edit them anymore, like private ones.
+    pub fn visible_to(&self, user: Option<&User>) -> bool {
+        self.status == PostStatus::Published
+            || user.is_some_and(|user| {
+                user.rank.can(Capability::EditOthersPosts)
+                    || (user.username == self.owner && user.rank.can(Capability::EditOwnPosts))
+            })
+    }
+
+    /// Strips anything `policy` doesn't allow from the html before it is handed out, the
+    /// rendered html in case it was rendered under an older policy and the content since it
+    /// is stored as it was written.
+    pub fn sanitized(self, policy: &SanitizeConfig) -> Self {
+        Post {
+            content: sanitize(self.content.as_str(), policy),
+            rendered: sanitize(self.rendered.as_str(), policy),
+            ..self
+        }
+    }
+
+    /// Like [`Post::sanitized`], but users who can edit the post get its content as it was
+    /// written, to edit it further.
+    pub fn sanitized_for(self, user: Option<&User>, policy: &SanitizeConfig) -> Self {
+        match user.is_some_and(|user| can_edit(user, &self.owner, &self.status)) {
+            true => Post {
+                rendered: sanitize(self.rendered.as_str(), policy),
+                ..self
+            },
+            false => self.sanitized(policy),
+        }
+    }
+
+    /// Fills in a permalink pattern like `/blog/:year/:month/:slug` for the post.
+    pub fn permalink(&self, pattern: &str) -> String {
+        permalink(pattern, self.id, &self.slug, self.date)
+    }
+}
+
+/// Fills in a permalink pattern like `/blog/:year/:month/:slug` for a post, dates are in UTC.
+pub fn permalink(pattern: &str, id: i64, slug: &str, date: i64) -> String {
+    let date = DateTime::from_timestamp(date, 0).unwrap_or_default();
+    pattern
+        .split('/')
+        .map(|segment| match segment {
+            ":year" => date.format("%Y").to_string(),
+            ":month" => date.format("%m").to_string(),
+            ":day" => date.format("%d").to_string(),
+            ":slug" => slug.to_string(),
+            ":id" => id.to_string(),
+            _ => segment.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+/// How long the publisher waits at most between checks, so that posts scheduled
+/// in the meantime are picked up.
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Publishes the scheduled posts that are due, they are dated to when they were due.
+pub async fn publish_scheduled_posts(pool: &SqlitePool) -> Result<u64, sqlx::Error> {
+    Ok(query!(
+        "UPDATE posts SET status = 'Published', date = publish_at
+        WHERE status = 'Scheduled' AND publish_at <= unixepoch()"
+    )
+    .execute(pool)
+    .await?
+    .rows_affected())
+}
+
+/// Spawns a task that publishes scheduled posts of the site as they become due.
+pub fn spawn_publisher(pool: SqlitePool) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            match publish_scheduled_posts(&pool).await {
+                Ok(0) => (),
+                Ok(published) => log::info!("Published {published} scheduled posts"),
+                Err(e) => error!("Error while publishing scheduled posts: {}", e),
+            }
+            let next = query_scalar!(
+                "SELECT MIN(publish_at) - unixepoch() FROM posts WHERE status = 'Scheduled'"
+            )
+            .fetch_one(&pool)
+            .await
+            .ok()
+            .flatten()
+            .map(|secs| Duration::from_secs(secs.max(0) as u64));
+            tokio::time::sleep(next.map_or(PUBLISH_INTERVAL, |next| next.min(PUBLISH_INTERVAL)))
+                .await;
+        }
+    })
 }
@@ -0,0 +1,295 @@
+//! Turning the content of posts into the html that is served.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    io::{self, Write},
+    sync::Mutex,
+};
+
+use axum_typed_multipart::TryFromField;
+use base64::{engine::general_purpose, Engine};
+use comrak::{
+    adapters::{HeadingAdapter, HeadingMeta},
+    markdown_to_html_with_plugins,
+    nodes::Sourcepos,
+    plugins::syntect::SyntectAdapter,
+    Anchorizer, Options, Plugins,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use sqlx::{query, SqliteConnection, SqlitePool};
+
+use crate::{config::SanitizeConfig, search::index_post};
+
+/// Prepended to the ids of headings, so they can't clash with the ids the site uses.
+pub const ANCHOR_PREFIX: &str = "user-content-";
+
+#[derive(Serialize, Deserialize, TryFromField, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ContentFormat {
+    #[default]
+    Html,
+    Markdown,
+}
+
+impl Display for ContentFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match *self {
+            Self::Html => "Html",
+            Self::Markdown => "Markdown",
+        })
+    }
+}
+
+impl From<String> for ContentFormat {
+    fn from(value: String) -> Self {
+        if value.eq("Markdown") {
+            Self::Markdown
+        } else {
+            Self::Html
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Heading {
+    pub level: u8,
+    pub title: String,
+    /// Id of the heading in the rendered html
+    pub anchor: String,
+}
+
+/// The headings of a post in order, stored as a json array.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Toc {
+    pub headings: Vec<Heading>,
+}
+
+impl Toc {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.headings).unwrap_or_else(|_| "[]".to_string())
+    }
+}
+
+impl From<String> for Toc {
+    fn from(json: String) -> Self {
+        Toc {
+            headings: serde_json::from_str(json.as_str()).unwrap_or_else(|e| {
+                log::warn!("Error while reading a table of contents: {}", e);
+                Vec::new()
+            }),
+        }
+    }
+}
+
+/// Highlights fenced code with css classes, so themes decide on the colours.
+static HIGHLIGHTER: Lazy<SyntectAdapter> = Lazy::new(|| SyntectAdapter::new(None));
+
+/// Gives headings an id and collects them into a table of contents while rendering.
+#[derive(Default)]
+struct TocBuilder(Mutex<(Anchorizer, Vec<Heading>)>);
+
+impl HeadingAdapter for TocBuilder {
+    fn enter(
+        &self,
+        output: &mut dyn Write,
+        heading: &HeadingMeta,
+        _sourcepos: Option<Sourcepos>,
+    ) -> io::Result<()> {
+        let mut toc = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let anchor = format!(
+            "{ANCHOR_PREFIX}{}",
+            toc.0.anchorize(heading.content.clone())
+        );
+        write!(output, "<h{} id=\"{}\">", heading.level, anchor)?;
+        toc.1.push(Heading {
+            level: heading.level,
+            title: heading.content.clone(),
+            anchor,
+        });
+        Ok(())
+    }
+
+    fn exit(&self, output: &mut dyn Write, heading: &HeadingMeta) -> io::Result<()> {
+        writeln!(output, "</h{}>", heading.level)
+    }
+}
+
+/// Tags whose content ammonia drops along with them, it can't allow these.
+const DROPPED_TAGS: [&str; 2] = ["script", "style"];
+
+/// Html that is safe to serve, with what `policy` allows on top of the defaults.
+pub fn sanitize(html: &str, policy: &SanitizeConfig) -> String {
+    let allowed = |attribute: &&str| !attribute.starts_with("on") && *attribute != "rel";
+    let mut builder = ammonia::Builder::default();
+    builder
+        .add_tag_attributes("h1", ["id"])
+        .add_tag_attributes("h2", ["id"])
+        .add_tag_attributes("h3", ["id"])
+        .add_tag_attributes("h4", ["id"])
+        .add_tag_attributes("h5", ["id"])
+        .add_tag_attributes("h6", ["id"])
+        .add_tag_attributes("pre", ["class"])
+        .add_tag_attributes("code", ["class"])
+        .add_tag_attributes("span", ["class"])
+        .add_tag_attributes("input", ["type", "checked", "disabled"])
+        .add_tags(["input"])
+        .add_tags(
+            policy
+                .tags
+                .iter()
+                .map(String::as_str)
+                .filter(|tag| !DROPPED_TAGS.contains(tag)),
+        )
+        .add_generic_attributes(
+            policy
+                .generic_attributes
+                .iter()
+                .map(String::as_str)
+                .filter(allowed),
+        )
+        .add_url_schemes(
+            policy
+                .url_schemes
+                .iter()
+                .map(String::as_str)
+                .filter(|scheme| !["javascript", "vbscript"].contains(scheme)),
+        )
+        .id_prefix(Some(ANCHOR_PREFIX));
+    for (tag, attributes) in policy.tag_attributes.iter() {
+        builder.add_tag_attributes(
+            tag.as_str(),
+            attributes.iter().map(String::as_str).filter(allowed),
+        );
+    }
+    builder.clean(html).to_string()
+}
+
+/// Identifies a policy, posts rendered with another one are rendered again.
+pub fn policy_fingerprint(policy: &SanitizeConfig) -> String {
+    // The attributes per tag are in a HashMap, which has no order of its own
+    let tag_attributes: BTreeMap<&String, &Vec<String>> = policy.tag_attributes.iter().collect();
+    let policy = serde_json::to_string(&(
+        &policy.tags,
+        &policy.generic_attributes,
+        tag_attributes,
+        &policy.url_schemes,
+    ))
+    .unwrap_or_default();
+    general_purpose::URL_SAFE_NO_PAD.encode(Sha3_256::digest(policy))
+}
+
+/// The text of html without its tags, which is still escaped so it can be used as html.
+pub fn text(html: &str) -> String {
+    ammonia::Builder::empty()
+        .add_clean_content_tags(DROPPED_TAGS)
+        .clean(html)
+        .to_string()
+}
+
+/// The first `words` words of the text of html, see [`text`].
+pub fn excerpt(html: &str, words: usize) -> String {
+    let text = text(html);
+    let mut words_iter = text.split_whitespace();
+    let mut excerpt = words_iter
+        .by_ref()
+        .take(words)
+        .collect::<Vec<&str>>()
+        .join(" ");
+    if words_iter.next().is_some() {
+        excerpt.push_str(" …");
+    }
+    excerpt
+}
+
+pub struct Rendered {
+    pub html: String,
+    pub toc: Toc,
+}
+
+/// Renders the content of a post into sanitized html, only markdown gets a table of contents.
+pub fn render(content: &str, format: ContentFormat, policy: &SanitizeConfig) -> Rendered {
+    match format {
+        ContentFormat::Html => Rendered {
+            html: sanitize(content, policy),
+            toc: Toc::default(),
+        },
+        ContentFormat::Markdown => {
+            let mut options = Options::default();
+            options.extension.strikethrough = true;
+            options.extension.table = true;
+            options.extension.autolink = true;
+            options.extension.tasklist = true;
+            options.extension.footnotes = true;
+            options.extension.shortcodes = true;
+            // Raw html is allowed since the output is sanitized anyway
+            options.render.unsafe_ = true;
+            let toc = TocBuilder::default();
+            let mut plugins = Plugins::default();
+            plugins.render.codefence_syntax_highlighter = Some(&*HIGHLIGHTER);
+            plugins.render.heading_adapter = Some(&toc);
+            let html = markdown_to_html_with_plugins(content, &options, &plugins);
+            let (_, headings) = toc.0.into_inner().unwrap_or_else(|e| e.into_inner());
+            Rendered {
+                html: sanitize(&html, policy),
+                toc: Toc { headings },
+            }
+        }
+    }
+}
+
+/// Caches the sanitized html of a post along with the policy it was sanitized with and
+/// updates the search index, this has to follow every change to a post. The content itself
+/// is kept as it was written.
+pub async fn cache_rendered(
+    conn: &mut SqliteConnection,
+    post: i64,
+    policy: &SanitizeConfig,
+) -> Result<(), sqlx::Error> {
+    let post_content = query!("SELECT content, format FROM posts WHERE id = ?", post)
+        .fetch_one(&mut *conn)
+        .await?;
+    let format = ContentFormat::from(post_content.format);
+    let rendered = render(&post_content.content, format, policy);
+    let toc = rendered.toc.to_json();
+    let fingerprint = policy_fingerprint(policy);
+    query!(
+        "UPDATE posts SET rendered = ?1, toc = ?2, rendered_with = ?3 WHERE id = ?4",
+        rendered.html,
+        toc,
+        fingerprint,
+        post
+    )
+    .execute(&mut *conn)
+    .await?;
+    index_post(conn, post, &text(&rendered.html)).await
+}
+
+/// Renders the posts that have not been rendered yet, like those from older versions, and
+/// those rendered with another policy than `policy`.
+pub async fn render_stale_posts(
+    pool: &SqlitePool,
+    policy: &SanitizeConfig,
+) -> Result<(), sqlx::Error> {
+    let fingerprint = policy_fingerprint(policy);
+    let stale: Vec<i64> = query!(
+        "SELECT id FROM posts WHERE rendered IS NULL OR rendered_with IS NOT ?",
+        fingerprint
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| row.id)
+    .collect();
+    if stale.is_empty() {
+        return Ok(());
+    }
+    let mut conn = pool.acquire().await?;
+    for post in stale.iter() {
+        cache_rendered(&mut conn, *post, policy).await?;
+    }
+    log::info!("Rendered {} posts", stale.len());
+    Ok(())
+}
@@ -0,0 +1,277 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use log::error;
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use sqlx::{query, query_as, SqliteConnection, SqlitePool};
+
+use crate::{
+    auth::user::User,
+    config::{SanitizeConfig, SiteConfig},
+    post::{can_edit, can_set_status, query_posts, Post, PostStatus, VecStr},
+    render::{cache_rendered, ContentFormat},
+    tag::set_post_tags,
+};
+
+/// A post as it was before one of its edits, `editor` made that edit at `date`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PostRevision {
+    pub id: i64,
+    pub post: i64,
+    pub name: String,
+    pub content: String,
+    pub tags: VecStr,
+    pub status: PostStatus,
+    pub format: ContentFormat,
+    pub editor: Option<String>,
+    pub date: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RevisionSummary {
+    pub id: i64,
+    pub post: i64,
+    pub name: String,
+    pub status: PostStatus,
+    pub editor: Option<String>,
+    pub date: i64,
+}
+
+/// Saves the current version of a post before `editor` changes it.
+pub async fn record_revision(
+    conn: &mut SqliteConnection,
+    post: i64,
+    editor: &str,
+) -> Result<(), sqlx::Error> {
+    query!(
+        "INSERT INTO post_revisions(post, name, content, tags, status, format, editor)
+        SELECT id, name, content, (
+            SELECT json_group_array(tags.name) FROM post_tags JOIN tags ON tags.id = post_tags.tag
+            WHERE post_tags.post = posts.id
+        ), status, format, ?2 FROM posts WHERE id = ?1",
+        post,
+        editor
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+async fn fetch_revision(pool: &SqlitePool, id: i64) -> Result<PostRevision, sqlx::Error> {
+    query_as!(
+        PostRevision,
+        r#"SELECT id, post, name, content, tags AS "tags: String", status, format, editor, date
+        FROM post_revisions WHERE id = ?"#,
+        id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+async fn fetch_post(pool: &SqlitePool, id: i64) -> Result<Post, sqlx::Error> {
+    query_posts!("WHERE id = ?", id).fetch_one(pool).await
+}
+
+/// Fetches a post, answering with the status to respond with when it is missing or the
+/// user may not edit it.
+async fn editable_post(pool: &SqlitePool, user: &User, id: i64) -> Result<Post, StatusCode> {
+    match fetch_post(pool, id).await {
+        Ok(post) if can_edit(user, &post.owner, &post.status) => Ok(post),
+        Ok(_) => Err(StatusCode::FORBIDDEN),
+        Err(sqlx::Error::RowNotFound) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Error while fetching a post: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Same as [`editable_post`], for revisions.
+async fn editable_revision(
+    pool: &SqlitePool,
+    user: &User,
+    id: i64,
+) -> Result<PostRevision, StatusCode> {
+    let revision = match fetch_revision(pool, id).await {
+        Ok(revision) => revision,
+        Err(sqlx::Error::RowNotFound) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Error while fetching a revision: {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    editable_post(pool, user, revision.post).await?;
+    Ok(revision)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RevisionListRequest {
+    post: i64,
+}
+
+/// The revisions of a post, newest first.
+pub async fn list_revisions(
+    user: User,
+    State(config): State<SiteConfig>,
+    Query(req): Query<RevisionListRequest>,
+) -> Result<Json<Vec<RevisionSummary>>, StatusCode> {
+    let pool = config.db_pool.unwrap();
+    editable_post(&pool, &user, req.post).await?;
+    match query_as!(
+        RevisionSummary,
+        "SELECT id, post, name, status, editor, date FROM post_revisions
+        WHERE post = ? ORDER BY id DESC",
+        req.post
+    )
+    .fetch_all(&pool)
+    .await
+    {
+        Ok(revisions) => Ok(Json(revisions)),
+        Err(e) => {
+            error!("Error while listing revisions: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RevisionRequest {
+    id: i64,
+}
+
+pub async fn get_revision(
+    user: User,
+    State(config): State<SiteConfig>,
+    Query(req): Query<RevisionRequest>,
+) -> Result<Json<PostRevision>, StatusCode> {
+    let revision = editable_revision(&config.db_pool.unwrap(), &user, req.id).await?;
+    Ok(Json(revision))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RevisionDiffRequest {
+    id: i64,
+    /// Another revision of the same post to compare with, the current version by default
+    against: Option<i64>,
+}
+
+/// What changed between two versions of a post, `name` and `content` are unified diffs.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RevisionDiff {
+    pub name: String,
+    pub content: String,
+    pub tags_added: Vec<String>,
+    pub tags_removed: Vec<String>,
+    pub status: Option<(PostStatus, PostStatus)>,
+}
+
+impl RevisionDiff {
+    fn new(old: &PostRevision, new: &PostRevision, new_label: &str) -> RevisionDiff {
+        let old_label = format!("revision {}", old.id);
+        let unified = |old: &str, new: &str| {
+            TextDiff::from_lines(old, new)
+                .unified_diff()
+                .header(old_label.as_str(), new_label)
+                .to_string()
+        };
+        let added = |from: &VecStr, to: &VecStr| {
+            to.data
+                .iter()
+                .filter(|tag| !from.data.contains(tag))
+                .cloned()
+                .collect()
+        };
+        RevisionDiff {
+            name: unified(&old.name, &new.name),
+            content: unified(&old.content, &new.content),
+            tags_added: added(&old.tags, &new.tags),
+            tags_removed: added(&new.tags, &old.tags),
+            status: (old.status != new.status).then_some((old.status, new.status)),
+        }
+    }
+}
+
+pub async fn diff_revision(
+    user: User,
+    State(config): State<SiteConfig>,
+    Query(req): Query<RevisionDiffRequest>,
+) -> Result<Json<RevisionDiff>, StatusCode> {
+    let pool = config.db_pool.unwrap();
+    let old = editable_revision(&pool, &user, req.id).await?;
+    let (new, label) = match req.against {
+        Some(id) => {
+            let new = editable_revision(&pool, &user, id).await?;
+            if new.post != old.post {
+                return Err(StatusCode::BAD_REQUEST);
+            }
+            (new, format!("revision {id}"))
+        }
+        None => {
+            let post = editable_post(&pool, &user, old.post).await?;
+            let current = PostRevision {
+                id: 0,
+                post: post.id,
+                name: post.name,
+                content: post.content,
+                tags: post.tags,
+                status: post.status,
+                format: post.format,
+                editor: None,
+                date: post.date,
+            };
+            (current, "current".to_string())
+        }
+    };
+    Ok(Json(RevisionDiff::new(&old, &new, label.as_str())))
+}
+
+/// Brings back a revision, the version it replaces is kept as a revision too.
+pub async fn restore_revision(
+    user: User,
+    State(config): State<SiteConfig>,
+    Query(req): Query<RevisionRequest>,
+) -> StatusCode {
+    let pool = config.db_pool.unwrap();
+    let revision = match editable_revision(&pool, &user, req.id).await {
+        Ok(revision) => revision,
+        Err(status) => return status,
+    };
+    if !can_set_status(&user, &revision.status) {
+        return StatusCode::FORBIDDEN;
+    }
+    match restore(&pool, &revision, &user.username, &config.sanitize).await {
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            error!("Error while restoring a revision: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn restore(
+    pool: &SqlitePool,
+    revision: &PostRevision,
+    editor: &str,
+    policy: &SanitizeConfig,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    record_revision(&mut tx, revision.post, editor).await?;
+    let status = revision.status.to_string();
+    let format = revision.format.to_string();
+    query!(
+        "UPDATE posts SET name = ?1, content = ?2, status = ?3, format = ?4 WHERE id = ?5",
+        revision.name,
+        revision.content,
+        status,
+        format,
+        revision.post
+    )
+    .execute(&mut *tx)
+    .await?;
+    set_post_tags(&mut tx, revision.post, &revision.tags).await?;
+    cache_rendered(&mut tx, revision.post, policy).await?;
+    tx.commit().await
+}
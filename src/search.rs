@@ -0,0 +1,210 @@
+//! Full-text search over posts with sqlite's fts5.
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+};
+use log::error;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_scalar, SqliteConnection, SqlitePool};
+
+use crate::{
+    auth::user::User,
+    config::SiteConfig,
+    post::{list_response, list_viewer, query_posts, ListFormat, Post, MAX_PER_PAGE},
+    render::text,
+};
+
+/// Keeps the text of a post in the search index, `text` is its rendered html without tags.
+pub async fn index_post(
+    conn: &mut SqliteConnection,
+    post: i64,
+    text: &str,
+) -> Result<(), sqlx::Error> {
+    query!("DELETE FROM posts_search WHERE rowid = ?", post)
+        .execute(&mut *conn)
+        .await?;
+    query!(
+        "INSERT INTO posts_search(rowid, name, body) SELECT id, name, ?2 FROM posts WHERE id = ?1",
+        post,
+        text
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Adds the posts that were rendered before there was a search index to it.
+pub async fn index_unsearched_posts(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let posts = query!(
+        r#"SELECT id, rendered AS "rendered!" FROM posts
+        WHERE rendered IS NOT NULL AND id NOT IN (SELECT rowid FROM posts_search)"#
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut conn = pool.acquire().await?;
+    for post in posts.iter() {
+        index_post(&mut conn, post.id, &text(&post.rendered)).await?;
+    }
+    Ok(())
+}
+
+/// Turns what was typed into an fts5 query that matches posts with all of the words, the
+/// last one also as the start of a word since it may still be being typed. Nothing in it
+/// is fts5 syntax, so it can't be malformed.
+fn match_query(q: &str) -> String {
+    let words: Vec<String> = q
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect();
+    match words.is_empty() {
+        // An empty phrase, which matches nothing
+        true => "\"\"".to_string(),
+        false => format!("{}*", words.join(" ")),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SearchRequest {
+    /// Every post is listed, newest first, when it is empty
+    #[serde(default)]
+    pub q: String,
+    #[serde(default = "one")]
+    pub page: i64,
+    #[serde(default = "default_per_page")]
+    pub per_page: i64,
+    /// Defaults to html for htmx requests and json otherwise
+    pub format: Option<ListFormat>,
+    /// The template html responses are rendered with
+    #[serde(default = "default_search_template")]
+    pub template: String,
+}
+
+fn one() -> i64 {
+    1
+}
+
+fn default_per_page() -> i64 {
+    10
+}
+
+pub(crate) fn default_search_template() -> String {
+    "data/search_results".to_string()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    pub post: Post,
+    /// Html of where the words were found in the post, they are in `<mark>`
+    pub snippet: String,
+    pub url: String,
+}
+
+/// Matching posts, the best matches first.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SearchResults {
+    pub q: String,
+    pub posts: Vec<SearchHit>,
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
+    /// Query string of the next page, for templates to load more with
+    pub next: Option<String>,
+}
+
+/// Searches the names and content of the posts the user may see, trashed ones are left out.
+/// Htmx callers get the results rendered with the `template` of the request, which public
+/// sites can use to render results with their own templates.
+pub async fn search_posts(
+    State(config): State<SiteConfig>,
+    user: Option<User>,
+    headers: HeaderMap,
+    Query(mut req): Query<SearchRequest>,
+) -> Result<Response, StatusCode> {
+    req.page = req.page.max(1);
+    req.per_page = req.per_page.clamp(1, MAX_PER_PAGE);
+    let results = match find_posts(&config, &req, list_viewer(user)).await {
+        Ok(results) => results,
+        Err(e) => {
+            error!("Error while searching posts: {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    list_response(&config, &headers, req.format, &req.template, &results)
+}
+
+async fn find_posts(
+    config: &SiteConfig,
+    req: &SearchRequest,
+    viewer: Option<String>,
+) -> Result<SearchResults, sqlx::Error> {
+    let pool = config.db_pool.as_ref().unwrap();
+    let everything = req.q.trim().is_empty();
+    let matching = match_query(&req.q);
+    let offset = (req.page - 1) * req.per_page;
+    // Names weigh more than the content
+    let hits = query!(
+        r#"SELECT posts.id, COALESCE(hits.snippet, '') AS "snippet!: String" FROM posts
+        LEFT JOIN (
+            SELECT rowid, snippet(posts_search, 1, '<mark>', '</mark>', '…', 24) AS snippet,
+            bm25(posts_search, 10.0, 1.0) AS rank FROM posts_search WHERE posts_search MATCH ?1
+        ) AS hits ON hits.rowid = posts.id
+        WHERE (?2 OR hits.rowid IS NOT NULL) AND status != 'Trashed'
+        AND (?3 IS NULL OR status = 'Published' OR owner = ?3)
+        ORDER BY hits.rank, posts.date DESC, posts.id DESC LIMIT ?4 OFFSET ?5"#,
+        matching,
+        everything,
+        viewer,
+        req.per_page,
+        offset
+    )
+    .fetch_all(pool)
+    .await?;
+    let total = query_scalar!(
+        r#"SELECT COUNT(*) AS "total!: i64" FROM posts
+        WHERE (?2 OR id IN (SELECT rowid FROM posts_search WHERE posts_search MATCH ?1))
+        AND status != 'Trashed' AND (?3 IS NULL OR status = 'Published' OR owner = ?3)"#,
+        matching,
+        everything,
+        viewer
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let ids = serde_json::to_string(&hits.iter().map(|hit| hit.id).collect::<Vec<i64>>())
+        .unwrap_or_else(|_| "[]".to_string());
+    let mut posts = query_posts!("WHERE id IN (SELECT value FROM json_each(?))", ids)
+        .fetch_all(pool)
+        .await?;
+    let posts = hits
+        .into_iter()
+        .filter_map(|hit| {
+            let index = posts.iter().position(|post| post.id == hit.id)?;
+            let post = posts.swap_remove(index).sanitized(&config.sanitize);
+            Some(SearchHit {
+                url: config.post_url(&post),
+                post,
+                snippet: hit.snippet,
+            })
+        })
+        .collect();
+    let next = (offset + req.per_page < total)
+        .then(|| {
+            serde_urlencoded::to_string(SearchRequest {
+                page: req.page + 1,
+                ..req.clone()
+            })
+            .ok()
+        })
+        .flatten();
+    Ok(SearchResults {
+        q: req.q.clone(),
+        posts,
+        page: req.page,
+        per_page: req.per_page,
+        total,
+        next,
+    })
+}
@@ -1,134 +1,269 @@
+use anyhow::Context;
 use inquire::{Password, Select, Text};
-use serde::*;
+use serde::Serialize;
 use std::{
     collections::HashMap,
     fmt::Write,
     fs,
+    io::IsTerminal,
+    net::SocketAddr,
     sync::{Arc, RwLock},
 };
-use tower::ServiceBuilder;
 
 use axum::{
-    extract::{Path, State},
+    extract::{MatchedPath, OriginalUri, Path, State},
+    handler::Handler,
     http::{StatusCode, Uri},
     response::Html,
-    routing::{get, post},
+    routing::{delete, get, post, put},
     Router,
 };
 use log::error;
-use sqlx::{query, query_as, sqlite::SqlitePoolOptions, SqlitePool};
+use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
 use tinytemplate_async::TinyTemplate;
 use tower_http::services::ServeDir;
 
 use crate::{
     auth::{
-        admin::{create_privileged, Admin},
+        account_token::{forgot_password, request_verification, reset_password, verify_email},
+        admin::create_privileged,
+        capability::{
+            require, CanEditOwnPosts, CanManageSettings, CanManageUsers, CanModerateComments,
+        },
+        invite::generate_invite,
+        keys::SiteKeys,
+        session::{list_sessions, logout, logout_everywhere, revoke_session},
         sign_in::sign_in,
-        sign_up::{create_user, UserSignUp},
+        sign_up::{create_user, sign_up, UserSignUp},
         user::{get_user, Rank, User},
     },
-    config::{change_domain, PagePath, SiteConfig},
-    post::{create_post, delete_post, get_post, Post},
+    comment::{
+        approved_comments, create_comment, list_comments, moderate_comment, thread,
+        ThreadedComment,
+    },
+    config::{change_domain, list_templates_default, PagePath, SiteConfig},
+    feed::{atom_feed, rss_feed},
+    mail::SiteMailer,
+    migrate::{migrate, status, MigrationError, MIGRATIONS},
+    post::{
+        assign_missing_slugs, create_post, delete_post, get_post, list_posts, query_posts,
+        spawn_publisher, update_post, Post,
+    },
+    render::render_stale_posts,
+    revision::{diff_revision, get_revision, list_revisions, restore_revision},
+    search::{index_unsearched_posts, search_posts},
+    sitemap::{robots, sitemap},
+    tag::{get_category_posts, get_tag_posts, list_categories, list_tags, unpack_post_tags},
 };
 
-pub async fn init_site(path: String) {
-    let mut site_config: SiteConfig = match toml::from_str(
-        match fs::read_to_string(format!("{}/PeroxideSite.toml", path)) {
-            Ok(t) => t,
-            Err(e) => {
-                error!(
-                    "Failed to read from the config file {}/PeroxideSite.toml, Error: {}",
-                    path, e
-                );
-                return;
-            }
-        }
-        .as_str(),
-    ) {
-        Ok(t) => t,
-        Err(e) => {
-            error!(
-                "Failed to parse the config file {}/PeroxideSite.toml, Error: {}",
-                path, e
-            );
-            return;
-        }
-    };
-    site_config.site_path = path.clone();
-    site_config.templates = Arc::from(RwLock::new(setup_templates(
-        &site_config.routes,
-        path.clone(),
-    )));
+/// Reads the config of the site at `path`, without its keys, mailer or database.
+pub fn read_site_config(path: &str) -> anyhow::Result<SiteConfig> {
+    let config = fs::read_to_string(format!("{path}/PeroxideSite.toml"))
+        .with_context(|| format!("Failed to read from the config file {path}/PeroxideSite.toml"))?;
+    let mut site_config: SiteConfig = toml::from_str(&config)
+        .with_context(|| format!("Failed to parse the config file {path}/PeroxideSite.toml"))?;
+    site_config.site_path = path.to_string();
+    Ok(site_config)
+}
+
+/// Reads the config of the site at `path` and connects to its database, leaving the schema
+/// of the database as it is. The keys of the site are made when it has none yet.
+pub async fn open_site(path: &str) -> anyhow::Result<SiteConfig> {
+    let mut site_config = read_site_config(path)?;
+    site_config.keys = SiteKeys::load_or_generate(path)?;
+    site_config.mailer = SiteMailer::new(&site_config.mail, path)?;
     let db_conn_url = format!("sqlite://{}/{}", path, site_config.db_filename);
     log::info!("Beginning Connection to {db_conn_url}");
-    let pool: SqlitePool = {
-        match SqlitePoolOptions::new()
-            .max_connections(50)
-            .connect(db_conn_url.as_str())
-            .await
-        {
-            Ok(t) => t,
-            Err(e) => {
-                error!(
-                    "Failed to connect to the sqlite database at {}, Error: {}",
-                    db_conn_url, e
-                );
-                return;
-            }
-        }
+    let pool = SqlitePoolOptions::new()
+        .max_connections(50)
+        .connect(db_conn_url.as_str())
+        .await
+        .with_context(|| format!("Failed to connect to the sqlite database at {db_conn_url}"))?;
+    site_config.db_pool = Some(pool);
+    Ok(site_config)
+}
+
+/// The address sites are served at unless they are given another.
+pub const DEFAULT_ADDRESS: &str = "0.0.0.0:3000";
+
+/// The page new sites start out with.
+const INDEX_TEMPLATE: &str = r##"<!DOCTYPE html>
+<html>
+
+<head>
+  <meta charset="utf-8">
+  <title>Peroxide</title>
+  <link rel="stylesheet" href="https://cdn.jsdelivr.net/npm/@picocss/pico@next/css/pico.min.css">
+  <script src="https://unpkg.com/htmx.org@1.9.10"></script>
+</head>
+
+<body>
+  <main class="container">
+    <h1>A new Peroxide site</h1>
+    <input type="search" name="q" placeholder="Search" hx-get="/api/search"
+      hx-trigger="input changed delay:300ms, search" hx-target="#results">
+    <section id="results" hx-get="/api/search" hx-trigger="load"></section>
+  </main>
+</body>
+
+</html>
+"##;
+
+/// Creates a site at `path` with a config, a page and an up to date database.
+pub async fn create_site(path: &str, domain: String, db_filename: String) -> anyhow::Result<SiteConfig> {
+    if fs::metadata(format!("{path}/PeroxideSite.toml")).is_ok() {
+        anyhow::bail!("There already is a site at {path}");
+    }
+    for dir in ["templates", "static"] {
+        fs::create_dir_all(format!("{path}/{dir}"))
+            .with_context(|| format!("Failed to create {path}/{dir}"))?;
+    }
+    fs::write(format!("{path}/templates/index.html"), INDEX_TEMPLATE)
+        .with_context(|| format!("Failed to write {path}/templates/index.html"))?;
+    let db_conn_url = format!("sqlite://{path}/{db_filename}?mode=rwc");
+    let pool = SqlitePoolOptions::new()
+        .connect(db_conn_url.as_str())
+        .await
+        .with_context(|| format!("Failed to create the sqlite database at {db_conn_url}"))?;
+    setup_db(&pool).await?;
+    let config = SiteConfig {
+        db_filename,
+        db_pool: Some(pool),
+        domain,
+        routes: HashMap::from([(
+            "/".to_string(),
+            PagePath {
+                path: "index.html".to_string(),
+                template: None,
+                permalink: None,
+            },
+        )]),
+        site_path: path.to_string(),
+        templates: Default::default(),
+        create_user: false,
+        sanitize: Default::default(),
+        feed: Default::default(),
+        robots: Default::default(),
+        mail: Default::default(),
+        registration: Default::default(),
+        list_templates: list_templates_default(),
+        forwarded_for: None,
+        keys: SiteKeys::load_or_generate(path)?,
+        mailer: Default::default(),
     };
+    config
+        .save()
+        .with_context(|| format!("Failed to write {path}/PeroxideSite.toml"))?;
+    Ok(config)
+}
 
-    match query!(
-        "CREATE TABLE IF NOT EXISTS users(
-        	salt blob unique not null,
-        	name text not null,
-        	username text unique not null primary key,
-        	profile_pic text,
-        	sh_pass blob not null,
-        	email text not null unique,
-            rank text not null
-        ) STRICT"
-    )
-    .execute(&pool)
-    .await
-    {
-        Ok(t) => t,
+/// Whether posts can be found by the permalinks a pattern makes.
+fn valid_permalink(pattern: &str) -> bool {
+    let segments: Vec<&str> = pattern.split('/').collect();
+    pattern.starts_with('/') && (segments.contains(&":slug") || segments.contains(&":id"))
+}
+
+/// What is wrong with the site at `path`, from its config to its templates and database.
+/// Nothing is written, keys the site doesn't have yet are left to be made when it is opened.
+pub async fn check_site(path: &str) -> Vec<String> {
+    let config = match read_site_config(path) {
+        Ok(config) => config,
+        Err(e) => return vec![format!("{:#}", e)],
+    };
+    let mut problems = Vec::new();
+    if let Err(e) = SiteKeys::load(path) {
+        problems.push(format!("{:#}", e));
+    }
+    let mut routes: Vec<(&String, &PagePath)> = config.routes.iter().collect();
+    routes.sort_by_key(|(route, _)| *route);
+    for (route, page) in routes {
+        let templates = std::iter::once(&page.path).chain(page.template.iter());
+        for template in templates {
+            let file = format!("{path}/templates/{template}");
+            let compiled = fs::read_to_string(&file)
+                .map_err(|e| e.to_string())
+                .and_then(|content| {
+                    TinyTemplate::new()
+                        .add_template(template.clone(), content)
+                        .map_err(|e| e.to_string())
+                });
+            if let Err(e) = compiled {
+                problems.push(format!("The template {file} of {route} is broken: {e}"));
+            }
+        }
+        if let Some(pattern) = page.permalink_pattern(route) {
+            if !valid_permalink(&pattern) {
+                problems.push(format!(
+                    "The permalink {pattern} of {route} needs to start with / and have a :slug or an :id"
+                ));
+            }
+        }
+    }
+    let db_conn_url = format!("sqlite://{}/{}?mode=ro", path, config.db_filename);
+    let pool = match SqlitePoolOptions::new().connect(db_conn_url.as_str()).await {
+        Ok(pool) => pool,
         Err(e) => {
-            error!(
-                "Failed to create the database users at {}, Error: {}",
-                db_conn_url, e
-            );
-            return;
+            problems.push(format!(
+                "Failed to open the sqlite database at {db_conn_url}: {e}"
+            ));
+            return problems;
         }
     };
+    match status(&pool).await {
+        Ok(status) => {
+            for migration in status.iter() {
+                match (migration.applied, MIGRATIONS.iter().any(|m| m.version == migration.version)) {
+                    (None, _) => problems.push(format!(
+                        "The migration {} {} is pending",
+                        migration.version, migration.name
+                    )),
+                    (Some(_), false) => problems.push(format!(
+                        "The migration {} {} is from a newer version",
+                        migration.version, migration.name
+                    )),
+                    _ => {}
+                }
+            }
+        }
+        Err(e) => problems.push(format!("Failed to read the migrations: {e}")),
+    }
+    problems
+}
 
-    match query!(
-        "CREATE TABLE IF NOT EXISTS posts(
-            id INTEGER NOT NULL PRIMARY KEY,
-            name TEXT NOT NULL,
-            content TEXT NOT NULL,
-            date INTEGER NOT NULL DEFAULT (unixepoch(CURRENT_TIMESTAMP)),
-            tags BLOB NOT NULL DEFAULT X'',
-            status TEXT NOT NULL DEFAULT 'Draft',
-            owner TEXT NOT NULL,
-            FOREIGN KEY(owner) REFERENCES users(username)
-        ) STRICT"
-    )
-    .execute(&pool)
-    .await
-    {
+pub async fn init_site(path: String) {
+    let mut site_config = match open_site(&path).await {
         Ok(t) => t,
         Err(e) => {
-            error!(
-                "Failed to create the database posts at {}, Error: {}",
-                db_conn_url, e
-            );
+            error!("{:#}", e);
             return;
         }
     };
-    site_config.db_pool = Some(pool);
+    site_config.templates = Arc::from(RwLock::new(setup_templates(
+        &site_config.routes,
+        path.clone(),
+    )));
+    let pool = site_config.db_pool.clone().unwrap();
+    if let Err(e) = setup_db(&pool).await {
+        error!(
+            "Failed to migrate the database of {}, Error: {}",
+            path, e
+        );
+        return;
+    }
+    if let Err(e) = render_stale_posts(&pool, &site_config.sanitize).await {
+        error!(
+            "Failed to render the posts of {}, Error: {}",
+            path, e
+        );
+        return;
+    }
+    spawn_publisher(pool);
     let app = setup_routes(&site_config);
-    if site_config.create_user {
+    if site_config.create_user && !std::io::stdin().is_terminal() {
+        log::warn!(
+            "There is no terminal to create the user of {path} on, add one with: peroxide user add --site {path}"
+        );
+    } else if site_config.create_user {
         log::info!("Creating a user for the site: {}", site_config.site_path);
         site_config.create_user = false;
         let name = Text::new("Enter the display name: ")
@@ -141,7 +276,7 @@ pub async fn init_site(path: String) {
             .unwrap();
         let pass = Password::new("Enter the password: ").prompt().unwrap();
         let email = Text::new("Enter the mail: ").prompt().unwrap();
-        let rank = Select::new("Select the rank: ", vec![Rank::Admin, Rank::User])
+        let rank = Select::new("Select the rank: ", Rank::ALL.into_iter().rev().collect())
             .prompt()
             .unwrap();
 
@@ -172,6 +307,8 @@ pub async fn init_site(path: String) {
         }
     };
 
+    // Sessions keep the address they were started from
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
     match axum::serve(listener, app).await {
         Ok(t) => t,
         Err(e) => {
@@ -179,11 +316,20 @@ pub async fn init_site(path: String) {
                 "Error occured while serving the website {}, Error: {}",
                 site_config.domain, e
             );
-            return;
         }
     };
 }
 
+/// Brings the database up to the schema of this version, along with the data that older
+/// versions stored differently.
+pub async fn setup_db(pool: &SqlitePool) -> Result<(), MigrationError> {
+    migrate(pool, None).await?;
+    unpack_post_tags(pool).await?;
+    assign_missing_slugs(pool).await?;
+    index_unsearched_posts(pool).await?;
+    Ok(())
+}
+
 fn increment(
     value: &serde_json::Value,
     string: &mut String,
@@ -202,30 +348,57 @@ fn increment(
     Ok(())
 }
 
+/// Reads a unix timestamp for the date formatters.
+fn timestamp(
+    value: &serde_json::Value,
+) -> tinytemplate_async::error::Result<chrono::DateTime<chrono::Utc>> {
+    value
+        .as_i64()
+        .and_then(|num| chrono::DateTime::from_timestamp(num, 0))
+        .ok_or_else(|| tinytemplate_async::error::Error::ParseError {
+            msg: format!(
+                "Could not format the non timestamp input as a date: {}",
+                value
+            ),
+            line: 0,
+            column: 0,
+        })
+}
+
 fn human_date(
     value: &serde_json::Value,
     string: &mut String,
 ) -> tinytemplate_async::error::Result<()> {
-    let num = match value {
-        serde_json::Value::Number(num) => num,
-        a => {
-            return Err(tinytemplate_async::error::Error::ParseError {
-                msg: format!("Could not increment the non number input: {}", a).to_string(),
-                line: 0,
-                column: 0,
-            })
-        }
-    };
-    let post_time = chrono::DateTime::from_timestamp(num.as_i64().unwrap(), 0).unwrap();
+    let post_time = timestamp(value)?;
     string
         .write_str(post_time.format("%H:%M:%S %d %b %Y").to_string().as_str())
         .unwrap();
     Ok(())
 }
+
+/// Dates as rss wants them, like `Thu, 07 Mar 2024 12:00:00 +0000`.
+fn rfc822_date(
+    value: &serde_json::Value,
+    string: &mut String,
+) -> tinytemplate_async::error::Result<()> {
+    string.push_str(timestamp(value)?.to_rfc2822().as_str());
+    Ok(())
+}
+
+/// Dates as atom and html want them, like `2024-03-07T12:00:00+00:00`.
+fn rfc3339_date(
+    value: &serde_json::Value,
+    string: &mut String,
+) -> tinytemplate_async::error::Result<()> {
+    string.push_str(timestamp(value)?.to_rfc3339().as_str());
+    Ok(())
+}
 fn setup_templates(routes: &HashMap<String, PagePath>, site_path: String) -> TinyTemplate {
     let mut templates = TinyTemplate::new();
     templates.add_formatter("increment".to_string(), increment);
     templates.add_formatter("human_date".to_string(), human_date);
+    templates.add_formatter("rfc822_date".to_string(), rfc822_date);
+    templates.add_formatter("rfc3339_date".to_string(), rfc3339_date);
     let admin_template = fs::read_to_string("data/admin.templ.html").unwrap();
     templates
         .add_template("admin".to_string(), admin_template)
@@ -271,114 +444,188 @@ fn setup_templates(routes: &HashMap<String, PagePath>, site_path: String) -> Tin
                 e,
             ),
         };
-        match path.template.clone() {
-            Some(template_path) => {
-                let content =
-                    fs::read_to_string(format!("{site_path}/templates/{template_path}")).unwrap();
-                templates
-                    .add_template(format!("pages/{name}.templ"), content)
-                    .unwrap();
-            }
-            None => {}
+        if let Some(template_path) = path.template.clone() {
+            let content =
+                fs::read_to_string(format!("{site_path}/templates/{template_path}")).unwrap();
+            templates
+                .add_template(format!("pages{name}.templ"), content)
+                .unwrap();
         }
     }
     templates
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct AdminPageTempl {
-    #[serde(default = "default_admin_page")]
-    path: String,
-    #[serde(skip_deserializing)]
-    content: Option<String>,
-    #[serde(skip_deserializing)]
-    user: User,
-}
-
-fn default_admin_page() -> String {
-    "blogs".to_string()
-}
-
 async fn serve_react(_user: User) -> Result<Html<String>, StatusCode> {
     fs::read_to_string("admin_panel/index.html")
-        .map(|content| Html(content))
+        .map(Html)
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
 
-fn setup_routes(config: &SiteConfig) -> Router {
+/// Routes that need more than being signed in are guarded by the capability they need, the
+/// handlers check what depends on the post or comment itself.
+pub fn setup_routes(config: &SiteConfig) -> Router {
+    let writer = || require::<CanEditOwnPosts>(config);
     let router = Router::new()
         .nest(
             "/admin",
             Router::new()
                 .nest_service("/assets", ServeDir::new("admin/assets"))
-                .route("/", get(serve_react)),
+                .route("/", get(serve_react.layer(writer()))),
         )
         .nest(
             "/api",
             Router::new()
-                .route("/post", get(get_post).post(create_post).delete(delete_post))
-                .route("/user", get(get_user).put(sign_in))
+                .route(
+                    "/post",
+                    get(get_post)
+                        .post(create_post.layer(writer()))
+                        .put(update_post.layer(writer()))
+                        .patch(update_post.layer(writer()))
+                        .delete(delete_post.layer(writer())),
+                )
+                .route("/post/revisions", get(list_revisions.layer(writer())))
+                .route("/post/revision", get(get_revision.layer(writer())))
+                .route("/post/revision/diff", get(diff_revision.layer(writer())))
+                .route(
+                    "/post/revision/restore",
+                    post(restore_revision.layer(writer())),
+                )
+                .route("/posts", get(list_posts))
+                .route("/search", get(search_posts))
+                .route("/comment", get(list_comments).post(create_comment))
+                .route("/tags", get(list_tags))
+                .route("/tag", get(get_tag_posts))
+                .route("/categories", get(list_categories))
+                .route("/category", get(get_category_posts))
+                .route("/user", get(get_user).put(sign_in).post(sign_up))
+                .route("/user/logout", post(logout))
+                .route("/user/logout/all", post(logout_everywhere))
+                .route("/user/sessions", get(list_sessions))
+                .route("/user/session", delete(revoke_session))
+                .route("/user/password/forgot", post(forgot_password))
+                .route("/user/password/reset", post(reset_password))
+                .route(
+                    "/user/email/verify",
+                    get(verify_email).post(request_verification),
+                )
                 .nest(
                     "/admin",
                     Router::new()
-                        .route("/user", post(create_user))
-                        .route("/settings/domain", post(change_domain))
-                        .layer(
-                            ServiceBuilder::new()
-                                .layer(axum::middleware::from_extractor::<Admin>()),
+                        .route(
+                            "/user",
+                            post(create_user.layer(require::<CanManageUsers>(config))),
+                        )
+                        .route(
+                            "/invite",
+                            post(generate_invite.layer(require::<CanManageUsers>(config))),
+                        )
+                        .route(
+                            "/settings/domain",
+                            post(change_domain.layer(require::<CanManageSettings>(config))),
+                        )
+                        .route(
+                            "/comment",
+                            put(moderate_comment.layer(require::<CanModerateComments>(config))),
                         ),
                 ),
         )
+        .route("/feed.xml", get(rss_feed))
+        .route("/atom.xml", get(atom_feed))
+        .route("/sitemap.xml", get(sitemap))
+        .route("/robots.txt", get(robots))
         .nest_service(
             "/static",
             ServeDir::new(format!("{}/static", config.site_path)),
         );
     let mut site_router = Router::new();
     for (route, path) in config.routes.iter() {
-        match path.template.clone() {
-            Some(_) => {
-                site_router = site_router.route(
-                    format!("{}/:page", route).as_str(),
-                    get(handle_page_templated),
-                )
-            }
-            None => site_router = site_router.route(route, get(handle_page)),
+        site_router = site_router.route(route, get(handle_page));
+        let Some(pattern) = path.permalink_pattern(route) else {
+            continue;
+        };
+        if valid_permalink(&pattern) {
+            site_router = site_router.route(pattern.as_str(), get(handle_post_page));
+        } else {
+            error!("The permalink {pattern} of {route} needs a :slug or an :id");
         }
     }
     router.nest("", site_router).with_state(config.clone())
 }
 
-pub async fn handle_page_templated(
-    uri: Uri,
-    Path(page): Path<String>,
+/// What the templates of routes render posts with, the post is in `rendered`, the comments
+/// can be rendered with `{{ call data/comments with @root }}` and the table of contents with
+/// `{{ call data/toc with toc }}`.
+#[derive(Serialize)]
+struct PostPage {
+    #[serde(flatten)]
+    post: Post,
+    /// Path of the post under the route it is rendered for
+    permalink: String,
+    comments: Vec<ThreadedComment>,
+}
+
+/// Renders a post with the template of the route whose permalink pattern matched.
+pub async fn handle_post_page(
+    matched: MatchedPath,
+    OriginalUri(uri): OriginalUri,
+    Path(params): Path<HashMap<String, String>>,
+    user: Option<User>,
     State(config): State<SiteConfig>,
 ) -> Result<Html<String>, StatusCode> {
-    let path = match match uri.path().strip_prefix('/') {
-        Some(s) => s,
-        None => "",
-    }
-    .strip_suffix(page.as_str())
-    {
-        Some(s) => s,
-        None => "",
+    let (route, pattern) = config
+        .routes
+        .iter()
+        .find_map(|(route, path)| {
+            path.permalink_pattern(route)
+                .filter(|pattern| pattern == matched.as_str())
+                .map(|pattern| (route, pattern))
+        })
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let id = match params.get("id") {
+        Some(id) => Some(id.parse::<i64>().map_err(|_| StatusCode::NOT_FOUND)?),
+        None => None,
     };
-    let name = format!("pages/{}.templ", path);
+    let slug = params.get("slug");
+    let pool = config.db_pool.clone().unwrap();
 
-    let post = match query_as!(
-        Post,
-        "SELECT id, name, content, date, tags, owner, status FROM posts WHERE name IS ?",
-        name
+    let post = match query_posts!(
+        "WHERE (?1 IS NULL OR slug = ?1) AND (?2 IS NULL OR id = ?2)",
+        slug,
+        id
     )
-    .fetch_one(&config.db_pool.unwrap())
+    .fetch_one(&pool)
     .await
     {
         Ok(post) => post,
+        Err(sqlx::Error::RowNotFound) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            log::error!("{}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    // The rest of the path, like the date, has to be the post's too
+    let permalink = post.permalink(&pattern);
+    if permalink != uri.path() || !post.visible_to(user.as_ref()) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let comments = match approved_comments(&pool, post.id).await {
+        Ok(comments) => thread(comments),
         Err(e) => {
             log::error!("{}", e);
-            return Err(StatusCode::NOT_FOUND);
+            Vec::new()
         }
     };
-    match config.templates.read().unwrap().render(path, &post) {
+    let page = PostPage {
+        post: post.sanitized(&config.sanitize),
+        permalink,
+        comments,
+    };
+    match config
+        .templates
+        .read()
+        .unwrap()
+        .render(format!("pages{route}.templ").as_str(), &page)
+    {
         Ok(x) => Ok(Html(x)),
         Err(e) => {
             log::error!("{e}");
@@ -391,10 +638,7 @@ pub async fn handle_page(
     uri: Uri,
     State(config): State<SiteConfig>,
 ) -> Result<Html<String>, StatusCode> {
-    let path = match uri.path().strip_prefix('/') {
-        Some(s) => s,
-        None => "",
-    };
+    let path = uri.path().strip_prefix('/').unwrap_or_default();
     let name = format!("pages/{}", path);
 
     match config
@@ -0,0 +1,163 @@
+//! The sitemap and robots.txt that crawlers find the pages of a site with.
+
+use std::fmt::Write;
+
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use chrono::DateTime;
+use log::error;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, SqlitePool};
+
+use crate::{config::SiteConfig, feed::escape, post::permalink};
+
+/// The most urls a sitemap may have, bigger sites get split up behind a sitemap index.
+pub const SITEMAP_URLS: i64 = 50_000;
+
+#[derive(Serialize, Deserialize)]
+pub struct SitemapRequest {
+    /// One of the sitemaps the index points at, counting from 1
+    page: Option<i64>,
+}
+
+struct SitemapUrl {
+    loc: String,
+    /// As a unix timestamp
+    lastmod: Option<i64>,
+}
+
+/// Whether crawlers may visit the path, by the `disallow` rules of robots.txt.
+fn crawlable(config: &SiteConfig, path: &str) -> bool {
+    !config
+        .robots
+        .disallow
+        .iter()
+        .any(|prefix| path.starts_with(prefix.as_str()))
+}
+
+/// The published posts that crawlers may visit, with when they were last changed by the
+/// latest of their date and their revisions.
+async fn sitemap_posts(
+    config: &SiteConfig,
+    pool: &SqlitePool,
+    pattern: &str,
+) -> Result<Vec<SitemapUrl>, sqlx::Error> {
+    let base_url = config.base_url();
+    Ok(query!(
+        r#"SELECT id, COALESCE(slug, '') AS "slug!: String", date, MAX(date, COALESCE(
+            (SELECT MAX(date) FROM post_revisions WHERE post_revisions.post = posts.id), 0
+        )) AS "lastmod!: i64" FROM posts WHERE status = 'Published' ORDER BY id"#
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|post| {
+        (
+            permalink(pattern, post.id, &post.slug, post.date),
+            post.lastmod,
+        )
+    })
+    .filter(|(path, _)| crawlable(config, path))
+    .map(|(path, lastmod)| SitemapUrl {
+        loc: format!("{base_url}{path}"),
+        lastmod: Some(lastmod),
+    })
+    .collect())
+}
+
+fn lastmod(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .to_rfc3339()
+}
+
+fn xml(body: String) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+        body,
+    )
+}
+
+/// Lists the routes of the site that crawlers may visit and the permalinks of the published
+/// posts. Once there are more than [`SITEMAP_URLS`] it is an index of sitemaps with that many
+/// urls each, which are at `?page=`.
+pub async fn sitemap(
+    State(config): State<SiteConfig>,
+    Query(req): Query<SitemapRequest>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let base_url = config.base_url();
+    let mut routes: Vec<&String> = config
+        .routes
+        .keys()
+        .filter(|route| crawlable(&config, route))
+        .collect();
+    routes.sort();
+    // The routes come first, then the posts
+    let mut urls: Vec<SitemapUrl> = routes
+        .into_iter()
+        .map(|route| SitemapUrl {
+            loc: format!("{base_url}{route}"),
+            lastmod: None,
+        })
+        .collect();
+    if let Some(pattern) = config.permalink_pattern() {
+        let pool = config.db_pool.clone().unwrap();
+        let posts = sitemap_posts(&config, &pool, &pattern).await.map_err(|e| {
+            error!("Error while fetching posts for the sitemap: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        urls.extend(posts);
+    }
+    let total = urls.len() as i64;
+    let pages = (total + SITEMAP_URLS - 1) / SITEMAP_URLS;
+
+    let mut body = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let page = match req.page {
+        None if pages > 1 => {
+            body.push_str("<sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+            for page in 1..=pages {
+                let _ = writeln!(
+                    body,
+                    "<sitemap><loc>{}</loc></sitemap>",
+                    escape(&format!("{base_url}/sitemap.xml?page={page}"))
+                );
+            }
+            body.push_str("</sitemapindex>\n");
+            return Ok(xml(body));
+        }
+        None => 1,
+        Some(page) if page >= 1 && page <= pages.max(1) => page,
+        Some(_) => return Err(StatusCode::NOT_FOUND),
+    };
+
+    let urls = urls
+        .iter()
+        .skip(((page - 1) * SITEMAP_URLS) as usize)
+        .take(SITEMAP_URLS as usize);
+    body.push_str("<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+    for url in urls {
+        let _ = write!(body, "<url><loc>{}</loc>", escape(&url.loc));
+        if let Some(date) = url.lastmod {
+            let _ = write!(body, "<lastmod>{}</lastmod>", lastmod(date));
+        }
+        body.push_str("</url>\n");
+    }
+    body.push_str("</urlset>\n");
+    Ok(xml(body))
+}
+
+/// The rules from the `[robots]` section of the config, along with where the sitemap is.
+pub async fn robots(State(config): State<SiteConfig>) -> impl IntoResponse {
+    let mut body = String::from("User-agent: *\n");
+    for path in config.robots.allow.iter() {
+        let _ = writeln!(body, "Allow: {path}");
+    }
+    for path in config.robots.disallow.iter() {
+        let _ = writeln!(body, "Disallow: {path}");
+    }
+    let _ = writeln!(body, "\nSitemap: {}/sitemap.xml", config.base_url());
+    ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], body)
+}
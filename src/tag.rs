@@ -0,0 +1,199 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use log::error;
+use serde::{Deserialize, Serialize};
+use slug::slugify;
+use sqlx::{query, query_as, query_scalar, SqliteConnection, SqlitePool};
+
+use crate::{
+    config::SiteConfig,
+    post::{query_posts, Post, VecStr},
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Tag {
+    pub id: i64,
+    pub name: String,
+    pub slug: String,
+    /// Number of published posts with the tag
+    pub count: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Category {
+    pub id: i64,
+    pub name: String,
+    pub slug: String,
+    pub description: String,
+    pub parent: Option<i64>,
+    /// Number of published posts directly in the category
+    pub count: i64,
+}
+
+/// Replaces the tags of a post with the given names, tags that don't exist yet are created.
+pub async fn set_post_tags(
+    conn: &mut SqliteConnection,
+    post: i64,
+    tags: &VecStr,
+) -> Result<(), sqlx::Error> {
+    query!("DELETE FROM post_tags WHERE post = ?", post)
+        .execute(&mut *conn)
+        .await?;
+    for name in tags.data.iter() {
+        let name = name.trim();
+        let slug = slugify(name);
+        if slug.is_empty() {
+            continue;
+        }
+        query!(
+            "INSERT INTO tags(name, slug) VALUES(?1, ?2) ON CONFLICT(slug) DO NOTHING",
+            name,
+            slug
+        )
+        .execute(&mut *conn)
+        .await?;
+        query!(
+            "INSERT OR IGNORE INTO post_tags(post, tag) SELECT ?1, id FROM tags WHERE slug = ?2",
+            post,
+            slug
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Older versions packed the tags of a post into a `tags` column of `posts`,
+/// this moves them into the tags tables and drops the column.
+pub async fn unpack_post_tags(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let packed = query_scalar!(
+        r#"SELECT COUNT(*) AS "count!: i64" FROM pragma_table_info('posts') WHERE name = 'tags'"#
+    )
+    .fetch_one(pool)
+    .await?;
+    if packed == 0 {
+        return Ok(());
+    }
+    let mut tx = pool.begin().await?;
+    // The column is gone from the current schema, so these can't be checked at compile time
+    let posts: Vec<(i64, VecStr)> = sqlx::query_as("SELECT id, tags FROM posts")
+        .fetch_all(&mut *tx)
+        .await?;
+    for (id, tags) in posts.iter() {
+        set_post_tags(&mut tx, *id, tags).await?;
+    }
+    sqlx::query("ALTER TABLE posts DROP COLUMN tags")
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    log::info!("Unpacked the tags of {} posts", posts.len());
+    Ok(())
+}
+
+pub async fn list_tags(State(config): State<SiteConfig>) -> Result<Json<Vec<Tag>>, StatusCode> {
+    match query_as!(
+        Tag,
+        r#"SELECT id, name, slug, (
+            SELECT COUNT(*) FROM post_tags JOIN posts ON posts.id = post_tags.post
+            WHERE post_tags.tag = tags.id AND posts.status = 'Published'
+        ) AS "count!: i64" FROM tags ORDER BY name"#
+    )
+    .fetch_all(&config.db_pool.unwrap())
+    .await
+    {
+        Ok(tags) => Ok(Json(tags)),
+        Err(e) => {
+            error!("Error while listing tags: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn list_categories(
+    State(config): State<SiteConfig>,
+) -> Result<Json<Vec<Category>>, StatusCode> {
+    match query_as!(
+        Category,
+        r#"SELECT id, name, slug, description, parent, (
+            SELECT COUNT(*) FROM post_categories JOIN posts ON posts.id = post_categories.post
+            WHERE post_categories.category = categories.id AND posts.status = 'Published'
+        ) AS "count!: i64" FROM categories ORDER BY name"#
+    )
+    .fetch_all(&config.db_pool.unwrap())
+    .await
+    {
+        Ok(categories) => Ok(Json(categories)),
+        Err(e) => {
+            error!("Error while listing categories: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TaxonomyPostsRequest {
+    slug: String,
+}
+
+/// Published posts with the tag, newest first.
+pub async fn get_tag_posts(
+    Query(req): Query<TaxonomyPostsRequest>,
+    State(config): State<SiteConfig>,
+) -> Result<Json<Vec<Post>>, StatusCode> {
+    match query_posts!(
+        "WHERE status = 'Published' AND id IN (
+            SELECT post_tags.post FROM post_tags JOIN tags ON tags.id = post_tags.tag WHERE tags.slug = ?
+        ) ORDER BY date DESC",
+        req.slug
+    )
+    .fetch_all(&config.db_pool.unwrap())
+    .await
+    {
+        Ok(posts) => Ok(Json(
+            posts
+                .into_iter()
+                .map(|post| post.sanitized(&config.sanitize))
+                .collect(),
+        )),
+        Err(e) => {
+            error!("Error while fetching posts by tag: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Published posts in the category or any of its subcategories, newest first.
+pub async fn get_category_posts(
+    Query(req): Query<TaxonomyPostsRequest>,
+    State(config): State<SiteConfig>,
+) -> Result<Json<Vec<Post>>, StatusCode> {
+    match query_posts!(
+        "WHERE status = 'Published' AND id IN (
+            SELECT post FROM post_categories WHERE category IN (
+                WITH RECURSIVE tree(id) AS (
+                    SELECT id FROM categories WHERE slug = ?
+                    UNION SELECT categories.id FROM categories JOIN tree ON categories.parent = tree.id
+                )
+                SELECT id FROM tree
+            )
+        ) ORDER BY date DESC",
+        req.slug
+    )
+    .fetch_all(&config.db_pool.unwrap())
+    .await
+    {
+        Ok(posts) => Ok(Json(
+            posts
+                .into_iter()
+                .map(|post| post.sanitized(&config.sanitize))
+                .collect(),
+        )),
+        Err(e) => {
+            error!("Error while fetching posts by category: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
@@ -1,26 +1,48 @@
+pub mod wxr;
+
+use std::fmt::Display;
 use std::str::FromStr;
 use std::sync::RwLock;
+use std::time::Duration;
 use std::{collections::HashMap, sync::Arc};
 
-use axum::http::Uri;
+use axum::{body::Bytes, http::Uri};
+use base64::{engine::general_purpose, Engine};
+use chrono::{DateTime, Utc};
+use rand::random;
 use reqwest::Client;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sqlx::{query, sqlite::SqlitePoolOptions, SqlitePool};
 use tinytemplate_async::TinyTemplate;
 
-use crate::config::SiteConfig;
+use crate::{
+    auth::{keys::SiteKeys, sign_up::UserSignUp, user::User},
+    comment::CommentStatus,
+    config::{list_templates_default, PagePath, SanitizeConfig, SiteConfig},
+    migrate::MigrationError,
+    post::{unique_slug, PostStatus},
+    render::{self, render_stale_posts, text},
+    site::{setup_db, DEFAULT_ADDRESS},
+};
+
+/// Username of the account imported posts without a known author are attributed to.
+const IMPORT_USER: &str = "wordpress";
 
 #[derive(Debug)]
+#[allow(dead_code)]
 pub struct WordpressSite {
     url: String,
     post: Vec<WordpressData>,
     page: Vec<WordpressData>,
     tags: Vec<WordpressTags>,
+    categories: Vec<WordpressCategory>,
     media: Vec<WordpressMedia>,
     users: Vec<WordpressUser>,
     comments: Vec<WordpressComment>,
 }
 
 #[derive(Debug, Deserialize)]
+#[allow(dead_code)]
 struct WordpressComment {
     id: isize,
     post: isize,
@@ -29,7 +51,8 @@ struct WordpressComment {
     author_name: String,
     author_url: String,
     date: String,
-    date_gmt: String,
+    #[serde(deserialize_with = "wordpress_date_format::deserialize")]
+    date_gmt: DateTime<Utc>,
     content: Content,
     link: String,
     status: WordpressStatus,
@@ -40,13 +63,13 @@ struct WordpressComment {
     links: TagLinks,
 }
 
-struct WordpressMediaUrls {}
-
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)]
 struct WordpressData {
     id: usize,
     date: String,
-    date_gmt: String,
+    #[serde(deserialize_with = "wordpress_date_format::deserialize")]
+    date_gmt: DateTime<Utc>,
     guid: Content,
     modified: String,
     modified_gmt: String,
@@ -72,6 +95,7 @@ struct WordpressData {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[allow(dead_code)]
 struct LinkHref {
     href: String,
     embeddable: Option<bool>,
@@ -81,25 +105,27 @@ struct LinkHref {
     templated: Option<bool>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[allow(dead_code)]
 struct Meta {
-    #[serde(rename = "_et_pb_use_builder")]
+    #[serde(rename = "_et_pb_use_builder", default)]
     et_pb_use_builder: String,
-    #[serde(rename = "_et_pb_old_content")]
+    #[serde(rename = "_et_pb_old_content", default)]
     et_pb_old_content: String,
-    #[serde(rename = "_et_gb_content_width")]
+    #[serde(rename = "_et_gb_content_width", default)]
     et_gb_content_width: String,
     footnotes: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[allow(dead_code)]
 struct Content {
     rendered: String,
     protected: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase", from = "String")]
 enum WordpressStatus {
     #[serde(rename_all = "lowercase")]
     Publish,
@@ -110,7 +136,7 @@ enum WordpressStatus {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase", from = "String")]
 enum WordpressRespType {
     Post,
     Page,
@@ -125,6 +151,7 @@ impl From<String> for WordpressRespType {
             "post" => Self::Post,
             "page" => Self::Page,
             "attachment" => Self::Attachment,
+            "comment" => Self::Comment,
             x => Self::Other(String::from(x)),
         }
     }
@@ -135,22 +162,182 @@ impl From<String> for WordpressStatus {
             "publish" => Self::Publish,
             "draft" => Self::Draft,
             "inherit" => Self::Inherit,
+            "approved" => Self::Approved,
             x => Self::Other(String::from(x)),
         }
     }
 }
 
+impl From<&WordpressStatus> for CommentStatus {
+    fn from(value: &WordpressStatus) -> Self {
+        match value {
+            WordpressStatus::Approved => Self::Approved,
+            WordpressStatus::Other(status) if status == "spam" => Self::Spam,
+            WordpressStatus::Other(status) if status == "trash" => Self::Rejected,
+            _ => Self::Pending,
+        }
+    }
+}
+
+impl From<&WordpressStatus> for PostStatus {
+    fn from(value: &WordpressStatus) -> Self {
+        match value {
+            WordpressStatus::Publish => Self::Published,
+            WordpressStatus::Other(status) => match status.as_str() {
+                "pending" => Self::Pending,
+                "future" => Self::Scheduled,
+                "private" => Self::Private,
+                "trash" => Self::Trashed,
+                _ => Self::Draft,
+            },
+            _ => Self::Draft,
+        }
+    }
+}
+
+async fn download(client: &Client, url: &str) -> Result<Bytes, reqwest::Error> {
+    client.get(url).send().await?.error_for_status()?.bytes().await
+}
+
+/// Maps a WordPress upload url to a path inside the static directory,
+/// `https://example.com/wp-content/uploads/2024/01/a.png` becomes `uploads/2024/01/a.png`.
+fn media_file_path(source_url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(source_url).ok()?;
+    let segments: Vec<&str> = url
+        .path_segments()?
+        .filter(|s| !s.is_empty() && *s != "." && *s != "..")
+        .skip_while(|s| *s == "wp-content")
+        .collect();
+    if segments.is_empty() {
+        return None;
+    }
+    Some(segments.join("/"))
+}
+
+/// Points the `src`, `href` and `srcset` attributes that refer to rehosted media at their
+/// local copies. Urls are compared whole, so others that only start like one are left alone.
+fn rehost_media(html: &str, media: &[(String, String)]) -> String {
+    let mut rehosted = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        let (text, tag) = rest.split_at(start);
+        rehosted.push_str(text);
+        let end = tag_end(tag);
+        rehost_tag(&tag[..end], media, &mut rehosted);
+        rest = &tag[end..];
+    }
+    rehosted.push_str(rest);
+    rehosted
+}
+
+/// Where the tag at the start of `html` ends, a `>` in a quoted attribute doesn't end it.
+fn tag_end(html: &str) -> usize {
+    let mut quote = None;
+    for (i, c) in html.char_indices().skip(1) {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), c) if open == c => quote = None,
+            (None, '>') => return i + 1,
+            _ => {}
+        }
+    }
+    html.len()
+}
+
+/// Copies a tag to `out` with the urls of its media attributes rehosted.
+fn rehost_tag(tag: &str, media: &[(String, String)], out: &mut String) {
+    let mut rest = tag;
+    while let Some(equals) = rest.find('=') {
+        let name = rest[..equals].trim_end();
+        let name = &name[name.rfind(char::is_whitespace).map_or(0, |i| i + 1)..];
+        let value = rest[equals + 1..].trim_start();
+        let value_start = rest.len() - value.len();
+        let (start, end) = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let length = value[1..].find(quote).unwrap_or(value.len() - 1);
+                (value_start + 1, value_start + 1 + length)
+            }
+            _ => {
+                let length = value
+                    .find(|c: char| c.is_whitespace() || c == '>')
+                    .unwrap_or(value.len());
+                (value_start, value_start + length)
+            }
+        };
+        out.push_str(&rest[..start]);
+        let value = &rest[start..end];
+        match name.to_ascii_lowercase().as_str() {
+            "src" | "href" => out.push_str(rehosted_url(value, media).unwrap_or(value)),
+            "srcset" => out.push_str(&rehost_srcset(value, media)),
+            _ => out.push_str(value),
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+}
+
+/// Rehosts the urls of a `srcset`, a list like `a-300x200.jpg 300w, a.jpg 1024w`.
+fn rehost_srcset(srcset: &str, media: &[(String, String)]) -> String {
+    srcset
+        .split(',')
+        .map(|candidate| {
+            let url = candidate.trim_start();
+            let url = &url[..url.find(char::is_whitespace).unwrap_or(url.len())];
+            match rehosted_url(url, media) {
+                Some(local) => candidate.replacen(url, local, 1),
+                None => candidate.to_string(),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// The local copy of a media url. WordPress links to resized copies of images like
+/// `a-300x200.jpg`, which are served from the copy of the original `a.jpg`.
+fn rehosted_url<'a>(url: &str, media: &'a [(String, String)]) -> Option<&'a str> {
+    let local = |url: &str| {
+        media
+            .iter()
+            .find(|(source, _)| source == url)
+            .map(|(_, local)| local.as_str())
+    };
+    local(url).or_else(|| {
+        let (stem, extension) = url.rsplit_once('.').filter(|(_, ext)| !ext.contains('/'))?;
+        let (original, size) = stem.rsplit_once('-')?;
+        let (width, height) = size.split_once('x')?;
+        let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+        match is_number(width) && is_number(height) {
+            true => local(&format!("{original}.{extension}")),
+            false => None,
+        }
+    })
+}
+
+/// Whether a slug can be used as a segment of a route and of the template file it is
+/// written to, so an imported page can't be written outside of the templates directory.
+fn is_safe_slug(slug: &str) -> bool {
+    !slug.is_empty() && slug != "." && slug != ".." && !slug.contains(['/', '\\'])
+}
+
+/// Escapes the text so TinyTemplate does not treat braces in it as template expressions.
+fn escape_template(text: &str) -> String {
+    text.replace('{', "\\{")
+}
+
+/// The title of a post as the plain text its name is stored as, WordPress hands them out
+/// as html with entities like `&#8217;`.
+fn title_text(html: &str) -> String {
+    text(html)
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", "\u{a0}")
+        .replace("&amp;", "&")
+}
+
 mod wordpress_date_format {
     use chrono::{DateTime, NaiveDateTime, Utc};
-    use serde::{Deserialize, Deserializer, Serializer};
-    const FORMAT: &'static str = "%Y-%M-%DT%H:%M:%S";
-    pub fn serialize<S>(date: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let s = format!("{}", date.format(FORMAT));
-        serializer.serialize_str(&s)
-    }
+    use serde::{Deserialize, Deserializer};
+    const FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
 
     pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
     where
@@ -163,6 +350,7 @@ mod wordpress_date_format {
 }
 
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)]
 struct WordpressTags {
     id: isize,
     count: isize,
@@ -177,143 +365,599 @@ struct WordpressTags {
 }
 
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)]
+struct WordpressCategory {
+    id: isize,
+    count: isize,
+    description: String,
+    link: String,
+    name: String,
+    slug: String,
+    taxonomy: String,
+    parent: isize,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[allow(dead_code)]
 struct TagLinks {
     #[serde(rename = "self")]
     inner: Vec<LinkHref>,
 }
 
+#[derive(Debug)]
 pub enum SiteSaveError {
     DirectoryCreateError,
     DirectoryReadError,
     InvalidUrl,
+    ConfigWriteError,
+    TemplateWriteError,
+    MediaWriteError,
+    UserCreateError,
+    DatabaseError(sqlx::Error),
+    MigrationError(MigrationError),
+}
+
+impl From<sqlx::Error> for SiteSaveError {
+    fn from(value: sqlx::Error) -> Self {
+        Self::DatabaseError(value)
+    }
+}
+
+impl From<MigrationError> for SiteSaveError {
+    fn from(value: MigrationError) -> Self {
+        Self::MigrationError(value)
+    }
 }
 
 impl WordpressSite {
-    pub fn save(&self, path: String) -> Result<SiteConfig, SiteSaveError> {
-        println!("{path}");
+    pub async fn save(&self, path: String) -> Result<SiteConfig, SiteSaveError> {
         let url: &str = self.url.as_str();
         std::fs::create_dir_all(path.clone()).map_err(|_| SiteSaveError::DirectoryCreateError)?;
-        let domain = Uri::from_str(url)
+        // The WordPress host only names the accounts, the site is served at the usual address
+        let host = Uri::from_str(url)
             .map_err(|_| SiteSaveError::InvalidUrl)?
             .host()
-            .unwrap()
+            .ok_or(SiteSaveError::InvalidUrl)?
             .to_string();
-        let routes = HashMap::new();
+        let db_filename = String::from("db.sqlite3");
+        let pool = SqlitePoolOptions::new()
+            .connect(format!("sqlite://{}/{}?mode=rwc", path, db_filename).as_str())
+            .await?;
+        setup_db(&pool).await?;
+        let media = self.save_media(&pool, &path).await?;
+        let sanitize = SanitizeConfig::wordpress();
+
+        let mut routes = HashMap::new();
         let templates = TinyTemplate::new();
-        for _page in self.page.iter() {}
+        // Templates are served to anyone, so drafts and private pages stay behind
+        let published = self
+            .page
+            .iter()
+            .filter(|page| matches!(page.status, WordpressStatus::Publish));
+        for page in published {
+            let Some(route) = self.page_route(page) else {
+                log::warn!(
+                    "Skipping page {} with an unsafe slug {:?}",
+                    page.id,
+                    page.slug
+                );
+                continue;
+            };
+            let file = format!("{}.html", route.trim_start_matches('/'));
+            let file_path = std::path::Path::new(&path).join("templates").join(&file);
+            if let Some(dir) = file_path.parent() {
+                std::fs::create_dir_all(dir).map_err(|_| SiteSaveError::DirectoryCreateError)?;
+            }
+            std::fs::write(
+                file_path,
+                format!(
+                    include_str!("../data/wordpress_page.html"),
+                    title = escape_template(&text(&page.title.rendered)),
+                    content = escape_template(&render::sanitize(
+                        &rehost_media(&page.content.rendered, &media),
+                        &sanitize
+                    )),
+                ),
+            )
+            .map_err(|_| SiteSaveError::TemplateWriteError)?;
+            routes.insert(
+                route,
+                PagePath {
+                    path: file,
+                    template: None,
+                    permalink: None,
+                },
+            );
+        }
         let templates = Arc::from(RwLock::from(templates));
-        Ok(SiteConfig {
-            db_filename: "db.sqlite3".into(),
-            db_pool: None,
-            site_path: domain.clone(),
-            domain,
+
+        self.save_users(&pool, &host).await?;
+        self.save_terms(&pool).await?;
+        self.save_posts(&pool, &media, &sanitize).await?;
+        self.save_comments(&pool).await?;
+
+        let keys =
+            SiteKeys::load_or_generate(&path).map_err(|_| SiteSaveError::ConfigWriteError)?;
+        let config = SiteConfig {
+            db_filename,
+            db_pool: Some(pool),
+            site_path: path,
+            domain: DEFAULT_ADDRESS.to_string(),
             routes,
             templates,
             create_user: false,
-        })
+            sanitize,
+            feed: Default::default(),
+            robots: Default::default(),
+            mail: Default::default(),
+            registration: Default::default(),
+            list_templates: list_templates_default(),
+            forwarded_for: None,
+            keys,
+            mailer: Default::default(),
+        };
+        config
+            .save()
+            .map_err(|_| SiteSaveError::ConfigWriteError)?;
+        Ok(config)
     }
-}
 
-impl WordpressSite {
-    pub async fn from_site_url(url: String) -> WordpressSite {
-        let client = Client::new();
-        WordpressSite {
-            url: url.clone(),
-            post: serde_json::from_str(
-                client
-                    .get(
-                        reqwest::Url::from_str(format!("{url}/wp-json/wp/v2/posts").as_str())
-                            .unwrap(),
-                    )
-                    .send()
-                    .await
-                    .unwrap()
-                    .text()
-                    .await
-                    .unwrap()
-                    .as_str(),
+    /// Creates an account for every WordPress author, along with the account that owns
+    /// posts whose author is unknown. The accounts get a random password and are flagged
+    /// as requiring a password reset.
+    async fn save_users(&self, pool: &SqlitePool, host: &str) -> Result<(), SiteSaveError> {
+        let authors = self
+            .users
+            .iter()
+            .map(|user| (user.name.as_str(), user.slug.as_str()));
+        for (name, username) in [(IMPORT_USER, IMPORT_USER)].into_iter().chain(authors) {
+            let mut pass: [u8; 48] = [0; 48];
+            for b in pass.iter_mut() {
+                *b = random();
+            }
+            let user: User = UserSignUp {
+                name: name.to_string(),
+                username: username.to_string(),
+                pass: general_purpose::STANDARD.encode(pass),
+                email: format!("{username}@{host}"),
+            }
+            .try_into()
+            .map_err(|_| SiteSaveError::UserCreateError)?;
+            let rank = user.rank.to_string();
+            query!(
+                "INSERT OR IGNORE INTO users (name, username, profile_pic, salt, sh_pass, email, rank, reset_password) VALUES($1, $2, $3, $4, $5, $6, $7, 1)",
+                user.name,
+                user.username,
+                user.profile_pic,
+                user.salt,
+                user.sh_pass,
+                user.email,
+                rank
             )
-            .unwrap(),
-            page: serde_json::from_str(
-                client
-                    .get(
-                        reqwest::Url::from_str(format!("{url}/wp-json/wp/v2/pages").as_str())
-                            .unwrap(),
-                    )
-                    .send()
-                    .await
-                    .unwrap()
-                    .text()
-                    .await
-                    .unwrap()
-                    .as_str(),
+            .execute(pool)
+            .await?;
+        }
+        log::info!("Imported {} users from {}", self.users.len(), self.url);
+        Ok(())
+    }
+
+    /// Saves the comments on imported posts, parents are saved before their replies so the
+    /// threads survive. Replies to comments that were not exported become top level comments.
+    async fn save_comments(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        let mut comments: Vec<&WordpressComment> = self
+            .comments
+            .iter()
+            .filter(|comment| self.post.iter().any(|post| post.id as isize == comment.post))
+            .collect();
+        // WordPress only allows replying to existing comments, so parents have lower ids
+        comments.sort_by_key(|comment| comment.id);
+        let mut saved = Vec::with_capacity(comments.len());
+        for comment in comments {
+            let id = comment.id as i64;
+            let post = comment.post as i64;
+            let parent = Some(comment.parent as i64).filter(|parent| saved.contains(parent));
+            let author = self
+                .users
+                .iter()
+                .find(|user| user.id == comment.author)
+                .map(|user| user.slug.as_str());
+            let date = comment.date_gmt.timestamp();
+            let status = CommentStatus::from(&comment.status).to_string();
+            query!(
+                "INSERT OR REPLACE INTO comments(id, post, parent, author, author_name, author_url, content, date, status) VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                id,
+                post,
+                parent,
+                author,
+                comment.author_name,
+                comment.author_url,
+                comment.content.rendered,
+                date,
+                status
             )
-            .unwrap(),
-            tags: serde_json::from_str(
-                client
-                    .get(
-                        reqwest::Url::from_str(format!("{url}/wp-json/wp/v2/tags").as_str())
-                            .unwrap(),
-                    )
-                    .send()
-                    .await
-                    .unwrap()
-                    .text()
-                    .await
-                    .unwrap()
-                    .as_str(),
+            .execute(pool)
+            .await?;
+            saved.push(id);
+        }
+        log::info!("Imported {} comments from {}", saved.len(), self.url);
+        Ok(())
+    }
+
+    /// Username of the account an imported post or page belongs to.
+    fn owner(&self, author: isize) -> &str {
+        self.users
+            .iter()
+            .find(|user| user.id == author)
+            .map(|user| user.slug.as_str())
+            .unwrap_or(IMPORT_USER)
+    }
+
+    async fn save_posts(
+        &self,
+        pool: &SqlitePool,
+        media: &[(String, String)],
+        sanitize: &SanitizeConfig,
+    ) -> Result<(), sqlx::Error> {
+        for post in self.post.iter() {
+            let id = post.id as i64;
+            let content = rehost_media(&post.content.rendered, media);
+            let date = post.date_gmt.timestamp();
+            let status = PostStatus::from(&post.status);
+            // Scheduled posts are dated to when they get published
+            let publish_at = (status == PostStatus::Scheduled).then_some(date);
+            let status = status.to_string();
+            let owner = self.owner(post.author);
+            let name = title_text(&post.title.rendered);
+            // Drafts don't have a slug yet
+            let wanted = match post.slug.is_empty() {
+                true => &name,
+                false => &post.slug,
+            };
+            let slug = unique_slug(&mut *pool.acquire().await?, wanted, Some(id)).await?;
+            query!(
+                "INSERT OR REPLACE INTO posts(id, name, slug, content, date, status, owner, publish_at) VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                id,
+                name,
+                slug,
+                content,
+                date,
+                status,
+                owner,
+                publish_at
             )
-            .unwrap(),
-            media: serde_json::from_str(
-                client
-                    .get(
-                        reqwest::Url::from_str(format!("{url}/wp-json/wp/v2/media").as_str())
-                            .unwrap(),
-                    )
-                    .send()
-                    .await
-                    .unwrap()
-                    .text()
-                    .await
-                    .unwrap()
-                    .as_str(),
+            .execute(pool)
+            .await?;
+            // Ids of terms that weren't imported are skipped
+            for tag in post.tags.iter().flatten().map(|tag| *tag as i64) {
+                query!(
+                    "INSERT OR IGNORE INTO post_tags(post, tag) SELECT ?1, id FROM tags WHERE id = ?2",
+                    id,
+                    tag
+                )
+                .execute(pool)
+                .await?;
+            }
+            for category in post.categories.iter().flatten().map(|c| *c as i64) {
+                query!(
+                    "INSERT OR IGNORE INTO post_categories(post, category) SELECT ?1, id FROM categories WHERE id = ?2",
+                    id,
+                    category
+                )
+                .execute(pool)
+                .await?;
+            }
+        }
+        render_stale_posts(pool, sanitize).await?;
+        log::info!("Imported {} posts from {}", self.post.len(), self.url);
+        Ok(())
+    }
+
+    /// Downloads the media into the static directory of the site and records it in the media table.
+    /// Returns the original urls paired with the urls they are now served from,
+    /// media that fails to download is skipped and stays hot-linked.
+    async fn save_media(
+        &self,
+        pool: &SqlitePool,
+        path: &str,
+    ) -> Result<Vec<(String, String)>, SiteSaveError> {
+        let client = Client::new();
+        let mut rehosted = Vec::with_capacity(self.media.len());
+        for media in self.media.iter() {
+            let file = match media_file_path(&media.source_url) {
+                Some(file) => file,
+                None => {
+                    log::warn!("Skipping media with invalid url {}", media.source_url);
+                    continue;
+                }
+            };
+            let bytes = match download(&client, &media.source_url).await {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    log::warn!("Failed to download {}, Error: {}", media.source_url, e);
+                    continue;
+                }
+            };
+            let file_path = std::path::Path::new(path).join("static").join(&file);
+            if let Some(dir) = file_path.parent() {
+                std::fs::create_dir_all(dir).map_err(|_| SiteSaveError::DirectoryCreateError)?;
+            }
+            std::fs::write(file_path, bytes).map_err(|_| SiteSaveError::MediaWriteError)?;
+
+            let id = media.id as i64;
+            let local_url = format!("/static/{file}");
+            query!(
+                "INSERT OR REPLACE INTO media(id, path, source_url, mime_type, alt_text, caption) VALUES(?1, ?2, ?3, ?4, ?5, ?6)",
+                id,
+                local_url,
+                media.source_url,
+                media.mime_type,
+                media.alt_text,
+                media.caption.rendered
             )
-            .unwrap(),
-            users: serde_json::from_str(
-                client
-                    .get(
-                        reqwest::Url::from_str(format!("{url}/wp-json/wp/v2/users").as_str())
-                            .unwrap(),
-                    )
-                    .send()
-                    .await
-                    .unwrap()
-                    .text()
-                    .await
-                    .unwrap()
-                    .as_str(),
+            .execute(pool)
+            .await?;
+            rehosted.push((media.source_url.clone(), local_url));
+        }
+        log::info!(
+            "Downloaded {} of {} media files from {}",
+            rehosted.len(),
+            self.media.len(),
+            self.url
+        );
+        Ok(rehosted)
+    }
+
+    /// Builds the url of a page from its slug and the slugs of its parents, unless one of
+    /// them isn't a safe path segment.
+    fn page_route(&self, page: &WordpressData) -> Option<String> {
+        let mut slugs = vec![page.slug.as_str()];
+        let mut parent = page.parent.unwrap_or_default();
+        // Bounded by the page count so a cycle in the parents can't loop forever
+        for _ in 0..self.page.len() {
+            match self.page.iter().find(|p| p.id as isize == parent) {
+                Some(p) => {
+                    slugs.push(p.slug.as_str());
+                    parent = p.parent.unwrap_or_default();
+                }
+                None => break,
+            }
+        }
+        if !slugs.iter().all(|slug| is_safe_slug(slug)) {
+            return None;
+        }
+        slugs.reverse();
+        Some(format!("/{}", slugs.join("/")))
+    }
+
+    /// Saves the tags and categories keeping their WordPress ids, so the ids on posts
+    /// refer to them.
+    async fn save_terms(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        for tag in self.tags.iter() {
+            let id = tag.id as i64;
+            query!(
+                "INSERT OR REPLACE INTO tags(id, name, slug) VALUES(?1, ?2, ?3)",
+                id,
+                tag.name,
+                tag.slug
+            )
+            .execute(pool)
+            .await?;
+        }
+        // Parents can come after their children, so they are linked once all exist
+        for category in self.categories.iter() {
+            let id = category.id as i64;
+            query!(
+                "INSERT OR REPLACE INTO categories(id, name, slug, description) VALUES(?1, ?2, ?3, ?4)",
+                id,
+                category.name,
+                category.slug,
+                category.description
             )
-            .unwrap(),
-            comments: serde_json::from_str(
-                client
-                    .get(
-                        reqwest::Url::from_str(format!("{url}/wp-json/wp/v2/comments").as_str())
-                            .unwrap(),
-                    )
-                    .send()
-                    .await
-                    .unwrap()
-                    .text()
-                    .await
-                    .unwrap()
-                    .as_str(),
+            .execute(pool)
+            .await?;
+        }
+        for category in self.categories.iter().filter(|c| c.parent != 0) {
+            let id = category.id as i64;
+            let parent = category.parent as i64;
+            query!(
+                "UPDATE categories SET parent = (SELECT id FROM categories WHERE id = ?1) WHERE id = ?2",
+                parent,
+                id
             )
-            .unwrap(),
+            .execute(pool)
+            .await?;
         }
+        log::info!(
+            "Imported {} tags and {} categories from {}",
+            self.tags.len(),
+            self.categories.len(),
+            self.url
+        );
+        Ok(())
+    }
+}
+
+/// Number of items requested per page, the maximum the wp-json api allows.
+const PER_PAGE: usize = 100;
+/// Number of times a request is retried after a transient failure.
+const MAX_RETRIES: u32 = 3;
+
+#[derive(Debug)]
+pub enum WordpressImportError {
+    /// The request for a page could not be completed, even after retrying.
+    Request {
+        endpoint: String,
+        page: usize,
+        source: reqwest::Error,
+    },
+    /// The server answered a page with an unsuccessful status.
+    Status {
+        endpoint: String,
+        page: usize,
+        status: reqwest::StatusCode,
+    },
+    /// The body of a page is not a json array.
+    Page {
+        endpoint: String,
+        page: usize,
+        source: serde_json::Error,
+    },
+    /// An item of a page does not have the expected shape.
+    Item {
+        endpoint: String,
+        page: usize,
+        id: Option<i64>,
+        source: serde_json::Error,
+    },
+}
+
+impl Display for WordpressImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Request {
+                endpoint,
+                page,
+                source,
+            } => write!(f, "Request for page {page} of {endpoint} failed: {source}"),
+            Self::Status {
+                endpoint,
+                page,
+                status,
+            } => write!(f, "Page {page} of {endpoint} returned {status}"),
+            Self::Page {
+                endpoint,
+                page,
+                source,
+            } => write!(f, "Page {page} of {endpoint} is not a list: {source}"),
+            Self::Item {
+                endpoint,
+                page,
+                id,
+                source,
+            } => match id {
+                Some(id) => write!(f, "Item {id} on page {page} of {endpoint} is invalid: {source}"),
+                None => write!(f, "An item on page {page} of {endpoint} is invalid: {source}"),
+            },
+        }
+    }
+}
+
+impl std::error::Error for WordpressImportError {}
+
+impl WordpressSite {
+    pub async fn from_site_url(url: String) -> Result<WordpressSite, WordpressImportError> {
+        let client = Client::new();
+        let url = url.trim_end_matches('/').to_string();
+        Ok(WordpressSite {
+            post: fetch_endpoint(&client, &url, "posts").await?,
+            page: fetch_endpoint(&client, &url, "pages").await?,
+            tags: fetch_endpoint(&client, &url, "tags").await?,
+            categories: fetch_endpoint(&client, &url, "categories").await?,
+            media: fetch_endpoint(&client, &url, "media").await?,
+            users: fetch_endpoint(&client, &url, "users").await?,
+            comments: fetch_endpoint(&client, &url, "comments").await?,
+            url,
+        })
+    }
+}
+
+/// Fetches every page of a wp-json endpoint, following the `X-WP-TotalPages` header.
+async fn fetch_endpoint<T: DeserializeOwned>(
+    client: &Client,
+    url: &str,
+    endpoint: &str,
+) -> Result<Vec<T>, WordpressImportError> {
+    let mut items = Vec::new();
+    let mut page = 1;
+    loop {
+        let (total_pages, body) = fetch_page(client, url, endpoint, page).await?;
+        let values: Vec<serde_json::Value> =
+            serde_json::from_str(&body).map_err(|source| WordpressImportError::Page {
+                endpoint: endpoint.to_string(),
+                page,
+                source,
+            })?;
+        for value in values {
+            let id = value.get("id").and_then(|id| id.as_i64());
+            items.push(
+                serde_json::from_value(value).map_err(|source| WordpressImportError::Item {
+                    endpoint: endpoint.to_string(),
+                    page,
+                    id,
+                    source,
+                })?,
+            );
+        }
+        log::info!("Fetched page {page} of {total_pages} from {endpoint}");
+        if page >= total_pages {
+            break;
+        }
+        page += 1;
+    }
+    Ok(items)
+}
+
+/// Fetches a single page of an endpoint, retrying connection failures and 5xx/429 responses.
+/// Returns the total number of pages along with the body.
+async fn fetch_page(
+    client: &Client,
+    url: &str,
+    endpoint: &str,
+    page: usize,
+) -> Result<(usize, String), WordpressImportError> {
+    let mut attempt = 0;
+    loop {
+        let result = client
+            .get(format!("{url}/wp-json/wp/v2/{endpoint}"))
+            .query(&[("per_page", PER_PAGE), ("page", page)])
+            .send()
+            .await;
+        let transient = match result {
+            Ok(resp) if resp.status().is_success() => {
+                let total_pages = resp
+                    .headers()
+                    .get("X-WP-TotalPages")
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(1);
+                match resp.text().await {
+                    Ok(body) => return Ok((total_pages, body)),
+                    Err(source) => WordpressImportError::Request {
+                        endpoint: endpoint.to_string(),
+                        page,
+                        source,
+                    },
+                }
+            }
+            Ok(resp) => {
+                let status = resp.status();
+                let error = WordpressImportError::Status {
+                    endpoint: endpoint.to_string(),
+                    page,
+                    status,
+                };
+                if !status.is_server_error() && status != reqwest::StatusCode::TOO_MANY_REQUESTS {
+                    return Err(error);
+                }
+                error
+            }
+            Err(source) => WordpressImportError::Request {
+                endpoint: endpoint.to_string(),
+                page,
+                source,
+            },
+        };
+        if attempt >= MAX_RETRIES {
+            return Err(transient);
+        }
+        attempt += 1;
+        log::warn!("{transient}, retrying ({attempt}/{MAX_RETRIES})");
+        tokio::time::sleep(Duration::from_millis(250 * 2u64.pow(attempt))).await;
     }
 }
 
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)]
 struct WordpressUser {
     id: isize,
     name: String,
@@ -321,6 +965,7 @@ struct WordpressUser {
 }
 
 #[derive(Deserialize, Debug)]
+#[allow(dead_code)]
 struct WordpressMedia {
     id: u32,
     date: String,
@@ -344,7 +989,7 @@ struct WordpressMedia {
     alt_text: String,
     media_type: String,
     mime_type: String,
-    post: isize,
+    post: Option<isize>,
     source_url: String,
     #[serde(rename = "_links")]
     links: TagLinks,
@@ -0,0 +1,341 @@
+//! Reading the WordPress eXtended RSS files made by "Tools → Export" in the WordPress dashboard.
+
+use std::{collections::HashMap, fmt::Display, fs, io};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use roxmltree::{Document, Node};
+use slug::slugify;
+
+use super::{
+    Content, Meta, TagLinks, WordpressCategory, WordpressComment, WordpressData, WordpressMedia,
+    WordpressRespType, WordpressSite, WordpressStatus, WordpressTags, WordpressUser,
+};
+
+/// Namespace of the `wp:` elements, `excerpt:encoded` lives in one below it so it is found with this too.
+const WP: &str = "http://wordpress.org/export/";
+const CONTENT: &str = "http://purl.org/rss/1.0/modules/content/";
+const DC: &str = "http://purl.org/dc/elements/1.1/";
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Debug)]
+pub enum WxrError {
+    Io(io::Error),
+    Xml(roxmltree::Error),
+    /// The file is valid xml, but not an export, it has no `<channel>`.
+    NotAnExport,
+    /// A field of an item could not be parsed.
+    InvalidField {
+        item: String,
+        field: &'static str,
+        value: String,
+    },
+}
+
+impl Display for WxrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Could not read the export: {e}"),
+            Self::Xml(e) => write!(f, "The export is not valid xml: {e}"),
+            Self::NotAnExport => write!(f, "The file is not a WordPress export"),
+            Self::InvalidField { item, field, value } => {
+                write!(f, "Item {item} has an invalid {field}: {value:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for WxrError {}
+
+/// Child elements with the given name, `namespace` is matched as a prefix so every
+/// version of the export format is understood. `None` matches elements without one.
+fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    namespace: Option<&'a str>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children().filter(move |child| {
+        child.is_element()
+            && child.tag_name().name() == name
+            && match (namespace, child.tag_name().namespace()) {
+                (Some(namespace), Some(ns)) => ns.starts_with(namespace),
+                (None, None) => true,
+                _ => false,
+            }
+    })
+}
+
+fn text(node: Node, namespace: Option<&str>, name: &str) -> String {
+    children(node, namespace, name)
+        .next()
+        .and_then(|child| child.text())
+        .unwrap_or_default()
+        .trim()
+        .to_string()
+}
+
+fn number(node: Node, name: &'static str) -> Result<isize, WxrError> {
+    let value = text(node, Some(WP), name);
+    if value.is_empty() {
+        return Ok(0);
+    }
+    value.parse().map_err(|_| WxrError::InvalidField {
+        item: text(node, Some(WP), "post_id"),
+        field: name,
+        value,
+    })
+}
+
+/// Drafts are exported with a gmt date of all zeroes, those fall back to the local date.
+fn date(node: Node, gmt: &str, local: &str) -> DateTime<Utc> {
+    [gmt, local]
+        .into_iter()
+        .find_map(|name| {
+            NaiveDateTime::parse_from_str(&text(node, Some(WP), name), DATE_FORMAT).ok()
+        })
+        .map(|date| DateTime::from_naive_utc_and_offset(date, Utc))
+        .unwrap_or_else(Utc::now)
+}
+
+fn content(rendered: String) -> Content {
+    Content {
+        rendered,
+        protected: None,
+    }
+}
+
+impl WordpressSite {
+    /// Reads a site from a WXR export file.
+    pub fn from_wxr_file(path: String) -> Result<WordpressSite, WxrError> {
+        Self::from_wxr(&fs::read_to_string(path).map_err(WxrError::Io)?)
+    }
+
+    /// Reads a site from the contents of a WXR export.
+    pub fn from_wxr(xml: &str) -> Result<WordpressSite, WxrError> {
+        let document = Document::parse(xml).map_err(WxrError::Xml)?;
+        let channel = children(document.root_element(), None, "channel")
+            .next()
+            .ok_or(WxrError::NotAnExport)?;
+
+        let users = children(channel, Some(WP), "author")
+            .map(|author| {
+                Ok(WordpressUser {
+                    id: number(author, "author_id")?,
+                    name: text(author, Some(WP), "author_display_name"),
+                    slug: text(author, Some(WP), "author_login"),
+                })
+            })
+            .collect::<Result<Vec<_>, WxrError>>()?;
+        let mut tags = children(channel, Some(WP), "tag")
+            .map(|tag| {
+                let slug = text(tag, Some(WP), "tag_slug");
+                Ok(WordpressTags {
+                    id: number(tag, "term_id")?,
+                    count: 0,
+                    description: text(tag, Some(WP), "tag_description"),
+                    link: String::new(),
+                    name: text(tag, Some(WP), "tag_name"),
+                    slug,
+                    taxonomy: "post_tag".to_string(),
+                    meta: Vec::new(),
+                    links: TagLinks::default(),
+                })
+            })
+            .collect::<Result<Vec<_>, WxrError>>()?;
+        // Parents are referenced by their slug, they are resolved once every category is read
+        let mut parents = Vec::new();
+        let mut categories = children(channel, Some(WP), "category")
+            .map(|category| {
+                parents.push(text(category, Some(WP), "category_parent"));
+                Ok(WordpressCategory {
+                    id: number(category, "term_id")?,
+                    count: 0,
+                    description: text(category, Some(WP), "category_description"),
+                    link: String::new(),
+                    name: text(category, Some(WP), "cat_name"),
+                    slug: text(category, Some(WP), "category_nicename"),
+                    taxonomy: "category".to_string(),
+                    parent: 0,
+                })
+            })
+            .collect::<Result<Vec<_>, WxrError>>()?;
+        for (i, parent) in parents.iter().enumerate() {
+            categories[i].parent = categories
+                .iter()
+                .find(|category| !parent.is_empty() && category.slug == *parent)
+                .map(|category| category.id)
+                .unwrap_or_default();
+        }
+
+        let mut site = WordpressSite {
+            url: text(channel, None, "link"),
+            post: Vec::new(),
+            page: Vec::new(),
+            tags: Vec::new(),
+            categories: Vec::new(),
+            media: Vec::new(),
+            users,
+            comments: Vec::new(),
+        };
+        for item in children(channel, None, "item") {
+            let id = number(item, "post_id")?;
+            let author_login = text(item, Some(DC), "creator");
+            let author = site
+                .users
+                .iter()
+                .find(|user| user.slug == author_login)
+                .map(|user| user.id)
+                .unwrap_or_default();
+            let status = WordpressStatus::from(text(item, Some(WP), "status"));
+            let page_type = WordpressRespType::from(text(item, Some(WP), "post_type"));
+            let date_gmt = date(item, "post_date_gmt", "post_date");
+            let title = text(item, None, "title");
+            // Drafts don't have a slug yet, like the api they get one made from their title
+            let mut slug = text(item, Some(WP), "post_name");
+            if slug.is_empty() {
+                slug = slugify(&title);
+            }
+            if slug.is_empty() {
+                slug = id.to_string();
+            }
+
+            if let WordpressRespType::Attachment = page_type {
+                let source_url = text(item, Some(WP), "attachment_url");
+                site.media.push(WordpressMedia {
+                    id: id as u32,
+                    date: text(item, Some(WP), "post_date"),
+                    date_gmt: date_gmt.format("%Y-%m-%dT%H:%M:%S").to_string(),
+                    guid: content(text(item, None, "guid")),
+                    modified: text(item, Some(WP), "post_modified"),
+                    modified_gmt: text(item, Some(WP), "post_modified_gmt"),
+                    slug,
+                    status,
+                    page_type,
+                    link: text(item, None, "link"),
+                    title: content(title.clone()),
+                    author,
+                    comment_status: text(item, Some(WP), "comment_status"),
+                    ping_status: text(item, Some(WP), "ping_status"),
+                    template: String::new(),
+                    meta: Meta::default(),
+                    description: content(text(item, Some(CONTENT), "encoded")),
+                    caption: content(text(item, Some(WP), "encoded")),
+                    alt_text: postmeta(item, "_wp_attachment_image_alt").unwrap_or_default(),
+                    media_type: String::new(),
+                    mime_type: mime_guess::from_path(&source_url)
+                        .first_or_octet_stream()
+                        .to_string(),
+                    post: Some(number(item, "post_parent")?),
+                    source_url,
+                    links: TagLinks::default(),
+                });
+                continue;
+            }
+
+            let mut tag_ids = Vec::new();
+            let mut category_ids = Vec::new();
+            for category in children(item, None, "category") {
+                let slug = category.attribute("nicename").unwrap_or_default();
+                if category.attribute("domain") == Some("category") {
+                    // Unlike tags, categories missing from the channel are left out
+                    category_ids.extend(categories.iter().find(|c| c.slug == slug).map(|c| c.id));
+                    continue;
+                }
+                if category.attribute("domain") != Some("post_tag") {
+                    continue;
+                }
+                let tag_id = match tags.iter().find(|tag| tag.slug == slug) {
+                    Some(tag) => tag.id,
+                    // Tags are not always listed in the channel, those get a made up id
+                    None => {
+                        let tag_id = tags.iter().map(|tag| tag.id).max().unwrap_or_default() + 1;
+                        tags.push(WordpressTags {
+                            id: tag_id,
+                            count: 0,
+                            description: String::new(),
+                            link: String::new(),
+                            name: category.text().unwrap_or(slug).trim().to_string(),
+                            slug: slug.to_string(),
+                            taxonomy: "post_tag".to_string(),
+                            meta: Vec::new(),
+                            links: TagLinks::default(),
+                        });
+                        tag_id
+                    }
+                };
+                tag_ids.push(tag_id);
+            }
+
+            for comment in children(item, Some(WP), "comment") {
+                site.comments.push(WordpressComment {
+                    id: number(comment, "comment_id")?,
+                    post: id,
+                    parent: number(comment, "comment_parent")?,
+                    author: number(comment, "comment_user_id")?,
+                    author_name: text(comment, Some(WP), "comment_author"),
+                    author_url: text(comment, Some(WP), "comment_author_url"),
+                    date: text(comment, Some(WP), "comment_date"),
+                    date_gmt: date(comment, "comment_date_gmt", "comment_date"),
+                    content: content(text(comment, Some(WP), "comment_content")),
+                    link: String::new(),
+                    status: match text(comment, Some(WP), "comment_approved").as_str() {
+                        "1" => WordpressStatus::Approved,
+                        "0" => WordpressStatus::Other("hold".to_string()),
+                        other => WordpressStatus::from(other.to_string()),
+                    },
+                    data_type: text(comment, Some(WP), "comment_type"),
+                    meta: Vec::new(),
+                    links: TagLinks::default(),
+                });
+            }
+
+            let data = WordpressData {
+                id: id as usize,
+                date: text(item, Some(WP), "post_date"),
+                date_gmt,
+                guid: content(text(item, None, "guid")),
+                modified: text(item, Some(WP), "post_modified"),
+                modified_gmt: text(item, Some(WP), "post_modified_gmt"),
+                slug,
+                status,
+                page_type,
+                link: text(item, None, "link"),
+                title: content(title),
+                content: content(text(item, Some(CONTENT), "encoded")),
+                excerpt: content(text(item, Some(WP), "encoded")),
+                author,
+                featured_media: 0,
+                comment_status: text(item, Some(WP), "comment_status"),
+                ping_status: text(item, Some(WP), "ping_status"),
+                template: String::new(),
+                meta: Meta::default(),
+                parent: Some(number(item, "post_parent")?),
+                categories: Some(category_ids),
+                tags: Some(tag_ids),
+                links: HashMap::new(),
+            };
+            match data.page_type {
+                WordpressRespType::Post => site.post.push(data),
+                WordpressRespType::Page => site.page.push(data),
+                // Menu items, revisions, custom post types and the like have no counterpart
+                _ => log::info!("Skipping item {} of type {:?}", data.id, data.page_type),
+            }
+        }
+        site.tags = tags;
+        site.categories = categories;
+        log::info!(
+            "Read {} posts, {} pages, {} media and {} comments from the export of {}",
+            site.post.len(),
+            site.page.len(),
+            site.media.len(),
+            site.comments.len(),
+            site.url
+        );
+        Ok(site)
+    }
+}
+
+fn postmeta(item: Node, key: &str) -> Option<String> {
+    children(item, Some(WP), "postmeta")
+        .find(|meta| text(*meta, Some(WP), "meta_key") == key)
+        .map(|meta| text(meta, Some(WP), "meta_value"))
+}
@@ -0,0 +1,388 @@
+mod common;
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use axum::{http::StatusCode, Router};
+use common::{add_user, send, send_from, sign_in, site};
+use peroxide::{
+    auth::{
+        account_token::{issue_token, redeem_token, TokenPurpose},
+        user::{Rank, UserInfo},
+    },
+    config::{MailConfig, MailTransport, SiteConfig},
+    mail::SiteMailer,
+    site::setup_routes,
+};
+
+/// A site whose mails are written to `mails.txt` in `dir`, which may have mail templates.
+async fn mailing_site(dir: &Path) -> (SiteConfig, Router, PathBuf) {
+    let mut config = site().await;
+    config.domain = "blog.example.com".to_string();
+    config.mail.transport = MailTransport::File {
+        path: "mails.txt".to_string(),
+    };
+    config.mailer = SiteMailer::new(&config.mail, dir.to_str().unwrap()).unwrap();
+    let app = setup_routes(&config);
+    (config, app, dir.join("mails.txt"))
+}
+
+fn mails(path: &Path) -> Vec<String> {
+    std::fs::read_to_string(path)
+        .unwrap_or_default()
+        .split_terminator("----\n")
+        .map(str::to_string)
+        .collect()
+}
+
+/// The mails in `path` once there are `count` of them, password resets are mailed in the
+/// background.
+async fn wait_for_mails(path: &Path, count: usize) -> Vec<String> {
+    for _ in 0..200 {
+        let sent = mails(path);
+        if sent.len() >= count {
+            return sent;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("{count} mails weren't sent to {}", path.display());
+}
+
+/// The token of the link in a mail.
+fn token(mail: &str) -> String {
+    let start = mail.find("token=").unwrap() + "token=".len();
+    mail[start..].split_whitespace().next().unwrap().to_string()
+}
+
+async fn try_sign_in(app: &Router, username: &str, pass: &str) -> StatusCode {
+    send(
+        app,
+        "PUT",
+        "/api/user",
+        None,
+        Some(&[("username", username), ("pass", pass)]),
+    )
+    .await
+    .status
+}
+
+#[tokio::test]
+async fn resets_passwords_through_mailed_links() {
+    let dir = tempfile::tempdir().unwrap();
+    let (config, app, outbox) = mailing_site(dir.path()).await;
+    add_user(&config, "jane", Rank::Author).await;
+    let session = sign_in(&app, "jane").await;
+
+    // Unknown accounts look the same, but nothing is mailed
+    let forgot = |login: &'static str| {
+        let app = app.clone();
+        async move {
+            send(
+                &app,
+                "POST",
+                "/api/user/password/forgot",
+                None,
+                Some(&[("login", login)]),
+            )
+            .await
+            .status
+        }
+    };
+    assert_eq!(forgot("nobody").await, StatusCode::OK);
+    assert_eq!(forgot("jane@example.com").await, StatusCode::OK);
+    let sent = wait_for_mails(&outbox, 1).await;
+    assert_eq!(sent.len(), 1);
+    assert!(sent[0].contains("To: jane@example.com"));
+    assert!(sent[0].contains("Subject: Reset your password on https://blog.example.com"));
+    assert!(sent[0].contains("https://blog.example.com/reset_password?token="));
+
+    let reset = |token: String| {
+        let app = app.clone();
+        async move {
+            send(
+                &app,
+                "POST",
+                "/api/user/password/reset",
+                None,
+                Some(&[("token", &token), ("pass", "a new password")]),
+            )
+            .await
+            .status
+        }
+    };
+    let first = token(&sent[0]);
+    let empty = send(
+        &app,
+        "POST",
+        "/api/user/password/reset",
+        None,
+        Some(&[("token", &first), ("pass", "")]),
+    )
+    .await;
+    assert_eq!(empty.status, StatusCode::BAD_REQUEST);
+    assert_eq!(reset(first.clone()).await, StatusCode::OK);
+    assert_eq!(
+        try_sign_in(&app, "jane", "a new password").await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        try_sign_in(&app, "jane", common::PASSWORD).await,
+        StatusCode::UNAUTHORIZED
+    );
+    // Sessions from before the reset are over
+    let response = send(&app, "GET", "/api/user/sessions", Some(&session), None).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    // Links work once
+    assert_eq!(reset(first).await, StatusCode::BAD_REQUEST);
+    assert_eq!(reset("made-up".to_string()).await, StatusCode::BAD_REQUEST);
+
+    // Asking again replaces the link sent before
+    forgot("jane").await;
+    wait_for_mails(&outbox, 2).await;
+    forgot("jane").await;
+    let sent = wait_for_mails(&outbox, 3).await;
+    assert_eq!(reset(token(&sent[1])).await, StatusCode::BAD_REQUEST);
+    assert_eq!(reset(token(&sent[2])).await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn limits_password_resets() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut config, _, outbox) = mailing_site(dir.path()).await;
+    config.mail.resets_per_hour = 2;
+    let app = setup_routes(&config);
+    add_user(&config, "jane", Rank::Author).await;
+    let forgot = |login: &'static str| {
+        let app = app.clone();
+        async move {
+            send(
+                &app,
+                "POST",
+                "/api/user/password/forgot",
+                None,
+                Some(&[("login", login)]),
+            )
+            .await
+            .status
+        }
+    };
+
+    let pool = config.db_pool.as_ref().unwrap();
+    assert_eq!(forgot("jane").await, StatusCode::OK);
+    assert_eq!(forgot("jane").await, StatusCode::OK);
+    // Requests for accounts that don't exist count as well
+    assert_eq!(forgot("nobody").await, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(wait_for_mails(&outbox, 2).await.len(), 2);
+
+    // Each account has a limit of its own, whatever the address
+    sqlx::query("UPDATE reset_attempts SET ip = 'elsewhere'")
+        .execute(pool)
+        .await
+        .unwrap();
+    assert_eq!(forgot("jane").await, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(forgot("nobody").await, StatusCode::OK);
+
+    // Requests from over an hour ago don't count
+    sqlx::query("UPDATE reset_attempts SET attempted = attempted - 60 * 60")
+        .execute(pool)
+        .await
+        .unwrap();
+    assert_eq!(forgot("jane").await, StatusCode::OK);
+    assert_eq!(wait_for_mails(&outbox, 3).await.len(), 3);
+}
+
+#[tokio::test]
+async fn tokens_expire_and_only_work_for_their_purpose() {
+    let config = site().await;
+    let pool = config.db_pool.as_ref().unwrap();
+    add_user(&config, "jane", Rank::Author).await;
+
+    let token = issue_token(pool, "jane", "jane@example.com", TokenPurpose::VerifyEmail)
+        .await
+        .unwrap();
+    let redeemed = redeem_token(pool, &token, TokenPurpose::ResetPassword)
+        .await
+        .unwrap();
+    assert_eq!(redeemed, None);
+
+    let token = issue_token(
+        pool,
+        "jane",
+        "jane@example.com",
+        TokenPurpose::ResetPassword,
+    )
+    .await
+    .unwrap();
+    sqlx::query("UPDATE account_tokens SET expires = unixepoch() - 1")
+        .execute(pool)
+        .await
+        .unwrap();
+    let redeemed = redeem_token(pool, &token, TokenPurpose::ResetPassword)
+        .await
+        .unwrap();
+    assert_eq!(redeemed, None);
+
+    // Only hashes are stored
+    let token = issue_token(
+        pool,
+        "jane",
+        "jane@example.com",
+        TokenPurpose::ResetPassword,
+    )
+    .await
+    .unwrap();
+    let stored: Vec<u8> = sqlx::query_scalar("SELECT hash FROM account_tokens")
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    assert_ne!(stored, token.as_bytes());
+    let owner = redeem_token(pool, &token, TokenPurpose::ResetPassword)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(owner.username, "jane");
+}
+
+#[tokio::test]
+async fn verifies_the_emails_of_new_users() {
+    let dir = tempfile::tempdir().unwrap();
+    let (config, app, outbox) = mailing_site(dir.path()).await;
+    let pool = config.db_pool.as_ref().unwrap();
+    add_user(&config, "admin", Rank::Administrator).await;
+    let admin = sign_in(&app, "admin").await;
+    let verified = |username: &'static str| {
+        let app = app.clone();
+        async move {
+            let uri = format!("/api/user?username={username}");
+            let user: UserInfo = send(&app, "GET", &uri, None, None).await.json();
+            user.email_verified
+        }
+    };
+
+    let response = send(
+        &app,
+        "POST",
+        "/api/admin/user",
+        Some(&admin),
+        Some(&[
+            ("name", "Jane"),
+            ("username", "jane"),
+            ("pass", "hunter22"),
+            ("email", "jane@example.com"),
+        ]),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert!(!verified("jane").await);
+    let sent = mails(&outbox);
+    assert!(sent[0].contains("Subject: Confirm your email"));
+    assert!(sent[0].contains("https://blog.example.com/api/user/email/verify?token="));
+
+    let uri = format!("/api/user/email/verify?token={}", token(&sent[0]));
+    assert_eq!(
+        send(&app, "GET", &uri, None, None).await.status,
+        StatusCode::OK
+    );
+    assert!(verified("jane").await);
+    assert_eq!(
+        send(&app, "GET", &uri, None, None).await.status,
+        StatusCode::BAD_REQUEST
+    );
+
+    // Links are for the address they were mailed to
+    let response = send(&app, "POST", "/api/user/email/verify", Some(&admin), None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    sqlx::query("UPDATE users SET email = 'admin@elsewhere.com' WHERE username = 'admin'")
+        .execute(pool)
+        .await
+        .unwrap();
+    let sent = mails(&outbox);
+    assert!(sent[1].contains("To: admin@example.com"));
+    let uri = format!("/api/user/email/verify?token={}", token(&sent[1]));
+    assert_eq!(
+        send(&app, "GET", &uri, None, None).await.status,
+        StatusCode::BAD_REQUEST
+    );
+    assert!(!verified("admin").await);
+
+    let response = send(&app, "POST", "/api/user/email/verify", None, None).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn sites_replace_mail_templates() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("templates/mail")).unwrap();
+    std::fs::write(
+        dir.path().join("templates/mail/reset_password.txt"),
+        "Subject: Locked out, {name}?\n\nGo to {link} & pick a new one.\n",
+    )
+    .unwrap();
+    let (config, app, outbox) = mailing_site(dir.path()).await;
+    add_user(&config, "jane", Rank::Author).await;
+    send(
+        &app,
+        "POST",
+        "/api/user/password/forgot",
+        None,
+        Some(&[("login", "jane")]),
+    )
+    .await;
+    let sent = wait_for_mails(&outbox, 1).await;
+    assert!(sent[0].contains("Subject: Locked out, jane?\n"));
+    // Mails are plain text, so nothing is escaped
+    assert!(!sent[0].contains("&amp;"));
+    assert!(sent[0].contains(" & pick a new one."));
+
+    std::fs::write(
+        dir.path().join("templates/mail/verify_email.txt"),
+        "Subject: {{ if open }}",
+    )
+    .unwrap();
+    assert!(SiteMailer::new(&MailConfig::default(), dir.path().to_str().unwrap()).is_err());
+}
+
+#[tokio::test]
+async fn reads_mail_config() {
+    let config: MailConfig = toml::from_str(
+        r#"
+        from = "Blog <noreply@blog.example.com>"
+
+        [transport]
+        kind = "smtp"
+        host = "smtp.example.com"
+        security = "start_tls"
+        username = "blog"
+        password = "secret"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.reset_page, "/reset_password");
+    assert!(matches!(config.transport, MailTransport::Smtp(ref smtp) if smtp.port.is_none()));
+    // Nothing is connected to yet, but the pool of connections needs a runtime
+    assert!(SiteMailer::new(&config, "").is_ok());
+    assert_eq!(MailConfig::default().transport, MailTransport::Stdout);
+}
+
+#[tokio::test]
+async fn turns_away_resets_from_unknown_addresses() {
+    let dir = tempfile::tempdir().unwrap();
+    let (config, app, outbox) = mailing_site(dir.path()).await;
+    add_user(&config, "jane", Rank::Author).await;
+    let form = [("login", "jane")];
+    let response = send_from(
+        &app,
+        None,
+        &[],
+        "POST",
+        "/api/user/password/forgot",
+        None,
+        Some(&form),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(mails(&outbox).is_empty());
+}
@@ -0,0 +1,278 @@
+mod common;
+
+use axum::{http::StatusCode, Router};
+use common::{add_user, app, send, sign_in};
+use peroxide::{
+    auth::{capability::Capability, user::Rank},
+    config::SiteConfig,
+    site::setup_routes,
+};
+use sqlx::SqlitePool;
+
+use StatusCode as S;
+
+const OK: StatusCode = S::OK;
+const NO: StatusCode = S::FORBIDDEN;
+
+/// Signs in a new user of every rank, named after the rank.
+async fn users(config: &SiteConfig, app: &Router) -> Vec<(Rank, String)> {
+    let mut users = Vec::new();
+    for rank in Rank::ALL {
+        let username = rank.to_string().to_lowercase();
+        add_user(config, &username, rank).await;
+        users.push((rank, sign_in(app, &username).await));
+    }
+    users
+}
+
+async fn newest_post(pool: &SqlitePool) -> i64 {
+    sqlx::query_scalar("SELECT MAX(id) FROM posts")
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+/// A post of someone else's to edit.
+async fn others_post(app: &Router, pool: &SqlitePool, owner: &str) -> i64 {
+    let response = send(
+        app,
+        "POST",
+        "/api/post",
+        Some(owner),
+        Some(&[("name", "Not yours"), ("content", "x")]),
+    )
+    .await;
+    assert_eq!(response.status, OK, "{}", response.body);
+    newest_post(pool).await
+}
+
+#[test]
+fn ranks_have_the_capabilities_of_those_below() {
+    use Capability::*;
+    assert_eq!(Rank::Subscriber.capabilities(), []);
+    assert_eq!(Rank::Contributor.capabilities(), [EditOwnPosts]);
+    assert_eq!(Rank::Author.capabilities(), [EditOwnPosts, PublishPosts]);
+    assert!(Rank::Editor.can(EditOthersPosts) && Rank::Editor.can(ModerateComments));
+    assert!(!Rank::Editor.can(ManageUsers) && !Rank::Editor.can(ManageSettings));
+    assert_eq!(Rank::Administrator.capabilities().len(), 6);
+    for pair in Rank::ALL.windows(2) {
+        let (lower, higher) = (pair[0], pair[1]);
+        assert!(lower
+            .capabilities()
+            .iter()
+            .all(|capability| higher.can(*capability)));
+    }
+    // Ranks of older versions
+    assert_eq!(Rank::from("User".to_string()), Rank::Author);
+    assert_eq!(Rank::from("Admin".to_string()), Rank::Administrator);
+    assert_eq!(Rank::from("Nonsense".to_string()), Rank::Subscriber);
+    assert_eq!("editor".parse::<Rank>(), Ok(Rank::Editor));
+    assert!("admin".parse::<Rank>().is_err());
+}
+
+#[tokio::test]
+async fn post_routes() {
+    let (config, app) = app().await;
+    let pool = config.db_pool.as_ref().unwrap();
+    add_user(&config, "owner", Rank::Author).await;
+    let owner = sign_in(&app, "owner").await;
+    let post = others_post(&app, pool, &owner).await;
+
+    // Writing a draft, publishing, editing, reading the revisions of and deleting a post of
+    // someone else's
+    let expected = [
+        (Rank::Subscriber, [NO, NO, NO, NO, NO]),
+        (Rank::Contributor, [OK, NO, NO, NO, NO]),
+        (Rank::Author, [OK, OK, NO, NO, NO]),
+        (Rank::Editor, [OK, OK, OK, OK, OK]),
+        (Rank::Administrator, [OK, OK, OK, OK, OK]),
+    ];
+    for ((rank, cookie), (expected_rank, expected)) in
+        users(&config, &app).await.into_iter().zip(expected)
+    {
+        assert_eq!(rank, expected_rank);
+        let cookie = Some(cookie.as_str());
+        let draft = [("name", "Draft"), ("content", "x")];
+        let published = [("name", "Out"), ("content", "x"), ("status", "Published")];
+        let edit = format!("/api/post?id={post}");
+        let revisions = format!("/api/post/revisions?post={post}");
+        let doomed = others_post(&app, pool, &owner).await;
+        let delete = format!("/api/post?id={doomed}");
+        let statuses = [
+            send(&app, "POST", "/api/post", cookie, Some(&draft)).await,
+            send(&app, "POST", "/api/post", cookie, Some(&published)).await,
+            send(&app, "PATCH", &edit, cookie, Some(&[("name", "Edited")])).await,
+            send(&app, "GET", &revisions, cookie, None).await,
+            send(&app, "DELETE", &delete, cookie, None).await,
+        ]
+        .map(|response| response.status);
+        assert_eq!(statuses, expected, "{rank}");
+    }
+
+    for (method, uri) in [
+        ("POST", "/api/post".to_string()),
+        ("PATCH", format!("/api/post?id={post}")),
+        ("DELETE", format!("/api/post?id={post}")),
+        ("GET", format!("/api/post/revisions?post={post}")),
+    ] {
+        let response = send(&app, method, &uri, None, Some(&[("name", "x")])).await;
+        assert_eq!(response.status, S::UNAUTHORIZED, "{method} {uri}");
+    }
+}
+
+#[tokio::test]
+async fn own_posts_need_the_rank_to_write_them() {
+    let (config, app) = app().await;
+    let pool = config.db_pool.as_ref().unwrap();
+    add_user(&config, "jane", Rank::Contributor).await;
+    let jane = sign_in(&app, "jane").await;
+    let post = others_post(&app, pool, &jane).await;
+    let uri = format!("/api/post?id={post}");
+
+    let edit = send(&app, "PATCH", &uri, Some(&jane), Some(&[("name", "Mine")])).await;
+    assert_eq!(edit.status, OK);
+    // Contributors send their posts for review instead of publishing them
+    let review = [("status", "Pending")];
+    let publish = [("status", "Published")];
+    assert_eq!(
+        send(&app, "PATCH", &uri, Some(&jane), Some(&review))
+            .await
+            .status,
+        OK
+    );
+    assert_eq!(
+        send(&app, "PATCH", &uri, Some(&jane), Some(&publish))
+            .await
+            .status,
+        NO
+    );
+
+    // Demoted users keep their posts, but can't edit them anymore
+    sqlx::query("UPDATE users SET rank = 'Subscriber' WHERE username = 'jane'")
+        .execute(pool)
+        .await
+        .unwrap();
+    let edit = send(&app, "PATCH", &uri, Some(&jane), Some(&[("name", "Gone")])).await;
+    assert_eq!(edit.status, NO);
+
+    // Contributors only edit their posts until an editor publishes them
+    sqlx::query("UPDATE users SET rank = 'Contributor' WHERE username = 'jane'")
+        .execute(pool)
+        .await
+        .unwrap();
+    sqlx::query("UPDATE posts SET status = 'Published' WHERE id = ?")
+        .bind(post)
+        .execute(pool)
+        .await
+        .unwrap();
+    let edit = send(&app, "PATCH", &uri, Some(&jane), Some(&[("name", "Mine")])).await;
+    assert_eq!(edit.status, NO);
+    let delete = send(&app, "DELETE", &uri, Some(&jane), None).await;
+    assert_eq!(delete.status, NO);
+    assert_eq!(send(&app, "GET", &uri, Some(&jane), None).await.status, OK);
+}
+
+#[tokio::test]
+async fn admin_routes() {
+    let (mut config, _) = app().await;
+    let dir = tempfile::tempdir().unwrap();
+    config.site_path = dir.path().to_string_lossy().to_string();
+    let app = setup_routes(&config);
+    let pool = config.db_pool.as_ref().unwrap();
+    add_user(&config, "owner", Rank::Author).await;
+    let owner = sign_in(&app, "owner").await;
+    let post = others_post(&app, pool, &owner).await;
+    sqlx::query("UPDATE posts SET status = 'Published'")
+        .execute(pool)
+        .await
+        .unwrap();
+    let comment = send(
+        &app,
+        "POST",
+        "/api/comment",
+        Some(&owner),
+        Some(&[("post", &post.to_string()), ("content", "Hi")]),
+    )
+    .await;
+    assert_eq!(comment.status, OK);
+
+    // Adding users, changing settings and moderating comments
+    let expected = [
+        (Rank::Subscriber, [NO, NO, NO]),
+        (Rank::Contributor, [NO, NO, NO]),
+        (Rank::Author, [NO, NO, NO]),
+        (Rank::Editor, [NO, NO, OK]),
+        (Rank::Administrator, [OK, OK, OK]),
+    ];
+    for ((rank, cookie), (_, expected)) in users(&config, &app).await.into_iter().zip(expected) {
+        let cookie = Some(cookie.as_str());
+        let username = format!("new-{rank}");
+        let email = format!("{username}@example.com");
+        let new_user = [
+            ("name", "New"),
+            ("username", username.as_str()),
+            ("pass", "hunter22"),
+            ("email", email.as_str()),
+        ];
+        let statuses = [
+            send(&app, "POST", "/api/admin/user", cookie, Some(&new_user)).await,
+            send(
+                &app,
+                "POST",
+                "/api/admin/settings/domain?domain=example.com",
+                cookie,
+                None,
+            )
+            .await,
+            send(
+                &app,
+                "PUT",
+                "/api/admin/comment?id=1&status=Approved",
+                cookie,
+                None,
+            )
+            .await,
+        ]
+        .map(|response| response.status);
+        assert_eq!(statuses, expected, "{rank}");
+    }
+    let added: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE username LIKE 'new-%'")
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    assert_eq!(added, 1);
+
+    for (method, uri) in [
+        ("POST", "/api/admin/user"),
+        ("POST", "/api/admin/settings/domain?domain=example.com"),
+        ("PUT", "/api/admin/comment?id=1&status=Approved"),
+    ] {
+        let response = send(&app, method, uri, None, None).await;
+        assert_eq!(response.status, S::UNAUTHORIZED, "{uri}");
+    }
+}
+
+#[tokio::test]
+async fn comments_of_moderators_are_approved() {
+    let (config, app) = app().await;
+    let pool = config.db_pool.as_ref().unwrap();
+    add_user(&config, "owner", Rank::Author).await;
+    let owner = sign_in(&app, "owner").await;
+    let post = others_post(&app, pool, &owner).await;
+    sqlx::query("UPDATE posts SET status = 'Published'")
+        .execute(pool)
+        .await
+        .unwrap();
+    for (rank, cookie) in users(&config, &app).await {
+        let form = [("post", post.to_string()), ("content", rank.to_string())];
+        let form: Vec<(&str, &str)> = form.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        let response = send(&app, "POST", "/api/comment", Some(&cookie), Some(&form)).await;
+        assert_eq!(response.status, OK);
+    }
+    let approved: Vec<String> =
+        sqlx::query_scalar("SELECT content FROM comments WHERE status = 'Approved' ORDER BY id")
+            .fetch_all(pool)
+            .await
+            .unwrap();
+    assert_eq!(approved, ["Editor", "Administrator"]);
+}
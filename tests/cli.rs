@@ -0,0 +1,283 @@
+use std::fs;
+
+use clap::Parser;
+use peroxide::{
+    auth::{
+        admin::list_users,
+        keys::KEYS_FILE,
+        password::{verify_password, Verification},
+        user::{Rank, User},
+    },
+    cli::{run, Args},
+    config::{PeroxideConfig, SiteConfig},
+    site::open_site,
+};
+use sqlx::SqlitePool;
+use tempfile::TempDir;
+
+async fn peroxide(args: &[&str]) -> anyhow::Result<()> {
+    run(Args::try_parse_from(std::iter::once("peroxide").chain(args.iter().copied())).unwrap())
+        .await
+}
+
+/// A new site in a temporary directory, with the path to it.
+async fn new_site() -> (TempDir, String) {
+    let dir = tempfile::tempdir().unwrap();
+    let site = dir.path().join("site").to_string_lossy().to_string();
+    let config = dir
+        .path()
+        .join("Peroxide.toml")
+        .to_string_lossy()
+        .to_string();
+    peroxide(&["-c", &config, "site", "new", &site])
+        .await
+        .unwrap();
+    (dir, site)
+}
+
+async fn add(site: &str, username: &str, rank: &str) {
+    let email = format!("{username}@example.com");
+    peroxide(&[
+        "user",
+        "add",
+        "--site",
+        site,
+        username,
+        "--name",
+        username,
+        "--email",
+        &email,
+        "--rank",
+        rank,
+        "--password",
+        "hunter22",
+    ])
+    .await
+    .unwrap();
+}
+
+async fn get_user(pool: &SqlitePool, username: &str) -> User {
+    let (salt, sh_pass, rank): (Vec<u8>, Vec<u8>, String) =
+        sqlx::query_as("SELECT salt, sh_pass, rank FROM users WHERE username = ?")
+            .bind(username)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+    User {
+        username: username.to_string(),
+        salt,
+        sh_pass,
+        rank: rank.parse().unwrap(),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn creates_sites() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir
+        .path()
+        .join("Peroxide.toml")
+        .to_string_lossy()
+        .to_string();
+    fs::write(
+        &config,
+        "directories = []\npanel_domain = \"0.0.0.0:4000\"\n",
+    )
+    .unwrap();
+    let site = dir.path().join("blog").to_string_lossy().to_string();
+    peroxide(&["-c", &config, "site", "new", &site])
+        .await
+        .unwrap();
+
+    assert!(fs::metadata(format!("{site}/templates/index.html")).is_ok());
+    assert!(fs::metadata(format!("{site}/static")).is_ok());
+    let config: PeroxideConfig = toml::from_str(&fs::read_to_string(&config).unwrap()).unwrap();
+    assert_eq!(config.directories, [site.as_str()]);
+    let site_config: SiteConfig =
+        toml::from_str(&fs::read_to_string(format!("{site}/PeroxideSite.toml")).unwrap()).unwrap();
+    assert!(site_config.routes.contains_key("/"));
+    peroxide(&["check", "--site", &site]).await.unwrap();
+    // Sites aren't overwritten
+    assert!(peroxide(&["site", "new", &site]).await.is_err());
+}
+
+#[tokio::test]
+async fn manages_users() {
+    let (_dir, site) = new_site().await;
+    add(&site, "jane", "administrator").await;
+    add(&site, "john", "author").await;
+    let config = open_site(&site).await.unwrap();
+    let pool = config.db_pool.as_ref().unwrap();
+    let users = list_users(pool).await.unwrap();
+    let ranks: Vec<(&str, Rank)> = users
+        .iter()
+        .map(|user| (user.username.as_str(), user.rank))
+        .collect();
+    assert_eq!(
+        ranks,
+        [("jane", Rank::Administrator), ("john", Rank::Author)]
+    );
+    // Usernames are unique
+    assert!(peroxide(&[
+        "user",
+        "add",
+        "--site",
+        &site,
+        "jane",
+        "--name",
+        "Jane",
+        "--email",
+        "other@example.com",
+        "--password",
+        "hunter22",
+    ])
+    .await
+    .is_err());
+
+    peroxide(&["user", "set-rank", "--site", &site, "john", "editor"])
+        .await
+        .unwrap();
+    assert_eq!(get_user(pool, "john").await.rank, Rank::Editor);
+    assert!(Args::try_parse_from([
+        "peroxide", "user", "set-rank", "--site", &site, "john", "admn"
+    ])
+    .is_err());
+    assert!(
+        peroxide(&["user", "set-rank", "--site", &site, "nobody", "author"])
+            .await
+            .is_err()
+    );
+
+    let john = get_user(pool, "john").await;
+    assert_eq!(verify_password(&john, "hunter22"), Verification::Valid);
+    peroxide(&[
+        "user",
+        "reset-password",
+        "--site",
+        &site,
+        "john",
+        "--password",
+        "swordfish",
+    ])
+    .await
+    .unwrap();
+    let john = get_user(pool, "john").await;
+    assert_eq!(verify_password(&john, "hunter22"), Verification::Invalid);
+    assert_eq!(verify_password(&john, "swordfish"), Verification::Valid);
+    peroxide(&[
+        "user",
+        "reset-password",
+        "--site",
+        &site,
+        "john",
+        "--require",
+    ])
+    .await
+    .unwrap();
+    let reset: i64 = sqlx::query_scalar("SELECT reset_password FROM users WHERE username = 'john'")
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    assert_eq!(reset, 1);
+}
+
+#[tokio::test]
+async fn deletes_users_with_their_posts_reassigned() {
+    let (_dir, site) = new_site().await;
+    add(&site, "jane", "administrator").await;
+    add(&site, "john", "author").await;
+    let config = open_site(&site).await.unwrap();
+    let pool = config.db_pool.as_ref().unwrap();
+    sqlx::query(
+        "INSERT INTO posts(name, content, owner, slug) VALUES('Hello', 'x', 'john', 'hello')",
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+
+    assert!(peroxide(&["user", "delete", "--site", &site, "john"])
+        .await
+        .is_err());
+    peroxide(&[
+        "user",
+        "delete",
+        "--site",
+        &site,
+        "john",
+        "--reassign",
+        "jane",
+    ])
+    .await
+    .unwrap();
+    let owner: String = sqlx::query_scalar("SELECT owner FROM posts")
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    assert_eq!(owner, "jane");
+    let users = list_users(pool).await.unwrap();
+    assert_eq!(users.len(), 1);
+    assert!(peroxide(&["user", "delete", "--site", &site, "john"])
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn check_reports_broken_sites() {
+    let (_dir, site) = new_site().await;
+    fs::write(format!("{site}/templates/index.html"), "{{ if open }}").unwrap();
+    fs::write(format!("{site}/templates/post.html"), "{ post.name }").unwrap();
+    let config = fs::read_to_string(format!("{site}/PeroxideSite.toml")).unwrap();
+    fs::write(
+        format!("{site}/PeroxideSite.toml"),
+        config.replace(
+            "path = \"index.html\"",
+            "path = \"index.html\"\ntemplate = \"post.html\"\npermalink = \"blog\"",
+        ),
+    )
+    .unwrap();
+
+    // Checking leaves the site as it is, keys that are missing are made once it is opened
+    let keys = format!("{site}/{KEYS_FILE}");
+    fs::remove_file(&keys).unwrap();
+
+    let problems = peroxide::site::check_site(&site).await;
+    assert_eq!(problems.len(), 2, "{problems:?}");
+    assert!(problems[0].contains("index.html"), "{problems:?}");
+    assert!(problems[1].contains("permalink"), "{problems:?}");
+    assert!(peroxide(&["check", "--site", &site]).await.is_err());
+    assert!(!std::path::Path::new(&keys).exists());
+}
+
+#[test]
+fn wordpress_imports_need_one_source() {
+    let parse = |args: &[&str]| {
+        Args::try_parse_from(
+            ["peroxide", "import", "wordpress"]
+                .iter()
+                .chain(args.iter()),
+        )
+    };
+    assert!(parse(&[]).is_err());
+    assert!(parse(&["https://example.com", "-x", "export.xml"]).is_err());
+    assert!(parse(&["https://example.com"]).is_ok());
+    assert!(parse(&["-x", "export.xml"]).is_ok());
+}
+
+#[tokio::test]
+async fn makes_invites() {
+    let (_dir, site) = new_site().await;
+    peroxide(&[
+        "user", "invite", "--site", &site, "--uses", "3", "--days", "2",
+    ])
+    .await
+    .unwrap();
+    let config = open_site(&site).await.unwrap();
+    let (uses, expires): (i64, Option<i64>) =
+        sqlx::query_as("SELECT uses_left, expires FROM invites")
+            .fetch_one(config.db_pool.as_ref().unwrap())
+            .await
+            .unwrap();
+    assert_eq!(uses, 3);
+    assert!(expires.is_some());
+}
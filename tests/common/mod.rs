@@ -0,0 +1,202 @@
+//! Helpers for driving a whole site through its router.
+#![allow(dead_code)]
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::ConnectInfo,
+    http::{header, Request, StatusCode},
+    Router,
+};
+use peroxide::{
+    auth::{admin::create_privileged, keys::SiteKeys, sign_up::UserSignUp, user::Rank},
+    config::{list_templates_default, SiteConfig},
+    site::{setup_db, setup_routes},
+};
+use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
+use tinytemplate_async::TinyTemplate;
+use tower::ServiceExt;
+
+pub const PASSWORD: &str = "correct horse battery staple";
+
+/// An empty database kept in memory, without any tables yet.
+pub async fn memory_db() -> SqlitePool {
+    SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap()
+}
+
+/// A site with an empty database kept in memory.
+pub async fn site() -> SiteConfig {
+    site_with(memory_db().await).await
+}
+
+/// A site with the database of `pool`, after bringing it up to date.
+pub async fn site_with(pool: SqlitePool) -> SiteConfig {
+    setup_db(&pool).await.unwrap();
+    SiteConfig {
+        db_filename: String::new(),
+        db_pool: Some(pool),
+        domain: String::new(),
+        routes: HashMap::new(),
+        site_path: String::new(),
+        templates: Arc::new(RwLock::new(TinyTemplate::new())),
+        create_user: false,
+        sanitize: Default::default(),
+        feed: Default::default(),
+        robots: Default::default(),
+        mail: Default::default(),
+        registration: Default::default(),
+        list_templates: list_templates_default(),
+        forwarded_for: None,
+        keys: SiteKeys::generate(),
+        mailer: Default::default(),
+    }
+}
+
+/// Adds a user whose password is [`PASSWORD`].
+pub async fn add_user(config: &SiteConfig, username: &str, rank: Rank) {
+    create_privileged(
+        UserSignUp {
+            name: username.to_string(),
+            username: username.to_string(),
+            pass: PASSWORD.to_string(),
+            email: format!("{username}@example.com"),
+        },
+        rank,
+        config,
+    )
+    .await
+    .unwrap();
+}
+
+/// Encodes the fields as a multipart form, returning its content type and body.
+pub fn multipart(fields: &[(&str, &str)]) -> (String, String) {
+    let boundary = "peroxide-test-boundary";
+    let mut body = String::new();
+    for (name, value) in fields {
+        body.push_str(&format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+        ));
+    }
+    body.push_str(&format!("--{boundary}--\r\n"));
+    (format!("multipart/form-data; boundary={boundary}"), body)
+}
+
+pub struct Response {
+    pub status: StatusCode,
+    pub cookies: Vec<String>,
+    pub body: String,
+}
+
+impl Response {
+    pub fn json<T: serde::de::DeserializeOwned>(&self) -> T {
+        serde_json::from_str(&self.body).unwrap_or_else(|e| panic!("{e}: {}", self.body))
+    }
+}
+
+/// The address requests are sent from, like a client on the same machine.
+pub const CLIENT: ([u8; 4], u16) = ([127, 0, 0, 1], 50000);
+
+/// Sends a request from `CLIENT`, `form` fields are sent as multipart and `cookie` as the
+/// Cookie header.
+pub async fn send(
+    app: &Router,
+    method: &str,
+    uri: &str,
+    cookie: Option<&str>,
+    form: Option<&[(&str, &str)]>,
+) -> Response {
+    send_from(
+        app,
+        Some(SocketAddr::from(CLIENT)),
+        &[],
+        method,
+        uri,
+        cookie,
+        form,
+    )
+    .await
+}
+
+/// Sends a request with extra `headers`, from `client` or from a connection whose address is
+/// unknown.
+pub async fn send_from(
+    app: &Router,
+    client: Option<SocketAddr>,
+    headers: &[(&str, &str)],
+    method: &str,
+    uri: &str,
+    cookie: Option<&str>,
+    form: Option<&[(&str, &str)]>,
+) -> Response {
+    let mut request = Request::builder().method(method).uri(uri);
+    if let Some(client) = client {
+        request = request.extension(ConnectInfo(client));
+    }
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    if let Some(cookie) = cookie {
+        request = request.header(header::COOKIE, cookie);
+    }
+    let body = match form {
+        Some(fields) => {
+            let (content_type, body) = multipart(fields);
+            request = request.header(header::CONTENT_TYPE, content_type);
+            Body::from(body)
+        }
+        None => Body::empty(),
+    };
+    let response = app
+        .clone()
+        .oneshot(request.body(body).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let cookies = response
+        .headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .map(|value| {
+            let value = value.to_str().unwrap();
+            value.split(';').next().unwrap_or(value).to_string()
+        })
+        .collect();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    Response {
+        status,
+        cookies,
+        body: String::from_utf8_lossy(&body).to_string(),
+    }
+}
+
+/// Signs in and returns the cookies to send along with requests.
+pub async fn sign_in(app: &Router, username: &str) -> String {
+    let response = send(
+        app,
+        "PUT",
+        "/api/user",
+        None,
+        Some(&[("username", username), ("pass", PASSWORD)]),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    response.cookies.join("; ")
+}
+
+/// The router of the site, along with it.
+pub async fn app() -> (SiteConfig, Router) {
+    let config = site().await;
+    let app = setup_routes(&config);
+    (config, app)
+}
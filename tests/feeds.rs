@@ -0,0 +1,131 @@
+mod common;
+
+use axum::{http::StatusCode, Router};
+use common::{add_user, send, sign_in, site};
+use peroxide::{
+    auth::user::Rank,
+    config::{FeedConfig, PagePath, SiteConfig},
+    site::setup_routes,
+};
+
+/// A site whose posts are served under `/blog`, with a few posts by jane and john.
+async fn blog(feed: FeedConfig) -> (SiteConfig, Router) {
+    let mut config = site().await;
+    config.domain = "example.com".to_string();
+    config.feed = feed;
+    config.routes.insert(
+        "/blog".to_string(),
+        PagePath {
+            path: "blog.html".to_string(),
+            template: Some("post.html".to_string()),
+            permalink: Some("/blog/:year/:slug".to_string()),
+        },
+    );
+    let app = setup_routes(&config);
+    add_user(&config, "jane", Rank::Author).await;
+    add_user(&config, "john", Rank::Author).await;
+    let posts = [
+        ("jane", "First & best", "Published", "rust", 1709812800),
+        ("jane", "Second", "Published", "web", 1709899200),
+        ("john", "Third", "Published", "rust", 1709985600),
+        ("john", "Unfinished", "Draft", "rust", 1710072000),
+    ];
+    for (owner, name, status, tag, date) in posts {
+        let cookie = sign_in(&app, owner).await;
+        let content = format!("<p>{name} has <em>some</em> words in it.</p>");
+        let response = send(
+            &app,
+            "POST",
+            "/api/post",
+            Some(&cookie),
+            Some(&[
+                ("name", name),
+                ("content", &content),
+                ("status", status),
+                ("tags", tag),
+            ]),
+        )
+        .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        sqlx::query("UPDATE posts SET date = ? WHERE name = ?")
+            .bind(date)
+            .bind(name)
+            .execute(config.db_pool.as_ref().unwrap())
+            .await
+            .unwrap();
+    }
+    (config, app)
+}
+
+/// Texts of the elements named `name` in the feed at `uri`, empty ones are left out.
+async fn texts(app: &Router, uri: &str, name: &str) -> Vec<String> {
+    let response = send(app, "GET", uri, None, None).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let document = roxmltree::Document::parse(&response.body).unwrap();
+    document
+        .descendants()
+        .filter(|node| node.tag_name().name() == name)
+        .filter_map(|node| node.text().map(str::to_string))
+        .collect()
+}
+
+#[tokio::test]
+async fn rss_has_the_newest_posts() {
+    let (_, app) = blog(FeedConfig {
+        items: 2,
+        ..Default::default()
+    })
+    .await;
+
+    let titles = texts(&app, "/feed.xml", "title").await;
+    assert_eq!(titles, ["example.com", "Third", "Second"]);
+    let links = texts(&app, "/feed.xml", "link").await;
+    assert_eq!(
+        links,
+        [
+            "https://example.com",
+            "https://example.com/blog/2024/third",
+            "https://example.com/blog/2024/second",
+        ]
+    );
+    let dates = texts(&app, "/feed.xml", "pubDate").await;
+    assert_eq!(dates[0], "Sat, 9 Mar 2024 12:00:00 +0000");
+    let descriptions = texts(&app, "/feed.xml", "description").await;
+    assert_eq!(descriptions[0], "Third has some words in it.");
+    assert!(texts(&app, "/feed.xml", "encoded").await.is_empty());
+}
+
+#[tokio::test]
+async fn feeds_per_tag_and_author() {
+    let (_, app) = blog(FeedConfig::default()).await;
+
+    let titles = texts(&app, "/feed.xml?tag=rust", "title").await;
+    assert_eq!(titles, ["example.com", "Third", "First & best"]);
+    let titles = texts(&app, "/atom.xml?author=jane", "title").await;
+    assert_eq!(titles, ["example.com", "Second", "First & best"]);
+    let titles = texts(&app, "/feed.xml?author=jane&tag=web", "title").await;
+    assert_eq!(titles, ["example.com", "Second"]);
+    let titles = texts(&app, "/feed.xml?tag=missing", "title").await;
+    assert_eq!(titles, ["example.com"]);
+}
+
+#[tokio::test]
+async fn atom_with_full_text() {
+    let (_, app) = blog(FeedConfig {
+        title: "Jane's <blog>".to_string(),
+        full_text: true,
+        ..Default::default()
+    })
+    .await;
+
+    let titles = texts(&app, "/atom.xml", "title").await;
+    assert_eq!(titles, ["Jane's <blog>", "Third", "Second", "First & best"]);
+    let updated = texts(&app, "/atom.xml", "updated").await;
+    assert_eq!(updated[0], "2024-03-09T12:00:00+00:00");
+    let ids = texts(&app, "/atom.xml", "id").await;
+    assert_eq!(ids[0], "https://example.com/atom.xml");
+    let content = texts(&app, "/atom.xml", "content").await;
+    assert_eq!(content[0], "<p>Third has <em>some</em> words in it.</p>");
+    let encoded = texts(&app, "/feed.xml", "encoded").await;
+    assert_eq!(encoded.len(), 3);
+}
@@ -0,0 +1,112 @@
+-- A database as the last version without migrations left it, with a bit of everything in it.
+-- Its schema is the one that version created with CREATE TABLE IF NOT EXISTS, so it has every
+-- table up to comments and the search index. Databases of older versions, with only users and
+-- posts, are covered by `adopts_databases_missing_later_columns` in tests/migrations.rs.
+CREATE TABLE IF NOT EXISTS users(
+    salt blob unique not null,
+    name text not null,
+    username text unique not null primary key,
+    profile_pic text,
+    sh_pass blob not null,
+    email text not null unique,
+    rank text not null,
+    reset_password integer not null default 0
+) STRICT;
+CREATE TABLE IF NOT EXISTS posts(
+    id INTEGER NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL,
+    slug TEXT,
+    content TEXT NOT NULL,
+    date INTEGER NOT NULL DEFAULT (unixepoch(CURRENT_TIMESTAMP)),
+    status TEXT NOT NULL DEFAULT 'Draft',
+    owner TEXT NOT NULL,
+    publish_at INTEGER,
+    format TEXT NOT NULL DEFAULT 'Html',
+    rendered TEXT,
+    toc TEXT,
+    FOREIGN KEY(owner) REFERENCES users(username)
+) STRICT;
+CREATE UNIQUE INDEX IF NOT EXISTS posts_slug ON posts(slug);
+CREATE TABLE IF NOT EXISTS tags(
+    id INTEGER NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL,
+    slug TEXT NOT NULL UNIQUE
+) STRICT;
+CREATE TABLE IF NOT EXISTS post_tags(
+    post INTEGER NOT NULL,
+    tag INTEGER NOT NULL,
+    PRIMARY KEY(post, tag),
+    FOREIGN KEY(post) REFERENCES posts(id) ON DELETE CASCADE,
+    FOREIGN KEY(tag) REFERENCES tags(id) ON DELETE CASCADE
+) STRICT;
+CREATE TABLE IF NOT EXISTS categories(
+    id INTEGER NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL,
+    slug TEXT NOT NULL UNIQUE,
+    description TEXT NOT NULL DEFAULT '',
+    parent INTEGER,
+    FOREIGN KEY(parent) REFERENCES categories(id) ON DELETE SET NULL
+) STRICT;
+CREATE TABLE IF NOT EXISTS post_categories(
+    post INTEGER NOT NULL,
+    category INTEGER NOT NULL,
+    PRIMARY KEY(post, category),
+    FOREIGN KEY(post) REFERENCES posts(id) ON DELETE CASCADE,
+    FOREIGN KEY(category) REFERENCES categories(id) ON DELETE CASCADE
+) STRICT;
+CREATE TABLE IF NOT EXISTS post_revisions(
+    id INTEGER NOT NULL PRIMARY KEY,
+    post INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    content TEXT NOT NULL,
+    tags TEXT NOT NULL DEFAULT '[]',
+    status TEXT NOT NULL,
+    format TEXT NOT NULL DEFAULT 'Html',
+    editor TEXT,
+    date INTEGER NOT NULL DEFAULT (unixepoch(CURRENT_TIMESTAMP)),
+    FOREIGN KEY(post) REFERENCES posts(id) ON DELETE CASCADE,
+    FOREIGN KEY(editor) REFERENCES users(username) ON DELETE SET NULL
+) STRICT;
+CREATE TABLE IF NOT EXISTS media(
+    id INTEGER NOT NULL PRIMARY KEY,
+    path TEXT NOT NULL UNIQUE,
+    source_url TEXT,
+    mime_type TEXT NOT NULL,
+    alt_text TEXT NOT NULL DEFAULT '',
+    caption TEXT NOT NULL DEFAULT '',
+    date INTEGER NOT NULL DEFAULT (unixepoch(CURRENT_TIMESTAMP))
+) STRICT;
+CREATE TABLE IF NOT EXISTS comments(
+    id INTEGER NOT NULL PRIMARY KEY,
+    post INTEGER NOT NULL,
+    parent INTEGER,
+    author TEXT,
+    author_name TEXT NOT NULL,
+    author_url TEXT NOT NULL DEFAULT '',
+    content TEXT NOT NULL,
+    date INTEGER NOT NULL DEFAULT (unixepoch(CURRENT_TIMESTAMP)),
+    status TEXT NOT NULL DEFAULT 'Pending',
+    FOREIGN KEY(post) REFERENCES posts(id) ON DELETE CASCADE,
+    FOREIGN KEY(parent) REFERENCES comments(id) ON DELETE CASCADE,
+    FOREIGN KEY(author) REFERENCES users(username)
+) STRICT;
+CREATE VIRTUAL TABLE IF NOT EXISTS posts_search USING fts5(
+    name,
+    body,
+    tokenize = 'porter unicode61'
+);
+CREATE TRIGGER IF NOT EXISTS posts_search_delete AFTER DELETE ON posts BEGIN
+    DELETE FROM posts_search WHERE rowid = old.id;
+END;
+
+INSERT INTO users(salt, name, username, sh_pass, email, rank)
+    VALUES(X'01', 'Jane', 'jane', X'02', 'jane@example.com', 'Admin');
+INSERT INTO posts(id, name, slug, content, date, status, owner, format, rendered, toc)
+    VALUES(1, 'Hello world', 'hello-world', '<p>Migrations keep everything.</p>', 1709812800,
+    'Published', 'jane', 'Html', '<p>Migrations keep everything.</p>', '[]');
+INSERT INTO tags(id, name, slug) VALUES(1, 'Rust', 'rust');
+INSERT INTO post_tags(post, tag) VALUES(1, 1);
+INSERT INTO post_revisions(post, name, content, status, editor)
+    VALUES(1, 'Hello', '<p>Draft</p>', 'Draft', 'jane');
+INSERT INTO comments(post, author, author_name, content, status)
+    VALUES(1, 'jane', 'Jane', 'First!', 'Approved');
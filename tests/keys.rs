@@ -0,0 +1,136 @@
+mod common;
+
+use axum::{http::StatusCode, Router};
+use clap::Parser;
+use common::{add_user, memory_db, send, sign_in, site, site_with};
+use peroxide::{
+    auth::{
+        keys::{SiteKeys, KEYS_FILE},
+        user::Rank,
+    },
+    cli::{run, Args},
+    config::SiteConfig,
+    site::setup_routes,
+};
+
+async fn status(app: &Router, cookie: &str) -> StatusCode {
+    send(app, "GET", "/api/user/sessions", Some(cookie), None)
+        .await
+        .status
+}
+
+/// The key id a token was signed with.
+fn kid(cookie: &str) -> String {
+    let token = cookie.trim_start_matches("jwt-token=");
+    jsonwebtoken::decode_header(token).unwrap().kid.unwrap()
+}
+
+#[tokio::test]
+async fn tokens_only_work_on_their_site() {
+    // Two sites sharing a database, so only the keys tell them apart
+    let pool = memory_db().await;
+    let blog = site_with(pool.clone()).await;
+    let shop = site_with(pool).await;
+    add_user(&blog, "jane", Rank::Author).await;
+    let (blog, shop) = (setup_routes(&blog), setup_routes(&shop));
+
+    let cookie = sign_in(&blog, "jane").await;
+    assert_eq!(status(&blog, &cookie).await, StatusCode::OK);
+    assert_eq!(status(&shop, &cookie).await, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn rotated_keys_keep_verifying_until_retired() {
+    let mut config: SiteConfig = site().await;
+    add_user(&config, "jane", Rank::Author).await;
+    let old = sign_in(&setup_routes(&config), "jane").await;
+
+    let new_kid = config.keys.rotate().kid.clone();
+    let app = setup_routes(&config);
+    let new = sign_in(&app, "jane").await;
+    assert_eq!(kid(&new), new_kid);
+    assert_ne!(kid(&old), new_kid);
+    assert_eq!(status(&app, &old).await, StatusCode::OK);
+    assert_eq!(status(&app, &new).await, StatusCode::OK);
+
+    config.keys.retire_old();
+    assert_eq!(config.keys.keys().len(), 1);
+    let app = setup_routes(&config);
+    assert_eq!(status(&app, &old).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(status(&app, &new).await, StatusCode::OK);
+}
+
+#[test]
+fn rotation_drops_keys_replaced_long_ago() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+    // The first key was replaced long ago, the second only by the current one
+    std::fs::write(
+        dir.path().join(KEYS_FILE),
+        r#"
+        [[keys]]
+        kid = "ancient"
+        secret = "c2VjcmV0"
+        created = 1000
+
+        [[keys]]
+        kid = "old"
+        secret = "c2VjcmV0"
+        created = 2000
+
+        [[keys]]
+        kid = "current"
+        secret = "c2VjcmV0"
+        created = 9999999999
+        "#,
+    )
+    .unwrap();
+    let mut keys = SiteKeys::load_or_generate(path).unwrap();
+    keys.rotate();
+    let kids: Vec<&str> = keys.keys().iter().map(|key| key.kid.as_str()).collect();
+    assert_eq!(kids[..2], ["old", "current"]);
+    assert_eq!(kids.len(), 3);
+}
+
+#[tokio::test]
+async fn keys_are_generated_once_and_rotated_from_the_cli() {
+    let dir = tempfile::tempdir().unwrap();
+    let site = dir.path().join("site").to_string_lossy().to_string();
+    let cli =
+        |args: &[&str]| run(Args::try_parse_from(["peroxide"].iter().chain(args.iter())).unwrap());
+    // Without a config to add the site to
+    let config = dir
+        .path()
+        .join("Peroxide.toml")
+        .to_string_lossy()
+        .to_string();
+    cli(&["-c", &config, "site", "new", &site]).await.unwrap();
+    let created = SiteKeys::load_or_generate(&site).unwrap();
+    assert_eq!(created.keys().len(), 1);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let file = std::fs::metadata(format!("{site}/{KEYS_FILE}")).unwrap();
+        assert_eq!(file.permissions().mode() & 0o777, 0o600);
+    }
+    let opened = peroxide::site::open_site(&site).await.unwrap();
+    assert_eq!(opened.keys.keys()[0].kid, created.keys()[0].kid);
+
+    cli(&["site", "rotate-key", &site]).await.unwrap();
+    let rotated = SiteKeys::load_or_generate(&site).unwrap();
+    assert_eq!(rotated.keys().len(), 2);
+    assert_eq!(rotated.keys()[0].kid, created.keys()[0].kid);
+
+    cli(&["site", "rotate-key", &site, "--retire-old"])
+        .await
+        .unwrap();
+    let retired = SiteKeys::load_or_generate(&site).unwrap();
+    assert_eq!(retired.keys().len(), 1);
+    assert!(rotated
+        .keys()
+        .iter()
+        .all(|key| key.kid != retired.keys()[0].kid));
+    assert!(cli(&["site", "rotate-key", dir.path().to_str().unwrap()])
+        .await
+        .is_err());
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{add_user, app, send, sign_in};
+use peroxide::{
+    auth::user::Rank,
+    config::SanitizeConfig,
+    post::{Post, PostList},
+    render::{render, render_stale_posts, ContentFormat, Heading},
+};
+
+#[test]
+fn renders_markdown() {
+    let rendered = render(
+        "# Intro\n\nSome *text* :rocket:\n\n## Usage\n\n## Usage\n\n```rust\nfn main() {}\n```\n",
+        ContentFormat::Markdown,
+        &SanitizeConfig::default(),
+    );
+    assert!(rendered
+        .html
+        .contains("<h1 id=\"user-content-intro\">Intro</h1>"));
+    assert!(
+        rendered.html.contains("<em>text</em> 🚀"),
+        "{}",
+        rendered.html
+    );
+    assert!(rendered
+        .html
+        .contains("<pre class=\"syntax-highlighting\">"));
+    assert!(
+        rendered.html.contains("<span class=\""),
+        "{}",
+        rendered.html
+    );
+    assert_eq!(
+        rendered.toc.headings,
+        [
+            Heading {
+                level: 1,
+                title: "Intro".into(),
+                anchor: "user-content-intro".into()
+            },
+            Heading {
+                level: 2,
+                title: "Usage".into(),
+                anchor: "user-content-usage".into()
+            },
+            Heading {
+                level: 2,
+                title: "Usage".into(),
+                anchor: "user-content-usage-1".into()
+            },
+        ]
+    );
+}
+
+#[test]
+fn sanitizes_rendered_markdown() {
+    let rendered = render(
+        "<script>alert(1)</script>\n\n[x](javascript:alert(1)) <img src=x onerror=alert(1)>",
+        ContentFormat::Markdown,
+        &SanitizeConfig::default(),
+    );
+    assert!(!rendered.html.contains("<script"), "{}", rendered.html);
+    assert!(!rendered.html.contains("javascript:"), "{}", rendered.html);
+    assert!(!rendered.html.contains("onerror"), "{}", rendered.html);
+}
+
+#[test]
+fn html_is_only_sanitized() {
+    let rendered = render(
+        "<h2>Plain</h2><p onclick=\"x()\">text</p>",
+        ContentFormat::Html,
+        &SanitizeConfig::default(),
+    );
+    assert_eq!(rendered.html, "<h2>Plain</h2><p>text</p>");
+    assert!(rendered.toc.headings.is_empty());
+}
+
+#[tokio::test]
+async fn caches_the_rendered_post() {
+    let (config, app) = app().await;
+    add_user(&config, "jane", Rank::Author).await;
+    let jane = sign_in(&app, "jane").await;
+    let response = send(
+        &app,
+        "POST",
+        "/api/post",
+        Some(&jane),
+        Some(&[
+            ("name", "Notes"),
+            ("content", "# Title\n\n1 < 2"),
+            ("format", "Markdown"),
+        ]),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let list: PostList = send(&app, "GET", "/api/posts", Some(&jane), None)
+        .await
+        .json();
+    let post = &list.posts[0];
+    // The source is handed out as it was written
+    assert_eq!(post.content, "# Title\n\n1 < 2");
+    assert!(
+        post.rendered.contains("<p>1 &lt; 2</p>"),
+        "{}",
+        post.rendered
+    );
+    assert_eq!(post.toc.headings[0].anchor, "user-content-title");
+
+    let uri = format!("/api/post?id={}", post.id);
+    let response = send(
+        &app,
+        "PATCH",
+        &uri,
+        Some(&jane),
+        Some(&[("content", "## Changed")]),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let post: Post = send(&app, "GET", &uri, Some(&jane), None).await.json();
+    assert_eq!(
+        post.rendered,
+        "<h2 id=\"user-content-changed\">Changed</h2>\n"
+    );
+}
+
+#[tokio::test]
+async fn renders_posts_from_older_versions() {
+    let (config, _) = app().await;
+    add_user(&config, "jane", Rank::Author).await;
+    let pool = config.db_pool.unwrap();
+    sqlx::query(
+        "INSERT INTO posts(id, name, content, owner, format) VALUES(1, 'Old', '*old*', 'jane', 'Markdown')",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    render_stale_posts(&pool, &config.sanitize).await.unwrap();
+    let rendered: String = sqlx::query_scalar("SELECT rendered FROM posts WHERE id = 1")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(rendered, "<p><em>old</em></p>\n");
+}
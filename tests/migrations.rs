@@ -0,0 +1,165 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{add_user, memory_db, send, sign_in, site_with};
+use peroxide::{
+    auth::user::Rank,
+    migrate::{migrate, rollback, status, MigrationError, MIGRATIONS},
+    post::Post,
+    site::setup_routes,
+};
+use serde_json::Value;
+use sqlx::{Executor, SqlitePool};
+
+async fn tables(pool: &SqlitePool) -> Vec<String> {
+    sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")
+        .fetch_all(pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn upgrades_databases_from_before_migrations() {
+    let pool = memory_db().await;
+    pool.execute(include_str!("fixtures/unversioned.sql"))
+        .await
+        .unwrap();
+    let config = site_with(pool.clone()).await;
+    let status = status(&pool).await.unwrap();
+    assert_eq!(status.len(), MIGRATIONS.len());
+    assert!(status.iter().all(|migration| migration.applied.is_some()));
+
+    // Everything that was there is still there
+    let app = setup_routes(&config);
+    let post: Post = send(&app, "GET", "/api/post?id=1", None, None).await.json();
+    assert_eq!(post.name, "Hello world");
+    assert_eq!(post.tags.data, ["Rust"]);
+    let comments = send(&app, "GET", "/api/comment?post=1", None, None).await;
+    assert!(comments.body.contains("First!"), "{}", comments.body);
+    let results: Value = send(&app, "GET", "/api/search?q=migrations", None, None)
+        .await
+        .json();
+    assert_eq!(results["total"], 1);
+
+    // And it can be used like a new one
+    add_user(&config, "john", Rank::Author).await;
+    let cookie = sign_in(&app, "john").await;
+    let response = send(
+        &app,
+        "POST",
+        "/api/post",
+        Some(&cookie),
+        Some(&[("name", "Hello world"), ("content", "<p>Again</p>")]),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let slug: String = sqlx::query_scalar("SELECT slug FROM posts WHERE owner = 'john'")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(slug, "hello-world-2");
+}
+
+#[tokio::test]
+async fn adopts_databases_missing_later_columns() {
+    // The tables as the first version created them. It stored tags as strings prefixed with
+    // their length as a little endian u64, and posts without tags as NULL.
+    let pool = memory_db().await;
+    pool.execute(
+        "CREATE TABLE users(
+            salt blob unique not null,
+            name text not null,
+            username text unique not null primary key,
+            profile_pic text,
+            sh_pass blob not null,
+            email text not null unique,
+            rank text not null
+        ) STRICT;
+        CREATE TABLE posts(
+            id INTEGER NOT NULL PRIMARY KEY,
+            name TEXT NOT NULL,
+            content TEXT NOT NULL,
+            date INTEGER NOT NULL DEFAULT (unixepoch(CURRENT_TIMESTAMP)),
+            tags BLOB DEFAULT X'',
+            status TEXT NOT NULL DEFAULT 'Draft',
+            owner TEXT NOT NULL,
+            FOREIGN KEY(owner) REFERENCES users(username)
+        ) STRICT;
+        INSERT INTO users VALUES(X'01', 'Jane', 'jane', NULL, X'02', 'jane@example.com', 'User');
+        INSERT INTO posts(id, name, content, tags, status, owner) VALUES
+            -- [\"rust\", \"web dev\"]
+            (1, 'Old post', 'Old', X'040000000000000072757374070000000000000077656220646576',
+                'Published', 'jane'),
+            (2, 'Untagged', 'Old', NULL, 'Published', 'jane');
+        INSERT INTO posts(id, name, content, status, owner)
+            VALUES(3, 'Default', 'Old', 'Published', 'jane');",
+    )
+    .await
+    .unwrap();
+    let config = site_with(pool.clone()).await;
+
+    let (slug, format): (String, String) =
+        sqlx::query_as("SELECT slug, format FROM posts WHERE id = 1")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!((slug.as_str(), format.as_str()), ("old-post", "Html"));
+    let (reset, rank): (i64, String) = sqlx::query_as("SELECT reset_password, rank FROM users")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    // Users of older versions wrote and published their own posts
+    assert_eq!((reset, rank.as_str()), (0, "Author"));
+    let app = setup_routes(&config);
+    for (id, tags) in [(1, vec!["rust", "web dev"]), (2, vec![]), (3, vec![])] {
+        let uri = format!("/api/post?id={id}");
+        let post: Post = send(&app, "GET", &uri, None, None).await.json();
+        assert_eq!(post.tags.data, tags, "{}", post.name);
+    }
+}
+
+#[tokio::test]
+async fn runs_and_rolls_back() {
+    let pool = memory_db().await;
+    let pending = status(&pool).await.unwrap();
+    assert!(pending.iter().all(|migration| migration.applied.is_none()));
+    // Looking doesn't change anything
+    assert!(tables(&pool).await.is_empty());
+
+    assert_eq!(migrate(&pool, Some(0)).await.unwrap(), Vec::<i64>::new());
+    let all: Vec<i64> = MIGRATIONS
+        .iter()
+        .map(|migration| migration.version)
+        .collect();
+    assert_eq!(migrate(&pool, None).await.unwrap(), all);
+    assert!(tables(&pool).await.contains(&"posts".to_string()));
+    assert_eq!(migrate(&pool, None).await.unwrap(), Vec::<i64>::new());
+
+    let mut newest_first = all.clone();
+    newest_first.reverse();
+    assert_eq!(rollback(&pool, 0).await.unwrap(), newest_first);
+    assert_eq!(tables(&pool).await, ["schema_version"]);
+    assert_eq!(migrate(&pool, None).await.unwrap(), all);
+}
+
+#[tokio::test]
+async fn refuses_migrations_from_newer_versions() {
+    let pool = memory_db().await;
+    migrate(&pool, None).await.unwrap();
+    sqlx::query("INSERT INTO schema_version(version, name) VALUES(9999, 'future')")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let status = status(&pool).await.unwrap();
+    assert_eq!(status.last().unwrap().name, "future");
+    assert!(matches!(
+        migrate(&pool, None).await,
+        Err(MigrationError::Unknown { version: 9999, .. })
+    ));
+    assert!(matches!(
+        rollback(&pool, 0).await,
+        Err(MigrationError::Unknown { version: 9999, .. })
+    ));
+    assert!(tables(&pool).await.contains(&"posts".to_string()));
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use axum::{http::StatusCode, Router};
+use common::{add_user, app, send, sign_in, PASSWORD};
+use peroxide::{auth::user::Rank, config::SiteConfig};
+use sha3::{Digest, Sha3_512};
+
+async fn stored_hash(config: &SiteConfig, username: &str) -> String {
+    let hash: Vec<u8> = sqlx::query_scalar("SELECT sh_pass FROM users WHERE username = ?")
+        .bind(username)
+        .fetch_one(config.db_pool.as_ref().unwrap())
+        .await
+        .unwrap();
+    String::from_utf8_lossy(&hash).to_string()
+}
+
+async fn try_sign_in(app: &Router, username: &str, pass: &str) -> StatusCode {
+    send(
+        app,
+        "PUT",
+        "/api/user",
+        None,
+        Some(&[("username", username), ("pass", pass)]),
+    )
+    .await
+    .status
+}
+
+#[tokio::test]
+async fn new_accounts_use_argon2id() {
+    let (config, app) = app().await;
+    add_user(&config, "jane", Rank::Author).await;
+
+    assert!(stored_hash(&config, "jane").await.starts_with("$argon2id$"));
+    sign_in(&app, "jane").await;
+    assert_eq!(
+        try_sign_in(&app, "jane", "wrong").await,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn legacy_hashes_are_upgraded_on_sign_in() {
+    let (config, app) = app().await;
+    // How older versions hashed passwords
+    let salt = [7u8; 64];
+    let mut hasher = Sha3_512::new();
+    hasher.update(salt);
+    hasher.update(PASSWORD.as_bytes());
+    let legacy: Vec<u8> = hasher.finalize()[..].into();
+    sqlx::query(
+        "INSERT INTO users(salt, name, username, sh_pass, email, rank) VALUES(?, 'Jane', 'jane', ?, 'jane@example.com', 'User')",
+    )
+    .bind(&salt[..])
+    .bind(&legacy)
+    .execute(config.db_pool.as_ref().unwrap())
+    .await
+    .unwrap();
+
+    // Wrong passwords don't touch the hash
+    assert_eq!(
+        try_sign_in(&app, "jane", "wrong").await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert!(!stored_hash(&config, "jane").await.starts_with("$argon2id$"));
+
+    let cookie = sign_in(&app, "jane").await;
+    assert!(stored_hash(&config, "jane").await.starts_with("$argon2id$"));
+    // The session is for the new hash
+    let response = send(
+        &app,
+        "POST",
+        "/api/post",
+        Some(&cookie),
+        Some(&[("name", "Hello"), ("content", "x")]),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    sign_in(&app, "jane").await;
+    assert_eq!(
+        try_sign_in(&app, "jane", "wrong").await,
+        StatusCode::UNAUTHORIZED
+    );
+}
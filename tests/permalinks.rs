@@ -0,0 +1,167 @@
+mod common;
+
+use axum::{http::StatusCode, Router};
+use common::{add_user, send, sign_in, site};
+use peroxide::{
+    auth::user::Rank,
+    config::{PagePath, SiteConfig},
+    post::Post,
+    site::{setup_db, setup_routes},
+};
+
+/// 2024-03-07, when the posts of these tests were posted.
+const DATE: i64 = 1709812800;
+
+/// A site with a blog whose posts are served under `permalink`, along with the cookie of
+/// an admin.
+async fn blog(permalink: Option<&str>) -> (SiteConfig, Router, String) {
+    let mut config = site().await;
+    config.routes.insert(
+        "/blog".to_string(),
+        PagePath {
+            path: "blog.html".to_string(),
+            template: Some("post.html".to_string()),
+            permalink: permalink.map(str::to_string),
+        },
+    );
+    {
+        let mut templates = config.templates.write().unwrap();
+        templates
+            .add_template("pages/blog".to_string(), "Blog".to_string())
+            .unwrap();
+        templates
+            .add_template(
+                "pages/blog.templ".to_string(),
+                "{name} at {permalink}".to_string(),
+            )
+            .unwrap();
+    }
+    let app = setup_routes(&config);
+    add_user(&config, "jane", Rank::Administrator).await;
+    let cookie = sign_in(&app, "jane").await;
+    (config, app, cookie)
+}
+
+/// Creates a post and returns it.
+async fn create_post(app: &Router, cookie: &str, fields: &[(&str, &str)]) -> Post {
+    let response = send(app, "POST", "/api/post", Some(cookie), Some(fields)).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let list: peroxide::post::PostList = send(
+        app,
+        "GET",
+        "/api/posts?sort=date&per_page=1",
+        Some(cookie),
+        None,
+    )
+    .await
+    .json();
+    list.posts.into_iter().next().unwrap()
+}
+
+#[tokio::test]
+async fn slugs_are_unique() {
+    let (config, app, cookie) = blog(None).await;
+    let fields = [("name", "Hello, World!"), ("content", "x")];
+    let first = create_post(&app, &cookie, &fields).await;
+    let second = create_post(&app, &cookie, &fields).await;
+    assert_eq!(first.slug, "hello-world");
+    assert_eq!(second.slug, "hello-world-2");
+    let custom = create_post(
+        &app,
+        &cookie,
+        &[("name", "Other"), ("slug", "My Slug"), ("content", "x")],
+    )
+    .await;
+    assert_eq!(custom.slug, "my-slug");
+
+    // Renaming keeps the slug, it only changes when asked to
+    let uri = format!("/api/post?id={}", second.id);
+    let response = send(
+        &app,
+        "PATCH",
+        &uri,
+        Some(&cookie),
+        Some(&[("name", "Renamed")]),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let post: Post = send(&app, "GET", &uri, Some(&cookie), None).await.json();
+    assert_eq!(post.slug, "hello-world-2");
+    let response = send(
+        &app,
+        "PATCH",
+        &uri,
+        Some(&cookie),
+        Some(&[("slug", "my-slug")]),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let post: Post = send(&app, "GET", "/api/post?slug=my-slug-2", Some(&cookie), None)
+        .await
+        .json();
+    assert_eq!(post.id, second.id);
+
+    // Posts from before slugs existed get one from their name
+    let pool = config.db_pool.unwrap();
+    sqlx::query("UPDATE posts SET slug = NULL WHERE id = ?")
+        .bind(first.id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    setup_db(&pool).await.unwrap();
+    let slug: String = sqlx::query_scalar("SELECT slug FROM posts WHERE id = ?")
+        .bind(first.id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(slug, "hello-world");
+}
+
+#[tokio::test]
+async fn serves_posts_under_their_permalink() {
+    let (config, app, cookie) = blog(Some("/blog/:year/:month/:slug")).await;
+    let post = create_post(
+        &app,
+        &cookie,
+        &[
+            ("name", "First post"),
+            ("content", "x"),
+            ("status", "Published"),
+        ],
+    )
+    .await;
+    sqlx::query("UPDATE posts SET date = ? WHERE id = ?")
+        .bind(DATE)
+        .bind(post.id)
+        .execute(config.db_pool.as_ref().unwrap())
+        .await
+        .unwrap();
+
+    let response = send(&app, "GET", "/blog/2024/03/first-post", None, None).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(response.body, "First post at /blog/2024/03/first-post");
+    let response = send(&app, "GET", "/blog", None, None).await;
+    assert_eq!(response.body, "Blog");
+
+    for uri in [
+        "/blog/2024/04/first-post",
+        "/blog/24/03/first-post",
+        "/blog/2024/03/missing",
+        "/blog/first-post",
+    ] {
+        let response = send(&app, "GET", uri, None, None).await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND, "{uri}");
+    }
+}
+
+#[tokio::test]
+async fn drafts_are_only_served_to_editors() {
+    let (_, app, cookie) = blog(None).await;
+    create_post(&app, &cookie, &[("name", "Draft"), ("content", "x")]).await;
+
+    let response = send(&app, "GET", "/blog/draft", None, None).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let response = send(&app, "GET", "/blog/draft", Some(&cookie), None).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(response.body, "Draft at /blog/draft");
+}
@@ -0,0 +1,157 @@
+mod common;
+
+use axum::{
+    body::to_bytes,
+    extract::{Query, State},
+    http::{HeaderMap, HeaderValue, StatusCode, Uri},
+    response::Response,
+};
+use common::{add_user, site};
+use peroxide::{
+    auth::user::{Rank, User},
+    config::SiteConfig,
+    post::{list_posts, PostList, PostListRequest},
+};
+
+/// A site with five posts by two owners, one of them a draft.
+async fn blog() -> SiteConfig {
+    let mut config = site().await;
+    add_user(&config, "jane", Rank::Administrator).await;
+    add_user(&config, "john", Rank::Author).await;
+    let pool = config.db_pool.clone().unwrap();
+    sqlx::query(
+        "INSERT INTO posts(id, name, content, rendered, date, status, owner) VALUES
+        (1, 'Alpha', '', '', 100, 'Published', 'jane'),
+        (2, 'Delta', '', '', 200, 'Published', 'john'),
+        (3, 'Charlie', '', '', 300, 'Draft', 'jane'),
+        (4, 'Bravo', '', '', 400, 'Published', 'jane'),
+        (5, 'Echo', '<script>alert(1)</script>', '<script>alert(1)</script>', 500, 'Published', 'john')",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    sqlx::query("INSERT INTO tags(id, name, slug) VALUES(1, 'Rust', 'rust')")
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query("INSERT INTO post_tags(post, tag) VALUES(2, 1), (3, 1), (4, 1)")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    {
+        let mut templates = config.templates.write().unwrap();
+        templates
+            .add_template(
+                "rows".to_string(),
+                "{{ for post in posts }}<tr>{post.name}</tr>{{ endfor }}|{next}".to_string(),
+            )
+            .unwrap();
+        templates
+            .add_template("admin".to_string(), "secret".to_string())
+            .unwrap();
+    }
+    config.list_templates.push("rows".to_string());
+    config
+}
+
+fn admin() -> User {
+    User {
+        name: "Jane".to_string(),
+        username: "jane".to_string(),
+        email: "jane@example.com".to_string(),
+        profile_pic: None,
+        salt: Vec::new(),
+        sh_pass: Vec::new(),
+        rank: Rank::Administrator,
+        reset_password: false,
+    }
+}
+
+async fn request(
+    user: Option<User>,
+    headers: HeaderMap,
+    query: &str,
+) -> Result<Response, StatusCode> {
+    let uri: Uri = format!("/api/posts?{query}").parse().unwrap();
+    let req: Query<PostListRequest> = Query::try_from_uri(&uri).unwrap();
+    list_posts(State(blog().await), user, headers, req).await
+}
+
+async fn fetch(user: Option<User>, query: &str) -> (Vec<String>, PostList) {
+    let response = request(user, HeaderMap::new(), query).await.unwrap();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let list: PostList = serde_json::from_slice(&body).unwrap();
+    (list.posts.iter().map(|p| p.name.clone()).collect(), list)
+}
+
+#[tokio::test]
+async fn newest_first_by_default() {
+    let (names, list) = fetch(Some(admin()), "").await;
+    assert_eq!(names, ["Echo", "Bravo", "Charlie", "Delta", "Alpha"]);
+    assert_eq!(list.total, 5);
+    assert_eq!(list.next, None);
+}
+
+#[tokio::test]
+async fn visitors_only_see_published_posts() {
+    let (names, _) = fetch(None, "").await;
+    assert_eq!(names, ["Echo", "Bravo", "Delta", "Alpha"]);
+    let (names, list) = fetch(None, "status=Draft").await;
+    assert!(names.is_empty());
+    assert_eq!(list.total, 0);
+}
+
+#[tokio::test]
+async fn paginates() {
+    let (names, list) = fetch(Some(admin()), "per_page=2&page=2&sort=name&order=asc").await;
+    assert_eq!(names, ["Charlie", "Delta"]);
+    assert_eq!(list.total, 5);
+    let next = list.next.unwrap();
+    assert!(next.contains("page=3"), "{next}");
+    assert!(next.contains("sort=name"), "{next}");
+
+    let (names, list) = fetch(Some(admin()), &next).await;
+    assert_eq!(names, ["Echo"]);
+    assert_eq!(list.next, None);
+}
+
+#[tokio::test]
+async fn filters() {
+    let (names, _) = fetch(Some(admin()), "owner=jane&status=Published").await;
+    assert_eq!(names, ["Bravo", "Alpha"]);
+    let (names, list) = fetch(Some(admin()), "tag=rust&order=asc").await;
+    assert_eq!(names, ["Delta", "Charlie", "Bravo"]);
+    assert_eq!(list.total, 3);
+    let (names, _) = fetch(Some(admin()), "after=200&before=500").await;
+    assert_eq!(names, ["Bravo", "Charlie", "Delta"]);
+}
+
+#[tokio::test]
+async fn sanitizes_content() {
+    let (_, list) = fetch(None, "per_page=1").await;
+    assert!(!list.posts[0].rendered.contains("<script>"));
+    assert!(!list.posts[0].content.contains("<script>"));
+    // The content is kept as it was written for those who can edit it
+    let (_, list) = fetch(Some(admin()), "per_page=1").await;
+    assert_eq!(list.posts[0].content, "<script>alert(1)</script>");
+}
+
+#[tokio::test]
+async fn renders_html_for_htmx() {
+    let mut headers = HeaderMap::new();
+    headers.insert("HX-Request", HeaderValue::from_static("true"));
+    let response = request(None, headers, "template=rows&per_page=2")
+        .await
+        .unwrap();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body.starts_with("<tr>Echo</tr><tr>Bravo</tr>|"), "{body}");
+    assert!(body.contains("page=2"), "{body}");
+
+    let missing = request(None, HeaderMap::new(), "format=html&template=missing").await;
+    assert_eq!(missing.err(), Some(StatusCode::BAD_REQUEST));
+    // Only the list templates of the site render lists, not the admin panel
+    let admin = request(None, HeaderMap::new(), "format=html&template=admin").await;
+    assert_eq!(admin.err(), Some(StatusCode::BAD_REQUEST));
+}
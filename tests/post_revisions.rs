@@ -0,0 +1,204 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{add_user, app, send, sign_in};
+use peroxide::{
+    auth::user::Rank,
+    post::{Post, PostStatus},
+    revision::{PostRevision, RevisionDiff, RevisionSummary},
+};
+
+/// Creates a post as the signed in user and returns its id.
+async fn create_post(app: &axum::Router, cookie: &str) -> i64 {
+    let response = send(
+        app,
+        "POST",
+        "/api/post",
+        Some(cookie),
+        Some(&[
+            ("name", "First"),
+            ("content", "one\ntwo\n"),
+            ("tags", "rust, web"),
+        ]),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let posts: peroxide::post::PostList = send(app, "GET", "/api/posts", Some(cookie), None)
+        .await
+        .json();
+    posts.posts[0].id
+}
+
+#[tokio::test]
+async fn edits_keep_revisions() {
+    let (config, app) = app().await;
+    add_user(&config, "jane", Rank::Author).await;
+    let jane = sign_in(&app, "jane").await;
+    let id = create_post(&app, &jane).await;
+
+    let response = send(
+        &app,
+        "PATCH",
+        &format!("/api/post?id={id}"),
+        Some(&jane),
+        Some(&[("content", "one\nthree\n"), ("status", "Published")]),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let response = send(
+        &app,
+        "PUT",
+        &format!("/api/post?id={id}"),
+        Some(&jane),
+        Some(&[("name", "Second"), ("tags", "[\"rust\"]")]),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+    let post: Post = send(
+        &app,
+        "GET",
+        &format!("/api/post?id={id}"),
+        Some(&jane),
+        None,
+    )
+    .await
+    .json();
+    assert_eq!(post.name, "Second");
+    assert_eq!(post.content, "one\nthree\n");
+    assert_eq!(post.status, PostStatus::Published);
+    assert_eq!(post.tags.data, ["rust"]);
+
+    let revisions: Vec<RevisionSummary> = send(
+        &app,
+        "GET",
+        &format!("/api/post/revisions?post={id}"),
+        Some(&jane),
+        None,
+    )
+    .await
+    .json();
+    assert_eq!(
+        revisions
+            .iter()
+            .map(|r| (r.name.as_str(), r.status, r.editor.as_deref()))
+            .collect::<Vec<_>>(),
+        [
+            ("First", PostStatus::Published, Some("jane")),
+            ("First", PostStatus::Draft, Some("jane")),
+        ]
+    );
+
+    let first = revisions[1].id;
+    let diff: RevisionDiff = send(
+        &app,
+        "GET",
+        &format!("/api/post/revision/diff?id={first}"),
+        Some(&jane),
+        None,
+    )
+    .await
+    .json();
+    assert!(diff.content.contains("-two\n+three\n"), "{}", diff.content);
+    assert!(diff.name.contains("-First") && diff.name.contains("+Second"));
+    assert_eq!(diff.tags_removed, ["web"]);
+    assert!(diff.tags_added.is_empty());
+    assert_eq!(
+        diff.status,
+        Some((PostStatus::Draft, PostStatus::Published))
+    );
+
+    let response = send(
+        &app,
+        "POST",
+        &format!("/api/post/revision/restore?id={first}"),
+        Some(&jane),
+        None,
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let post: Post = send(
+        &app,
+        "GET",
+        &format!("/api/post?id={id}"),
+        Some(&jane),
+        None,
+    )
+    .await
+    .json();
+    assert_eq!(post.name, "First");
+    assert_eq!(post.content, "one\ntwo\n");
+    assert_eq!(post.status, PostStatus::Draft);
+    assert_eq!(post.tags.data, ["rust", "web"]);
+
+    let revision: PostRevision = send(
+        &app,
+        "GET",
+        &format!("/api/post/revision?id={}", revisions[0].id),
+        Some(&jane),
+        None,
+    )
+    .await
+    .json();
+    assert_eq!(revision.content, "one\nthree\n");
+    let revisions: Vec<RevisionSummary> = send(
+        &app,
+        "GET",
+        &format!("/api/post/revisions?post={id}"),
+        Some(&jane),
+        None,
+    )
+    .await
+    .json();
+    assert_eq!(revisions.len(), 3);
+    assert_eq!(revisions[0].name, "Second");
+}
+
+#[tokio::test]
+async fn only_owners_and_admins_edit() {
+    let (config, app) = app().await;
+    add_user(&config, "jane", Rank::Author).await;
+    add_user(&config, "john", Rank::Author).await;
+    add_user(&config, "root", Rank::Administrator).await;
+    let jane = sign_in(&app, "jane").await;
+    let john = sign_in(&app, "john").await;
+    let root = sign_in(&app, "root").await;
+    let id = create_post(&app, &jane).await;
+    let uri = format!("/api/post?id={id}");
+    let form: &[(&str, &str)] = &[("name", "Renamed")];
+
+    assert_eq!(
+        send(&app, "PATCH", &uri, None, Some(form)).await.status,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        send(&app, "PATCH", &uri, Some(&john), Some(form))
+            .await
+            .status,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        send(
+            &app,
+            "GET",
+            &format!("/api/post/revisions?post={id}"),
+            Some(&john),
+            None
+        )
+        .await
+        .status,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        send(&app, "PATCH", &uri, Some(&root), Some(form))
+            .await
+            .status,
+        StatusCode::OK
+    );
+    assert_eq!(
+        send(&app, "PATCH", "/api/post?id=999", Some(&root), Some(form))
+            .await
+            .status,
+        StatusCode::NOT_FOUND
+    );
+}
@@ -0,0 +1,162 @@
+mod common;
+
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use common::{add_user, app, send, sign_in, site};
+use peroxide::{
+    auth::user::Rank,
+    post::{publish_scheduled_posts, spawn_publisher, Post, PostList, PostStatus},
+};
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+#[tokio::test]
+async fn publishes_due_posts() {
+    let config = site().await;
+    add_user(&config, "jane", Rank::Author).await;
+    let pool = config.db_pool.unwrap();
+    sqlx::query(
+        "INSERT INTO posts(id, name, content, status, owner, publish_at) VALUES
+        (1, 'Due', '', 'Scheduled', 'jane', ?1),
+        (2, 'Later', '', 'Scheduled', 'jane', ?2),
+        (3, 'Draft', '', 'Draft', 'jane', ?1)",
+    )
+    .bind(now() - 10)
+    .bind(now() + 3600)
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    assert_eq!(publish_scheduled_posts(&pool).await.unwrap(), 1);
+    let posts: Vec<(i64, String, i64)> =
+        sqlx::query_as("SELECT id, status, date FROM posts ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(posts[0].1, "Published");
+    assert_eq!(posts[0].2, now() - 10);
+    assert_eq!(posts[1].1, "Scheduled");
+    assert_eq!(posts[2].1, "Draft");
+}
+
+#[tokio::test]
+async fn publisher_wakes_up_when_a_post_is_due() {
+    let (config, app) = app().await;
+    add_user(&config, "jane", Rank::Author).await;
+    let jane = sign_in(&app, "jane").await;
+    let publish_at = (now() + 2).to_string();
+    let response = send(
+        &app,
+        "POST",
+        "/api/post",
+        Some(&jane),
+        Some(&[
+            ("name", "Soon"),
+            ("content", ""),
+            ("status", "Scheduled"),
+            ("publish_at", &publish_at),
+        ]),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+    let publisher = spawn_publisher(config.db_pool.clone().unwrap());
+    let list: PostList = send(&app, "GET", "/api/posts", None, None).await.json();
+    assert!(list.posts.is_empty());
+    tokio::time::sleep(Duration::from_secs(4)).await;
+    let list: PostList = send(&app, "GET", "/api/posts", None, None).await.json();
+    publisher.abort();
+    assert_eq!(list.posts.len(), 1);
+    assert_eq!(list.posts[0].status, PostStatus::Published);
+}
+
+#[tokio::test]
+async fn scheduling_needs_a_time() {
+    let (config, app) = app().await;
+    add_user(&config, "jane", Rank::Author).await;
+    let jane = sign_in(&app, "jane").await;
+    let response = send(
+        &app,
+        "POST",
+        "/api/post",
+        Some(&jane),
+        Some(&[("name", "Soon"), ("content", ""), ("status", "Scheduled")]),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn only_published_posts_are_public() {
+    let (config, app) = app().await;
+    add_user(&config, "jane", Rank::Author).await;
+    add_user(&config, "john", Rank::Author).await;
+    add_user(&config, "root", Rank::Administrator).await;
+    let statuses = [
+        "Draft",
+        "Pending",
+        "Scheduled",
+        "Published",
+        "Private",
+        "Trashed",
+    ];
+    for (id, status) in statuses.iter().enumerate() {
+        sqlx::query(
+            "INSERT INTO posts(id, name, content, status, owner, publish_at)
+            VALUES(?1, ?2, '', ?2, 'jane', ?3)",
+        )
+        .bind(id as i64 + 1)
+        .bind(status)
+        .bind(now() + 3600)
+        .execute(config.db_pool.as_ref().unwrap())
+        .await
+        .unwrap();
+    }
+    let jane = sign_in(&app, "jane").await;
+    let john = sign_in(&app, "john").await;
+    let root = sign_in(&app, "root").await;
+
+    for (id, status) in statuses.iter().enumerate() {
+        let uri = format!("/api/post?id={}", id + 1);
+        let public = *status == "Published";
+        for cookie in [None, Some(john.as_str())] {
+            let response = send(&app, "GET", &uri, cookie, None).await;
+            assert_eq!(response.status.is_success(), public, "{status}");
+        }
+        for cookie in [&jane, &root] {
+            let post: Post = send(&app, "GET", &uri, Some(cookie), None).await.json();
+            assert_eq!(post.status.to_string(), *status);
+        }
+    }
+
+    let names =
+        |list: PostList| -> Vec<String> { list.posts.into_iter().map(|p| p.name).collect() };
+    let list = send(&app, "GET", "/api/posts?sort=name&order=asc", None, None).await;
+    assert_eq!(names(list.json()), ["Published"]);
+    let list = send(
+        &app,
+        "GET",
+        "/api/posts?sort=name&order=asc",
+        Some(&john),
+        None,
+    )
+    .await;
+    assert_eq!(names(list.json()), ["Published"]);
+    let list = send(
+        &app,
+        "GET",
+        "/api/posts?sort=name&order=asc",
+        Some(&jane),
+        None,
+    )
+    .await;
+    assert_eq!(
+        names(list.json()),
+        ["Draft", "Pending", "Private", "Published", "Scheduled"]
+    );
+    let list = send(&app, "GET", "/api/posts?status=Trashed", Some(&root), None).await;
+    assert_eq!(names(list.json()), ["Trashed"]);
+}
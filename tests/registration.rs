@@ -0,0 +1,278 @@
+mod common;
+
+use axum::{http::StatusCode, Router};
+use common::{add_user, send, send_from, sign_in, site};
+use peroxide::{
+    auth::{
+        invite::{create_invite, Invite},
+        user::{Rank, UserInfo},
+    },
+    config::{RegistrationConfig, RegistrationPolicy, SiteConfig},
+    site::setup_routes,
+};
+
+async fn site_with_policy(policy: RegistrationPolicy) -> (SiteConfig, Router) {
+    let mut config = site().await;
+    config.registration = RegistrationConfig {
+        policy,
+        allowed_domains: vec!["example.com".to_string()],
+        attempts_per_hour: 100,
+        ..Default::default()
+    };
+    let app = setup_routes(&config);
+    (config, app)
+}
+
+async fn sign_up(app: &Router, username: &str, email: &str, invite: Option<&str>) -> StatusCode {
+    let mut form = vec![
+        ("name", username),
+        ("username", username),
+        ("pass", common::PASSWORD),
+        ("email", email),
+    ];
+    form.extend(invite.map(|invite| ("invite", invite)));
+    send(app, "POST", "/api/user", None, Some(&form))
+        .await
+        .status
+}
+
+#[tokio::test]
+async fn closed_by_default() {
+    let config = site().await;
+    assert_eq!(config.registration.policy, RegistrationPolicy::Closed);
+    let app = setup_routes(&config);
+    assert_eq!(
+        sign_up(&app, "jane", "jane@example.com", None).await,
+        StatusCode::FORBIDDEN
+    );
+}
+
+#[tokio::test]
+async fn open_sites_take_anyone() {
+    let (mut config, _) = site_with_policy(RegistrationPolicy::Open).await;
+    config.registration.default_rank = Rank::Contributor;
+    let app = setup_routes(&config);
+
+    let response = send(
+        &app,
+        "POST",
+        "/api/user",
+        None,
+        Some(&[
+            ("name", "Jane"),
+            ("username", "jane"),
+            ("pass", common::PASSWORD),
+            ("email", "jane@elsewhere.org"),
+        ]),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let user: UserInfo = response.json();
+    assert_eq!(user.rank, Rank::Contributor);
+    assert!(!user.email_verified);
+    sign_in(&app, "jane").await;
+
+    assert_eq!(
+        sign_up(&app, "jane", "other@example.com", None).await,
+        StatusCode::CONFLICT
+    );
+    assert_eq!(
+        sign_up(&app, "john", "jane@elsewhere.org", None).await,
+        StatusCode::CONFLICT
+    );
+    assert_eq!(
+        sign_up(&app, "john", "not an email", None).await,
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        sign_up(&app, "", "john@example.com", None).await,
+        StatusCode::BAD_REQUEST
+    );
+}
+
+#[tokio::test]
+async fn allowlists_email_domains() {
+    let (_, app) = site_with_policy(RegistrationPolicy::DomainAllowlist).await;
+    assert_eq!(
+        sign_up(&app, "jane", "jane@Example.COM", None).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        sign_up(&app, "john", "john@example.com.evil.org", None).await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        sign_up(&app, "jim", "jim@mail.example.com", None).await,
+        StatusCode::FORBIDDEN
+    );
+}
+
+#[tokio::test]
+async fn invites_are_used_up() {
+    let (config, app) = site_with_policy(RegistrationPolicy::InviteOnly).await;
+    let pool = config.db_pool.as_ref().unwrap();
+    add_user(&config, "admin", Rank::Administrator).await;
+    add_user(&config, "editor", Rank::Editor).await;
+    let admin = sign_in(&app, "admin").await;
+    let editor = sign_in(&app, "editor").await;
+
+    let uri = "/api/admin/invite?uses=2&days=7";
+    let response = send(&app, "POST", uri, Some(&editor), None).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    let response = send(&app, "POST", uri, Some(&admin), None).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let invite: Invite = response.json();
+    assert_eq!(invite.uses, 2);
+    assert!(invite.expires.is_some());
+
+    assert_eq!(
+        sign_up(&app, "jane", "jane@example.com", None).await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        sign_up(&app, "jane", "jane@example.com", Some("guessed")).await,
+        StatusCode::FORBIDDEN
+    );
+    let code = Some(invite.code.as_str());
+    assert_eq!(
+        sign_up(&app, "jane", "jane@example.com", code).await,
+        StatusCode::OK
+    );
+    // Sign ups that fail keep the invite
+    assert_eq!(
+        sign_up(&app, "jane", "jane@example.org", code).await,
+        StatusCode::CONFLICT
+    );
+    assert_eq!(
+        sign_up(&app, "john", "john@example.com", code).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        sign_up(&app, "jim", "jim@example.com", code).await,
+        StatusCode::FORBIDDEN
+    );
+
+    let expired = create_invite(pool, None, 1, Some(0)).await.unwrap();
+    assert_eq!(
+        sign_up(&app, "jim", "jim@example.com", Some(&expired.code)).await,
+        StatusCode::FORBIDDEN
+    );
+    let forever = create_invite(pool, None, 1, None).await.unwrap();
+    assert_eq!(
+        sign_up(&app, "jim", "jim@example.com", Some(&forever.code)).await,
+        StatusCode::OK
+    );
+}
+
+#[tokio::test]
+async fn limits_attempts() {
+    let (mut config, _) = site_with_policy(RegistrationPolicy::Open).await;
+    config.registration.attempts_per_hour = 2;
+    let app = setup_routes(&config);
+
+    assert_eq!(
+        sign_up(&app, "jane", "jane@example.com", None).await,
+        StatusCode::OK
+    );
+    // Failed attempts count as well
+    assert_eq!(
+        sign_up(&app, "jane", "jane@example.com", None).await,
+        StatusCode::CONFLICT
+    );
+    assert_eq!(
+        sign_up(&app, "john", "john@example.com", None).await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // Attempts from over an hour ago don't
+    sqlx::query("UPDATE sign_up_attempts SET attempted = attempted - 60 * 60")
+        .execute(config.db_pool.as_ref().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(
+        sign_up(&app, "john", "john@example.com", None).await,
+        StatusCode::OK
+    );
+}
+
+/// Signs up `username` through a proxy, with the `X-Forwarded-For` header it would send.
+async fn sign_up_via(app: &Router, forwarded_for: Option<&str>, username: &str) -> StatusCode {
+    let email = format!("{username}@example.com");
+    let form = [
+        ("name", username),
+        ("username", username),
+        ("pass", common::PASSWORD),
+        ("email", &email),
+    ];
+    let headers: Vec<_> = forwarded_for
+        .map(|addresses| ("X-Forwarded-For", addresses))
+        .into_iter()
+        .collect();
+    let proxy = Some(common::CLIENT.into());
+    send_from(app, proxy, &headers, "POST", "/api/user", None, Some(&form))
+        .await
+        .status
+}
+
+#[tokio::test]
+async fn turns_away_unknown_addresses() {
+    let (config, app) = site_with_policy(RegistrationPolicy::Open).await;
+    let form = [
+        ("name", "jane"),
+        ("username", "jane"),
+        ("pass", common::PASSWORD),
+        ("email", "jane@example.com"),
+    ];
+    let response = send_from(&app, None, &[], "POST", "/api/user", None, Some(&form)).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+    let pool = config.db_pool.as_ref().unwrap();
+    let (attempts,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM sign_up_attempts")
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    assert_eq!(attempts, 0);
+    let (users,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM users")
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    assert_eq!(users, 0);
+}
+
+#[tokio::test]
+async fn limits_forwarded_addresses_apart() {
+    let (mut config, _) = site_with_policy(RegistrationPolicy::Open).await;
+    config.registration.attempts_per_hour = 1;
+    let app = setup_routes(&config);
+
+    // Unless the site is behind a proxy the header is ignored, clients can make it up
+    assert_eq!(
+        sign_up_via(&app, Some("203.0.113.1"), "jane").await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        sign_up_via(&app, Some("203.0.113.2"), "john").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    config.forwarded_for = Some("X-Forwarded-For".to_string());
+    let app = setup_routes(&config);
+    // The proxy adds the address it saw last, whatever the client put before it
+    assert_eq!(
+        sign_up_via(&app, Some("203.0.113.2, 198.51.100.1"), "john").await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        sign_up_via(&app, Some("203.0.113.3, 198.51.100.1"), "jim").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(
+        sign_up_via(&app, Some("198.51.100.2"), "jim").await,
+        StatusCode::OK
+    );
+    // Nor does the address of the proxy stand in for a missing header
+    assert_eq!(
+        sign_up_via(&app, None, "joe").await,
+        StatusCode::BAD_REQUEST
+    );
+}
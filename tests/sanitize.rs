@@ -0,0 +1,224 @@
+mod common;
+
+use std::collections::HashMap;
+
+use axum::http::StatusCode;
+use common::{add_user, send, sign_in, site};
+use peroxide::{
+    auth::user::Rank,
+    config::SanitizeConfig,
+    post::Post,
+    render::{policy_fingerprint, render, render_stale_posts, sanitize, ContentFormat},
+    site::setup_routes,
+};
+
+/// Markup that runs script in some browser when it isn't sanitized.
+const VECTORS: &[&str] = &[
+    "<script>alert(1)</script>",
+    "<SCRIPT SRC=//evil.example/x.js></SCRIPT>",
+    "<scr<script>ipt>alert(1)</script>",
+    "<img src=x onerror=alert(1)>",
+    "<IMG SRC=\"javascript:alert(1)\">",
+    "<a href=\"javascript:alert(1)\">x</a>",
+    "<a href=\"JaVaScRiPt:alert(1)\">x</a>",
+    "<a href=\"&#106;avascript:alert(1)\">x</a>",
+    "<a href=\" javascript:alert(1)\">x</a>",
+    "<a href=\"vbscript:msgbox(1)\">x</a>",
+    "<a href=\"data:text/html;base64,PHNjcmlwdD5hbGVydCgxKTwvc2NyaXB0Pg==\">x</a>",
+    "<svg onload=alert(1)><circle r=1></circle></svg>",
+    "<math><mi xlink:href=\"javascript:alert(1)\">x</mi></math>",
+    "<iframe src=\"javascript:alert(1)\"></iframe>",
+    "<iframe srcdoc=\"<script>alert(1)</script>\"></iframe>",
+    "<object data=\"javascript:alert(1)\"></object>",
+    "<embed src=\"javascript:alert(1)\">",
+    "<style>body { background: url(javascript:alert(1)) }</style>",
+    "<div style=\"background:url(javascript:alert(1))\">x</div>",
+    "<body onload=alert(1)>",
+    "<details open ontoggle=alert(1)>",
+    "<form action=\"javascript:alert(1)\"><button>x</button></form>",
+    "<base href=\"//evil.example/\">",
+    "<meta http-equiv=\"refresh\" content=\"0;url=javascript:alert(1)\">",
+    "<noscript><p title=\"</noscript><img src=x onerror=alert(1)>\"></noscript>",
+    "<input type=\"image\" src=x onfocus=alert(1) autofocus>",
+    "<video><source onerror=alert(1)></video>",
+    "<a href=\"#\" onclick=\"alert(1)\">x</a>",
+];
+
+/// Tags that can run script or change how the page around them is loaded.
+const UNSAFE_TAGS: &[&str] = &[
+    "script", "style", "svg", "math", "object", "embed", "form", "base", "meta", "body", "noscript",
+];
+
+/// Panics when any tag of `html` is unsafe. Text is escaped by the sanitizer, so only the
+/// tags are looked at.
+fn assert_safe(html: &str, vector: &str) {
+    for tag in html.split('<').skip(1) {
+        let tag = tag.split('>').next().unwrap_or_default().to_lowercase();
+        let name = tag
+            .trim_start_matches('/')
+            .split_whitespace()
+            .next()
+            .unwrap_or_default();
+        assert!(!UNSAFE_TAGS.contains(&name), "{vector} gave {html}");
+        for attribute in tag.split_whitespace().skip(1) {
+            assert!(!attribute.starts_with("on"), "{vector} gave {html}");
+            assert!(!attribute.starts_with("srcdoc"), "{vector} gave {html}");
+            assert!(!attribute.starts_with("style"), "{vector} gave {html}");
+        }
+        for scheme in ["javascript:", "vbscript:", "data:"] {
+            assert!(!tag.contains(scheme), "{vector} gave {html}");
+        }
+    }
+}
+
+/// Allows as much as it can, including what it never should.
+fn permissive() -> SanitizeConfig {
+    let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+    SanitizeConfig {
+        tags: names(&["iframe", "figure", "script", "style", "video", "source"]),
+        generic_attributes: names(&["class", "onclick", "rel"]),
+        tag_attributes: HashMap::from([
+            (
+                "iframe".to_string(),
+                names(&["src", "allowfullscreen", "onload"]),
+            ),
+            ("a".to_string(), names(&["rel", "onmouseover"])),
+            ("source".to_string(), names(&["src", "onerror"])),
+        ]),
+        url_schemes: names(&["javascript", "vbscript", "ipfs"]),
+    }
+}
+
+#[test]
+fn strips_vectors_by_default() {
+    let policy = SanitizeConfig::default();
+    for vector in VECTORS {
+        assert_safe(&sanitize(vector, &policy), vector);
+        let markdown = render(vector, ContentFormat::Markdown, &policy);
+        assert_safe(&markdown.html, vector);
+        assert!(!markdown.html.contains("<iframe"), "{}", markdown.html);
+    }
+}
+
+#[test]
+fn policy_cannot_allow_script() {
+    let policy = permissive();
+    for vector in VECTORS {
+        assert_safe(&sanitize(vector, &policy), vector);
+        assert_safe(
+            &render(vector, ContentFormat::Markdown, &policy).html,
+            vector,
+        );
+    }
+}
+
+#[test]
+fn policy_allows_embeds() {
+    let html = "<figure class=\"wp-block-embed\"><iframe src=\"https://www.youtube.com/embed/abc\" allowfullscreen></iframe><figcaption class=\"caption\">Talk</figcaption></figure><a href=\"ipfs://bafy\">x</a>";
+
+    let cleaned = sanitize(html, &SanitizeConfig::default());
+    assert!(!cleaned.contains("<iframe"), "{cleaned}");
+    assert!(!cleaned.contains("ipfs:"), "{cleaned}");
+
+    let cleaned = sanitize(html, &permissive());
+    assert!(
+        cleaned.contains(
+            "<iframe src=\"https://www.youtube.com/embed/abc\" allowfullscreen=\"\"></iframe>"
+        ),
+        "{cleaned}"
+    );
+    assert!(
+        cleaned.contains("<figure class=\"wp-block-embed\">"),
+        "{cleaned}"
+    );
+    assert!(cleaned.contains("href=\"ipfs:"), "{cleaned}");
+
+    let cleaned = sanitize(html, &SanitizeConfig::wordpress());
+    assert!(cleaned.contains("<iframe"), "{cleaned}");
+    assert!(
+        cleaned.contains("<figcaption class=\"caption\">"),
+        "{cleaned}"
+    );
+}
+
+#[tokio::test]
+async fn sanitizes_posts_when_written_and_read() {
+    let mut config = site().await;
+    config.sanitize = SanitizeConfig {
+        tags: vec!["iframe".to_string()],
+        tag_attributes: HashMap::from([("iframe".to_string(), vec!["src".to_string()])]),
+        ..Default::default()
+    };
+    let app = setup_routes(&config);
+    add_user(&config, "alice", Rank::Administrator).await;
+    let cookie = sign_in(&app, "alice").await;
+    let content = "<p onclick=\"alert(1)\">Hi</p><script>alert(1)</script><iframe src=\"https://video.example/1\"></iframe>";
+    for (name, format) in [("Html", "Html"), ("Markdown", "Markdown")] {
+        let response = send(
+            &app,
+            "POST",
+            "/api/post",
+            Some(&cookie),
+            Some(&[
+                ("name", name),
+                ("content", content),
+                ("status", "Published"),
+                ("format", format),
+            ]),
+        )
+        .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    }
+
+    let pool = config.db_pool.clone().unwrap();
+    let stored: Vec<(String, String)> =
+        sqlx::query_as("SELECT content, rendered FROM posts ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    // Both are stored as written, only their rendered html is sanitized
+    assert_eq!(stored[0].0, content);
+    assert_eq!(stored[1].0, content);
+    assert_eq!(
+        stored[0].1,
+        "<p>Hi</p><iframe src=\"https://video.example/1\"></iframe>"
+    );
+    for (_, rendered) in stored.iter() {
+        assert_safe(rendered, content);
+        assert!(rendered.contains("<iframe src=\"https://video.example/1\">"));
+    }
+
+    // Html rendered before the policy was tightened is sanitized when read
+    sqlx::query("UPDATE posts SET rendered = ?1 WHERE id = 1")
+        .bind(content)
+        .execute(&pool)
+        .await
+        .unwrap();
+    config.sanitize = SanitizeConfig::default();
+    let app = setup_routes(&config);
+    let post: Post = send(&app, "GET", "/api/post?id=1", None, None).await.json();
+    assert_eq!(post.content, "<p>Hi</p>");
+    assert_eq!(post.rendered, "<p>Hi</p>");
+    // Only those who can edit the post get its content as it was written
+    let post: Post = send(&app, "GET", "/api/post?id=1", Some(&cookie), None)
+        .await
+        .json();
+    assert_eq!(post.content, content);
+    assert_eq!(post.rendered, "<p>Hi</p>");
+
+    // And the posts are rendered again with the new policy when the site is opened
+    render_stale_posts(&pool, &config.sanitize).await.unwrap();
+    let rendered: Vec<String> = sqlx::query_scalar("SELECT rendered FROM posts ORDER BY id")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(rendered[0], "<p>Hi</p>");
+    assert!(!rendered[1].contains("<iframe"), "{}", rendered[1]);
+    let fingerprints: Vec<String> = sqlx::query_scalar("SELECT rendered_with FROM posts")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert!(fingerprints
+        .iter()
+        .all(|fingerprint| *fingerprint == policy_fingerprint(&config.sanitize)));
+}
@@ -0,0 +1,215 @@
+mod common;
+
+use axum::{http::StatusCode, Router};
+use common::{add_user, send, sign_in, site};
+use peroxide::{
+    auth::user::Rank,
+    config::{PagePath, SiteConfig},
+    site::setup_routes,
+};
+use serde_json::Value;
+
+/// A blog by jane with a few posts, of which one is a draft.
+async fn blog() -> (SiteConfig, Router, String) {
+    let mut config = site().await;
+    config.domain = "example.com".to_string();
+    config.routes.insert(
+        "/blog".to_string(),
+        PagePath {
+            path: "blog.html".to_string(),
+            template: Some("post.html".to_string()),
+            permalink: None,
+        },
+    );
+    let template = std::fs::read_to_string("data/search_results.html").unwrap();
+    config
+        .templates
+        .write()
+        .unwrap()
+        .add_template("data/search_results".to_string(), template)
+        .unwrap();
+    let app = setup_routes(&config);
+    add_user(&config, "jane", Rank::Author).await;
+    let cookie = sign_in(&app, "jane").await;
+    let posts = [
+        (
+            "Baking bread",
+            "<p>Flour, water and salt make a loaf.</p>",
+            "Published",
+        ),
+        (
+            "Sourdough",
+            "<p>A starter of flour and water makes bread rise.</p>",
+            "Published",
+        ),
+        (
+            "Gardening",
+            "<p>Tomatoes need sun and water.</p>",
+            "Published",
+        ),
+        ("Secret bread", "<p>Not ready yet.</p>", "Draft"),
+    ];
+    for (name, content, status) in posts {
+        let response = send(
+            &app,
+            "POST",
+            "/api/post",
+            Some(&cookie),
+            Some(&[("name", name), ("content", content), ("status", status)]),
+        )
+        .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    }
+    (config, app, cookie)
+}
+
+/// Names of the posts found by searching with `query`, along with the whole response.
+async fn search(app: &Router, cookie: Option<&str>, query: &str) -> (Vec<String>, Value) {
+    let response = send(app, "GET", &format!("/api/search?{query}"), cookie, None).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let results: Value = response.json();
+    let names = results["posts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|post| post["name"].as_str().unwrap().to_string())
+        .collect();
+    (names, results)
+}
+
+#[tokio::test]
+async fn ranks_and_highlights_matches() {
+    let (_, app, _) = blog().await;
+
+    // Names count for more than the content
+    let (names, results) = search(&app, None, "q=bread").await;
+    assert_eq!(names, ["Baking bread", "Sourdough"]);
+    assert_eq!(results["total"], 2);
+    assert_eq!(
+        results["posts"][1]["snippet"],
+        "A starter of flour and water makes <mark>bread</mark> rise."
+    );
+    assert_eq!(
+        results["posts"][0]["url"],
+        "https://example.com/blog/baking-bread"
+    );
+
+    // Every word has to be there, the last one may be unfinished
+    let (names, _) = search(&app, None, "q=water+tom").await;
+    assert_eq!(names, ["Gardening"]);
+    // Words are stemmed
+    let (names, _) = search(&app, None, "q=salted").await;
+    assert_eq!(names, ["Baking bread"]);
+}
+
+#[tokio::test]
+async fn pages_through_results() {
+    let (_, app, _) = blog().await;
+
+    let (names, results) = search(&app, None, "q=water&per_page=2").await;
+    assert_eq!(names.len(), 2);
+    assert_eq!(results["total"], 3);
+    let next = results["next"].as_str().unwrap();
+    let (rest, results) = search(&app, None, next).await;
+    assert_eq!(rest.len(), 1);
+    assert!(!names.contains(&rest[0]));
+    assert_eq!(results["next"], Value::Null);
+
+    // Without a query everything is listed, newest first
+    let (names, _) = search(&app, None, "").await;
+    assert_eq!(names, ["Gardening", "Sourdough", "Baking bread"]);
+}
+
+#[tokio::test]
+async fn drafts_are_only_found_by_their_owner() {
+    let (_, app, cookie) = blog().await;
+
+    let (names, _) = search(&app, None, "q=secret").await;
+    assert!(names.is_empty());
+    let (names, _) = search(&app, Some(&cookie), "q=secret").await;
+    assert_eq!(names, ["Secret bread"]);
+}
+
+#[tokio::test]
+async fn follows_edits_and_deletions() {
+    let (_, app, cookie) = blog().await;
+
+    let response = send(
+        &app,
+        "PATCH",
+        "/api/post?id=3",
+        Some(&cookie),
+        Some(&[("name", "Potatoes"), ("content", "<p>Dig them up.</p>")]),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let (names, _) = search(&app, None, "q=tomatoes").await;
+    assert!(names.is_empty());
+    let (names, _) = search(&app, None, "q=potatoes").await;
+    assert_eq!(names, ["Potatoes"]);
+
+    let response = send(&app, "DELETE", "/api/post?id=1", Some(&cookie), None).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let (names, _) = search(&app, None, "q=bread").await;
+    assert_eq!(names, ["Sourdough"]);
+}
+
+#[tokio::test]
+async fn queries_are_not_fts_syntax() {
+    let (_, app, _) = blog().await;
+
+    for query in [
+        "q=%22",
+        "q=bread%22+OR",
+        "q=NEAR(bread",
+        "q=*",
+        "q=-water",
+        "q=name:bread",
+        "q=%5E",
+    ] {
+        let response = send(&app, "GET", &format!("/api/search?{query}"), None, None).await;
+        assert_eq!(
+            response.status,
+            StatusCode::OK,
+            "{query}: {}",
+            response.body
+        );
+    }
+    // Quotes don't make phrases, they are left out like other punctuation
+    let (names, _) = search(&app, None, "q=%22bread%22").await;
+    assert_eq!(names, ["Baking bread", "Sourdough"]);
+}
+
+#[tokio::test]
+async fn renders_results_for_public_sites() {
+    let (_, app, _) = blog().await;
+
+    let response = send(&app, "GET", "/api/search?q=bread&format=html", None, None).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert!(response
+        .body
+        .contains("<a href=\"https://example.com/blog/sourdough\">Sourdough</a>"));
+    assert!(response.body.contains("makes <mark>bread</mark> rise."));
+    assert!(!response.body.contains("More results"));
+}
+
+#[tokio::test]
+async fn only_renders_with_list_templates() {
+    let (config, app, _) = blog().await;
+    config
+        .templates
+        .write()
+        .unwrap()
+        .add_template("admin_panel/secret".to_string(), "secret".to_string())
+        .unwrap();
+
+    let uri = "/api/search?q=bread&format=html&template=admin_panel/secret";
+    let response = send(&app, "GET", uri, None, None).await;
+    assert_eq!(
+        response.status,
+        StatusCode::BAD_REQUEST,
+        "{}",
+        response.body
+    );
+    assert!(!response.body.contains("secret"));
+}
@@ -0,0 +1,132 @@
+mod common;
+
+use axum::{http::StatusCode, Router};
+use common::{add_user, app, send, sign_in};
+use peroxide::auth::{
+    admin::{delete_user, require_password_reset},
+    password::set_password,
+    session::SessionInfo,
+    user::Rank,
+};
+
+async fn sessions(app: &Router, cookie: &str) -> Vec<SessionInfo> {
+    let response = send(app, "GET", "/api/user/sessions", Some(cookie), None).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    response.json()
+}
+
+async fn status(app: &Router, cookie: &str) -> StatusCode {
+    send(app, "GET", "/api/user/sessions", Some(cookie), None)
+        .await
+        .status
+}
+
+#[tokio::test]
+async fn lists_and_ends_sessions() {
+    let (config, app) = app().await;
+    add_user(&config, "jane", Rank::Author).await;
+    let laptop = sign_in(&app, "jane").await;
+    let phone = sign_in(&app, "jane").await;
+
+    let listed = sessions(&app, &laptop).await;
+    assert_eq!(listed.len(), 2);
+    assert_eq!(listed.iter().filter(|session| session.current).count(), 1);
+    let laptop_id = listed.iter().find(|s| s.current).unwrap().id.clone();
+
+    let response = send(&app, "POST", "/api/user/logout", Some(&laptop), None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.cookies.iter().any(|c| c == "jwt-token="));
+    // The token still has a valid signature, but its session is gone
+    assert_eq!(status(&app, &laptop).await, StatusCode::UNAUTHORIZED);
+    let listed = sessions(&app, &phone).await;
+    assert_eq!(listed.len(), 1);
+    assert_ne!(listed[0].id, laptop_id);
+}
+
+#[tokio::test]
+async fn revokes_single_sessions_of_the_same_user() {
+    let (config, app) = app().await;
+    add_user(&config, "jane", Rank::Author).await;
+    add_user(&config, "john", Rank::Author).await;
+    let lost = sign_in(&app, "jane").await;
+    let kept = sign_in(&app, "jane").await;
+    let john = sign_in(&app, "john").await;
+    let lost_id = sessions(&app, &lost)
+        .await
+        .into_iter()
+        .find(|s| s.current)
+        .unwrap()
+        .id;
+
+    // Nobody else's sessions can be ended
+    let uri = format!("/api/user/session?id={lost_id}");
+    let response = send(&app, "DELETE", &uri, Some(&john), None).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert_eq!(status(&app, &lost).await, StatusCode::OK);
+
+    let response = send(&app, "DELETE", &uri, Some(&kept), None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(status(&app, &lost).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(status(&app, &kept).await, StatusCode::OK);
+    assert_eq!(status(&app, &john).await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn logs_out_everywhere() {
+    let (config, app) = app().await;
+    add_user(&config, "jane", Rank::Author).await;
+    add_user(&config, "john", Rank::Author).await;
+    let laptop = sign_in(&app, "jane").await;
+    let phone = sign_in(&app, "jane").await;
+    let john = sign_in(&app, "john").await;
+
+    let response = send(&app, "POST", "/api/user/logout/all", Some(&phone), None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(status(&app, &laptop).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(status(&app, &phone).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(status(&app, &john).await, StatusCode::OK);
+    // Signing in again works
+    let again = sign_in(&app, "jane").await;
+    assert_eq!(sessions(&app, &again).await.len(), 1);
+}
+
+#[tokio::test]
+async fn password_changes_end_sessions() {
+    let (config, app) = app().await;
+    let pool = config.db_pool.as_ref().unwrap();
+    add_user(&config, "jane", Rank::Author).await;
+    add_user(&config, "john", Rank::Author).await;
+
+    let jane = sign_in(&app, "jane").await;
+    assert!(set_password(pool, "jane", common::PASSWORD).await.unwrap());
+    assert_eq!(status(&app, &jane).await, StatusCode::UNAUTHORIZED);
+
+    let john = sign_in(&app, "john").await;
+    assert!(require_password_reset(pool, "john").await.unwrap());
+    assert_eq!(status(&app, &john).await, StatusCode::UNAUTHORIZED);
+
+    let jane = sign_in(&app, "jane").await;
+    delete_user(pool, "jane", None).await.unwrap();
+    assert_eq!(status(&app, &jane).await, StatusCode::UNAUTHORIZED);
+    let left: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sessions")
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    assert_eq!(left, 0);
+}
+
+#[tokio::test]
+async fn rejects_missing_and_forged_tokens() {
+    let (config, app) = app().await;
+    add_user(&config, "jane", Rank::Author).await;
+    sign_in(&app, "jane").await;
+
+    let response = send(&app, "GET", "/api/user/sessions", None, None).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        status(&app, "jwt-token=not.a.token").await,
+        StatusCode::UNAUTHORIZED
+    );
+    let response = send(&app, "POST", "/api/user/logout", None, None).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,193 @@
+mod common;
+
+use axum::{http::StatusCode, Router};
+use common::{add_user, send, sign_in, site};
+use peroxide::{
+    auth::user::Rank,
+    config::{PagePath, RobotsConfig, SiteConfig},
+    site::setup_routes,
+    sitemap::SITEMAP_URLS,
+};
+
+/// A site with a few pages and a blog, crawlers are kept away from the navbar.
+async fn blog() -> (SiteConfig, Router) {
+    let mut config = site().await;
+    config.domain = "example.com".to_string();
+    config.robots = RobotsConfig {
+        allow: vec!["/api/posts".to_string()],
+        disallow: vec!["/navbar".to_string()],
+    };
+    for (route, template) in [
+        ("/", None),
+        ("/about", None),
+        ("/navbar", None),
+        ("/blog", Some("post.html")),
+    ] {
+        config.routes.insert(
+            route.to_string(),
+            PagePath {
+                path: format!("{}.html", route.trim_start_matches('/')),
+                template: template.map(str::to_string),
+                permalink: None,
+            },
+        );
+    }
+    let app = setup_routes(&config);
+    add_user(&config, "jane", Rank::Author).await;
+    (config, app)
+}
+
+/// The `loc` and `lastmod` of every url in the sitemap at `uri`.
+async fn urls(app: &Router, uri: &str) -> Vec<(String, Option<String>)> {
+    let response = send(app, "GET", uri, None, None).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let document = roxmltree::Document::parse(&response.body).unwrap();
+    let child = |node: roxmltree::Node, name: &str| {
+        node.children()
+            .find(|child| child.tag_name().name() == name)
+            .and_then(|child| child.text())
+            .map(str::to_string)
+    };
+    document
+        .root_element()
+        .children()
+        .filter(|node| node.is_element())
+        .map(|node| (child(node, "loc").unwrap(), child(node, "lastmod")))
+        .collect()
+}
+
+#[tokio::test]
+async fn lists_routes_and_posts() {
+    let (config, app) = blog().await;
+    let cookie = sign_in(&app, "jane").await;
+    for (name, status) in [("Hello", "Published"), ("Draft", "Draft")] {
+        let response = send(
+            &app,
+            "POST",
+            "/api/post",
+            Some(&cookie),
+            Some(&[("name", name), ("content", "x"), ("status", status)]),
+        )
+        .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    }
+    let pool = config.db_pool.as_ref().unwrap();
+    sqlx::query("UPDATE posts SET date = 1709812800")
+        .execute(pool)
+        .await
+        .unwrap();
+
+    let urls = urls(&app, "/sitemap.xml").await;
+    assert_eq!(
+        urls,
+        [
+            ("https://example.com/".to_string(), None),
+            ("https://example.com/about".to_string(), None),
+            ("https://example.com/blog".to_string(), None),
+            (
+                "https://example.com/blog/hello".to_string(),
+                Some("2024-03-07T12:00:00+00:00".to_string())
+            ),
+        ]
+    );
+
+    // Edits count as changes
+    let response = send(
+        &app,
+        "PATCH",
+        "/api/post?id=1",
+        Some(&cookie),
+        Some(&[("content", "y")]),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    sqlx::query("UPDATE post_revisions SET date = 1709900000")
+        .execute(pool)
+        .await
+        .unwrap();
+    let lastmod = self::urls(&app, "/sitemap.xml").await[3].1.clone();
+    assert_eq!(lastmod.as_deref(), Some("2024-03-08T12:13:20+00:00"));
+}
+
+#[tokio::test]
+async fn leaves_out_disallowed_posts() {
+    let (mut config, _) = blog().await;
+    config.robots.disallow.push("/blog/secret".to_string());
+    let app = setup_routes(&config);
+    let cookie = sign_in(&app, "jane").await;
+    for name in ["Secret plans", "Hello"] {
+        let response = send(
+            &app,
+            "POST",
+            "/api/post",
+            Some(&cookie),
+            Some(&[("name", name), ("content", "x"), ("status", "Published")]),
+        )
+        .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    }
+
+    let urls: Vec<String> = urls(&app, "/sitemap.xml")
+        .await
+        .into_iter()
+        .map(|(loc, _)| loc)
+        .collect();
+    assert_eq!(
+        urls,
+        [
+            "https://example.com/",
+            "https://example.com/about",
+            "https://example.com/blog",
+            "https://example.com/blog/hello",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn splits_big_sites_behind_an_index() {
+    let (config, app) = blog().await;
+    sqlx::query(
+        "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < ?)
+        INSERT INTO posts(name, slug, content, owner, status) SELECT 'Post', 'post-' || i, '', 'jane', 'Published' FROM n",
+    )
+    .bind(SITEMAP_URLS)
+    .execute(config.db_pool.as_ref().unwrap())
+    .await
+    .unwrap();
+
+    // Three routes and as many posts as fit in a sitemap
+    let index = urls(&app, "/sitemap.xml").await;
+    assert_eq!(
+        index,
+        [
+            ("https://example.com/sitemap.xml?page=1".to_string(), None),
+            ("https://example.com/sitemap.xml?page=2".to_string(), None),
+        ]
+    );
+    let first = urls(&app, "/sitemap.xml?page=1").await;
+    assert_eq!(first.len(), SITEMAP_URLS as usize);
+    assert_eq!(first[3].0, "https://example.com/blog/post-1");
+    let second = urls(&app, "/sitemap.xml?page=2").await;
+    let second: Vec<String> = second.into_iter().map(|(loc, _)| loc).collect();
+    assert_eq!(
+        second,
+        [
+            "https://example.com/blog/post-49998",
+            "https://example.com/blog/post-49999",
+            "https://example.com/blog/post-50000",
+        ]
+    );
+    let response = send(&app, "GET", "/sitemap.xml?page=3", None, None).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn robots_points_at_the_sitemap() {
+    let (_, app) = blog().await;
+    let response = send(&app, "GET", "/robots.txt", None, None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        response.body,
+        "User-agent: *\nAllow: /api/posts\nDisallow: /navbar\n\nSitemap: https://example.com/sitemap.xml\n"
+    );
+}
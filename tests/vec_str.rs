@@ -0,0 +1,102 @@
+use peroxide::post::VecStr;
+use proptest::prelude::*;
+use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
+
+/// The blob format older versions stored, kept here to check it can still be read.
+fn length_prefixed(data: &[String]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for string in data {
+        bytes.extend((string.len() as u64).to_le_bytes());
+        bytes.extend(string.as_bytes());
+    }
+    bytes
+}
+
+fn pool() -> (tokio::runtime::Runtime, SqlitePool) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let pool = runtime
+        .block_on(
+            SqlitePoolOptions::new()
+                .max_connections(1)
+                .connect("sqlite::memory:"),
+        )
+        .unwrap();
+    (runtime, pool)
+}
+
+proptest! {
+    #[test]
+    fn json_round_trip(data: Vec<String>) {
+        let vec = VecStr { data };
+        prop_assert_eq!(VecStr::from_json(&vec.to_json()).unwrap(), vec.clone());
+        prop_assert_eq!(VecStr::from_field(&vec.to_json()).unwrap(), vec.clone());
+        let serialized = serde_json::to_string(&vec).unwrap();
+        prop_assert_eq!(&serialized, &vec.to_json());
+        prop_assert_eq!(serde_json::from_str::<VecStr>(&serialized).unwrap(), vec);
+    }
+
+    #[test]
+    fn comma_separated_round_trip(data in prop::collection::vec("[^,\\[\\s][^,]*[^,\\s]", 0..8)) {
+        let field = data.join(", ");
+        prop_assert_eq!(VecStr::from_field(&field).unwrap(), VecStr { data });
+    }
+
+    #[test]
+    fn legacy_blob_round_trip(data: Vec<String>) {
+        let bytes = length_prefixed(&data);
+        prop_assert_eq!(VecStr::from_bytes(&bytes).unwrap(), VecStr { data });
+    }
+
+    #[test]
+    fn arbitrary_bytes_do_not_panic(bytes: Vec<u8>) {
+        let _ = VecStr::from_bytes(&bytes);
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn database_round_trip(data: Vec<String>) {
+        let (runtime, pool) = pool();
+        let vec = VecStr { data };
+        let stored: VecStr = runtime
+            .block_on(sqlx::query_scalar("SELECT ?").bind(&vec).fetch_one(&pool))
+            .unwrap();
+        prop_assert_eq!(stored, vec);
+    }
+}
+
+#[tokio::test]
+async fn reads_legacy_blobs_from_the_database() {
+    let pool = SqlitePoolOptions::new()
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    let data = vec!["rust".to_string(), "web".to_string()];
+    let stored: VecStr = sqlx::query_scalar("SELECT ?")
+        .bind(length_prefixed(&data))
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(stored, VecStr { data });
+}
+
+#[tokio::test]
+async fn invalid_values_are_errors() {
+    assert!(VecStr::from_bytes(&[3, 0, 0, 0, 0, 0, 0, 0, b'a']).is_err());
+    assert!(VecStr::from_bytes(&[1, 0, 0, 0, 0, 0, 0, 0, 0xff]).is_err());
+    assert!(VecStr::from_field("[\"unterminated").is_err());
+
+    let pool = SqlitePoolOptions::new()
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    let stored: Result<VecStr, _> = sqlx::query_scalar("SELECT x'01000000000000'")
+        .fetch_one(&pool)
+        .await;
+    assert!(stored.is_err());
+}
@@ -0,0 +1,33 @@
+use peroxide::post::VecStr;
+use sqlx::sqlite::SqlitePoolOptions;
+
+/// Posts without tags have to be storable in the tags column, which doesn't allow NULL.
+#[tokio::test]
+async fn stores_empty_values() {
+    let pool = SqlitePoolOptions::new()
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    sqlx::query("CREATE TABLE posts(id INTEGER NOT NULL PRIMARY KEY, tags BLOB NOT NULL)")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let values = [
+        (1, Vec::new()),
+        (2, vec!["rust".to_string(), String::new()]),
+    ];
+    for (id, data) in values {
+        sqlx::query("INSERT INTO posts(id, tags) VALUES(?, ?)")
+            .bind(id)
+            .bind(VecStr { data: data.clone() })
+            .execute(&pool)
+            .await
+            .unwrap();
+        let stored: VecStr = sqlx::query_scalar("SELECT tags FROM posts WHERE id = ?")
+            .bind(id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(stored.data, data);
+    }
+}
@@ -0,0 +1,361 @@
+use std::net::SocketAddr;
+
+use peroxide::{
+    site::open_site,
+    wordpress::{WordpressImportError, WordpressSite},
+};
+use wiremock::{
+    matchers::{method, path, query_param},
+    Mock, MockServer, ResponseTemplate,
+};
+
+fn fixture(name: &str) -> String {
+    std::fs::read_to_string(format!(
+        "{}/tests/fixtures/wordpress/{name}",
+        env!("CARGO_MANIFEST_DIR")
+    ))
+    .unwrap()
+}
+
+fn json(name: &str) -> ResponseTemplate {
+    ResponseTemplate::new(200)
+        .insert_header("Content-Type", "application/json")
+        .insert_header("X-WP-TotalPages", "1")
+        .set_body_string(fixture(name))
+}
+
+async fn mount(server: &MockServer, endpoint: &str, response: ResponseTemplate) {
+    Mock::given(method("GET"))
+        .and(path(format!("/wp-json/wp/v2/{endpoint}")))
+        .respond_with(response)
+        .mount(server)
+        .await;
+}
+
+/// Serves a recorded site, with the posts split over two pages.
+async fn recorded_site() -> MockServer {
+    let server = MockServer::start().await;
+    for page in ["1", "2"] {
+        Mock::given(method("GET"))
+            .and(path("/wp-json/wp/v2/posts"))
+            .and(query_param("per_page", "100"))
+            .and(query_param("page", page))
+            .respond_with(
+                json(&format!("posts_page_{page}.json")).insert_header("X-WP-TotalPages", "2"),
+            )
+            .mount(&server)
+            .await;
+    }
+    for endpoint in ["pages", "tags", "categories", "media", "users", "comments"] {
+        mount(&server, endpoint, json(&format!("{endpoint}.json"))).await;
+    }
+    server
+}
+
+#[tokio::test]
+async fn fetches_every_page() {
+    let server = recorded_site().await;
+    let site = WordpressSite::from_site_url(server.uri()).await.unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = site
+        .save(dir.path().to_str().unwrap().to_string())
+        .await
+        .unwrap();
+    let pool = config.db_pool.unwrap();
+    let posts: Vec<(i64, String, String, String)> =
+        sqlx::query_as("SELECT id, name, status, owner FROM posts ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        posts,
+        vec![
+            (3, "Hello world!".into(), "Published".into(), "jane".into()),
+            (5, "Rust in production".into(), "Published".into(), "jane".into()),
+            (
+                8,
+                "Upcoming release \u{2013} v2 & more".into(),
+                "Draft".into(),
+                "jane".into()
+            ),
+        ]
+    );
+    // Titles are stored as text, drafts without a slug get theirs from it
+    let slugs: Vec<String> = sqlx::query_scalar("SELECT slug FROM posts ORDER BY id")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(slugs, ["hello-world", "rust-in-production", "upcoming-release-v2-more"]);
+    let users: Vec<(String, i64)> =
+        sqlx::query_as("SELECT username, reset_password FROM users ORDER BY username")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(users, vec![("jane".into(), 1), ("wordpress".into(), 1)]);
+    let comments: Vec<(i64, i64, Option<i64>, String)> =
+        sqlx::query_as("SELECT id, post, parent, status FROM comments ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(comments, vec![(1, 3, None, "Approved".into())]);
+    let tags: Vec<(i64, String)> = sqlx::query_as(
+        "SELECT post, name FROM post_tags JOIN tags ON tags.id = tag ORDER BY post, name",
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        tags,
+        vec![
+            (3, "Rust".into()),
+            (5, "Operations".into()),
+            (5, "Rust".into()),
+        ]
+    );
+    let categories: Vec<(i64, String, Option<i64>)> =
+        sqlx::query_as("SELECT id, slug, parent FROM categories ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        categories,
+        vec![
+            (1, "uncategorized".into(), None),
+            (9, "notes".into(), Some(1))
+        ]
+    );
+}
+
+#[tokio::test]
+async fn retries_transient_failures() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/wp-json/wp/v2/tags"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    mount(&server, "posts", json("posts_page_1.json")).await;
+    for endpoint in ["pages", "tags", "categories", "media", "users", "comments"] {
+        mount(&server, endpoint, json(&format!("{endpoint}.json"))).await;
+    }
+
+    assert!(WordpressSite::from_site_url(server.uri()).await.is_ok());
+}
+
+#[tokio::test]
+async fn reports_failing_endpoint() {
+    let server = MockServer::start().await;
+    mount(&server, "posts", json("posts_page_1.json")).await;
+    mount(&server, "pages", ResponseTemplate::new(404)).await;
+
+    match WordpressSite::from_site_url(server.uri()).await {
+        Err(WordpressImportError::Status {
+            endpoint,
+            page,
+            status,
+        }) => {
+            assert_eq!(endpoint, "pages");
+            assert_eq!(page, 1);
+            assert_eq!(status.as_u16(), 404);
+        }
+        other => panic!("Expected a status error, got {:?}", other.err()),
+    }
+}
+
+#[tokio::test]
+async fn reports_invalid_item() {
+    let server = MockServer::start().await;
+    mount(&server, "posts", json("posts_invalid_item.json")).await;
+
+    match WordpressSite::from_site_url(server.uri()).await {
+        Err(WordpressImportError::Item { endpoint, id, .. }) => {
+            assert_eq!(endpoint, "posts");
+            assert_eq!(id, Some(7));
+        }
+        other => panic!("Expected an item error, got {:?}", other.err()),
+    }
+}
+
+#[tokio::test]
+async fn imports_export_file() {
+    let site = WordpressSite::from_wxr(&fixture("export.xml")).unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = site
+        .save(dir.path().to_str().unwrap().to_string())
+        .await
+        .unwrap();
+    assert!(config.routes.contains_key("/sample-page"));
+    // Draft pages aren't published as templates
+    assert!(!config.routes.contains_key("/coming-soon"));
+    assert!(!dir.path().join("templates/coming-soon.html").exists());
+    let pool = config.db_pool.unwrap();
+    let posts: Vec<(i64, String, String, String)> =
+        sqlx::query_as("SELECT id, name, status, owner FROM posts ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        posts,
+        vec![
+            (3, "Hello world!".into(), "Published".into(), "jane".into()),
+            (8, "Upcoming release".into(), "Draft".into(), "jane".into()),
+        ]
+    );
+    // Drafts are exported without a slug, theirs is made from the title
+    let slugs: Vec<String> = sqlx::query_scalar("SELECT slug FROM posts ORDER BY id")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(slugs, ["hello-world", "upcoming-release"]);
+    let comments: Vec<(i64, Option<i64>, Option<String>, String)> =
+        sqlx::query_as("SELECT id, parent, author, status FROM comments ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        comments,
+        vec![
+            (1, None, None, "Approved".into()),
+            (2, Some(1), Some("jane".into()), "Pending".into()),
+        ]
+    );
+    let terms: Vec<(i64, String)> = sqlx::query_as(
+        "SELECT post, slug FROM post_tags JOIN tags ON tags.id = tag
+        UNION ALL SELECT post, slug FROM post_categories JOIN categories ON categories.id = category
+        ORDER BY post, slug",
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        terms,
+        vec![
+            (3, "rust".into()),
+            (3, "uncategorized".into()),
+            (3, "wasm".into()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn imported_sites_can_be_served() {
+    let site = WordpressSite::from_wxr(&fixture("export.xml")).unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+    site.save(path.to_string()).await.unwrap();
+    let config = open_site(path).await.unwrap();
+    let address: SocketAddr = config.domain.parse().unwrap();
+    // Any port of the saved address will do, the default one may be taken
+    tokio::net::TcpListener::bind(SocketAddr::new(address.ip(), 0))
+        .await
+        .unwrap();
+    // The WordPress host still names the imported accounts
+    let email: String = sqlx::query_scalar("SELECT email FROM users WHERE username = 'jane'")
+        .fetch_one(config.db_pool.as_ref().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(email, "jane@blog.example.com");
+}
+
+#[tokio::test]
+async fn imports_pages_as_templates() {
+    let site = WordpressSite::from_wxr(&fixture("export.xml")).unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = site
+        .save(dir.path().to_str().unwrap().to_string())
+        .await
+        .unwrap();
+    let mut routes: Vec<&String> = config.routes.keys().collect();
+    routes.sort();
+    assert_eq!(routes, ["/about", "/sample-page"]);
+    // Slugs that would leave the templates directory aren't imported
+    assert!(!dir.path().parent().unwrap().join("outside.html").exists());
+
+    let about = std::fs::read_to_string(dir.path().join("templates/about.html")).unwrap();
+    // Titles are text in both the title and the heading, and braces stay out of the template
+    assert_eq!(about.matches("About us &amp; \\{\\{ this }}").count(), 2, "{about}");
+    assert!(!about.contains("alert(1)"), "{about}");
+    // Pages are sanitized like posts, they are served as they were imported
+    assert!(about.contains("<p>Who we are.</p><img src=\"x\">"), "{about}");
+    assert!(!about.contains("<script>") && !about.contains("onerror"), "{about}");
+}
+
+#[tokio::test]
+async fn rehosts_media() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/wp-content/uploads/2024/01/cat.jpg"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"meow".to_vec()))
+        .mount(&server)
+        .await;
+    let export = fixture("media.xml").replace("{server}", &server.uri());
+    let site = WordpressSite::from_wxr(&export).unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = site
+        .save(dir.path().to_str().unwrap().to_string())
+        .await
+        .unwrap();
+    let saved = std::fs::read(dir.path().join("static/uploads/2024/01/cat.jpg")).unwrap();
+    assert_eq!(saved, b"meow");
+    let content: String = sqlx::query_scalar("SELECT content FROM posts WHERE id = 21")
+        .fetch_one(&config.db_pool.unwrap())
+        .await
+        .unwrap();
+    let uploads = format!("{}/wp-content/uploads/2024/01", server.uri());
+    // Resized copies are served from the original
+    assert!(
+        content.contains(
+            "<img src=\"/static/uploads/2024/01/cat.jpg\" srcset=\"/static/uploads/2024/01/cat.jpg 300w, /static/uploads/2024/01/cat.jpg 1024w\" alt=\"a > b\">"
+        ),
+        "{content}"
+    );
+    assert!(
+        content.contains("<a href='/static/uploads/2024/01/cat.jpg'>Full size</a>"),
+        "{content}"
+    );
+    // Urls that only start like the picture, and urls outside of attributes, are left alone
+    assert!(
+        content.contains(&format!("<a href=\"{uploads}/cat.jpg.html\">")),
+        "{content}"
+    );
+    assert!(
+        content.contains(&format!("<a href={uploads}/cat-large.jpg>")),
+        "{content}"
+    );
+    assert!(
+        content.contains(&format!("Pictures live at {uploads}/cat.jpg</p>")),
+        "{content}"
+    );
+}
+
+#[tokio::test]
+async fn sanitizes_imported_posts() {
+    let site = WordpressSite::from_wxr(&fixture("export.xml")).unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = site
+        .save(dir.path().to_str().unwrap().to_string())
+        .await
+        .unwrap();
+    let (content, rendered): (String, String) =
+        sqlx::query_as("SELECT content, rendered FROM posts WHERE id = 8")
+            .fetch_one(&config.db_pool.unwrap())
+            .await
+            .unwrap();
+    // Embeds are kept, the script and event handlers are not
+    assert_eq!(
+        rendered,
+        "<p>Not quite ready.</p><figure class=\"wp-block-embed\"><iframe src=\"https://www.youtube.com/embed/abc\"></iframe></figure>"
+    );
+    // The content stays as it was exported
+    assert!(content.contains("<script>alert(1)</script>"), "{content}");
+    let saved = std::fs::read_to_string(dir.path().join("PeroxideSite.toml")).unwrap();
+    assert!(saved.contains("[sanitize]"), "{saved}");
+}